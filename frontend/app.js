@@ -10,6 +10,7 @@ const i18n = {
         login: 'Login',
         logout: 'Logout',
         settings: 'Settings',
+        import: 'Import',
         // Empty state
         emptyState: 'No servers configured. Add a server to start monitoring.',
         // Card
@@ -23,6 +24,12 @@ const i18n = {
         edit: 'Edit',
         del: 'Del',
         protocol: 'Protocol',
+        // Groups
+        groupUp: 'All systems operational',
+        groupPartial: 'Partial outage',
+        groupDown: 'Major outage',
+        groupUnknown: 'No data',
+        serversUp: 'up',
         // Time
         justNow: 'just now',
         secsAgo: 's ago',
@@ -44,7 +51,20 @@ const i18n = {
         port: 'Port',
         ssPassword: 'Password',
         encMethod: 'Encryption Method',
+        plugin: 'Plugin',
+        pluginOpts: 'Plugin Options',
+        identityKeys: 'Identity Keys (SS 2022, one per line)',
+        users: 'Users (name:key or name:method:password, one per line)',
         tags: 'Tags (comma separated)',
+        displayName: 'Public Name',
+        group: 'Group',
+        visibility: 'Visibility',
+        visPublic: 'Public',
+        visUnlisted: 'Unlisted',
+        visHidden: 'Hidden',
+        retentionDays: 'Retention (days)',
+        serverInterval: 'Check Interval (s)',
+        retries: 'Retries',
         enabled: 'Enabled',
         cancel: 'Cancel',
         save: 'Save',
@@ -53,11 +73,27 @@ const i18n = {
         checkInterval: 'Check Interval (seconds)',
         checkIntervalHint: 'Min 5s, how often to check all servers',
         intervalError: 'Interval must be at least 5 seconds',
+        tcpTimeout: 'TCP Timeout (s)',
+        ssTimeout: 'SS Timeout (s)',
+        testTarget: 'Test Targets',
+        maxConcurrent: 'Max Concurrent Checks',
+        jitter: 'Jitter (%)',
+        missedTicks: 'Overrun Checks',
+        missedDelay: 'Check once, then wait a full interval',
+        missedSkip: 'Check once, keep the original schedule',
+        missedBurst: 'Run every missed check',
+        rawDays: 'Raw Results (days)',
+        maxRows: 'Max Rows / Server',
+        phMaxRows: 'Unlimited',
+        hourlyDays: 'Hourly Rollups (days)',
+        dailyDays: 'Daily Rollups (days)',
+        cleanupAt: 'Daily Cleanup (UTC)',
         // Errors
         error: 'Error: ',
         deleteConfirm: 'Delete server',
         invalidHost: 'Invalid host: please enter a valid IP address or domain name',
         invalidPort: 'Invalid port: must be between 1 and 65535',
+        invalidFields: 'Please fix these fields:',
         // Theme
         switchLight: 'Switch to light mode',
         switchDark: 'Switch to dark mode',
@@ -70,6 +106,39 @@ const i18n = {
         phHost: 'e.g. 103.45.67.89',
         phPassword: 'SS password',
         phTags: 'e.g. jp, premium',
+        phDisplayName: 'Defaults to name',
+        phGroup: 'e.g. Asia',
+        phPlugin: 'e.g. obfs-local',
+        phRetention: 'Default',
+        // Import
+        importTitle: 'Import Servers',
+        importContent: 'ss:// links, SIP008 JSON or base64 subscription',
+        importGroup: 'Group',
+        phOptional: 'Optional',
+        preview: 'Preview',
+        importNew: 'new',
+        importDuplicate: 'duplicate',
+        importInvalid: 'invalid',
+        importSummary: '{new} new, {duplicates} duplicate, {invalid} invalid',
+        importDone: 'Imported {new} server(s)',
+        // Subscriptions
+        subscriptions: 'Subscriptions',
+        subName: 'Name',
+        subUrl: 'Subscription URL',
+        subInterval: 'Sync interval (seconds)',
+        subAdd: 'Add',
+        subSync: 'Sync',
+        subRemove: 'Remove',
+        subEnable: 'Enable',
+        subDisable: 'Disable',
+        subEmpty: 'No subscriptions yet.',
+        subNeverSynced: 'never synced',
+        subServers: '{n} server(s)',
+        subResult: '{added} added, {updated} updated, {disabled} disabled, {duplicates} duplicate, {invalid} invalid',
+        subRemoveConfirm: 'Remove subscription "{name}"? Its servers are kept.',
+        via: 'via',
+        user: 'User',
+        pluginFail: 'PLUGIN FAIL',
     },
     zh: {
         title: 'SS 服务器监控',
@@ -80,6 +149,7 @@ const i18n = {
         login: '登录',
         logout: '退出',
         settings: '设置',
+        import: '导入',
         emptyState: '未配置服务器，请添加服务器以开始监控。',
         tcpLatency: 'TCP 延迟',
         uptime: '在线率',
@@ -91,6 +161,11 @@ const i18n = {
         edit: '编辑',
         del: '删除',
         protocol: '协议',
+        groupUp: '全部正常',
+        groupPartial: '部分故障',
+        groupDown: '严重故障',
+        groupUnknown: '暂无数据',
+        serversUp: '在线',
         justNow: '刚刚',
         secsAgo: '秒前',
         minsAgo: '分钟前',
@@ -109,7 +184,20 @@ const i18n = {
         port: '端口',
         ssPassword: '密码',
         encMethod: '加密方式',
+        plugin: '插件',
+        pluginOpts: '插件参数',
+        identityKeys: '身份密钥（SS 2022，每行一个）',
+        users: '用户（名称:密钥 或 名称:加密方式:密码，每行一个）',
         tags: '标签（逗号分隔）',
+        displayName: '公开名称',
+        group: '分组',
+        visibility: '可见性',
+        visPublic: '公开',
+        visUnlisted: '不列出',
+        visHidden: '隐藏',
+        retentionDays: '保留天数',
+        serverInterval: '检测间隔（秒）',
+        retries: '重试次数',
         enabled: '启用',
         cancel: '取消',
         save: '保存',
@@ -117,10 +205,26 @@ const i18n = {
         checkInterval: '检查间隔（秒）',
         checkIntervalHint: '最少 5 秒，检查所有服务器的频率',
         intervalError: '间隔至少为 5 秒',
+        tcpTimeout: 'TCP 超时（秒）',
+        ssTimeout: 'SS 超时（秒）',
+        testTarget: '测试目标',
+        maxConcurrent: '最大并发检测数',
+        jitter: '随机抖动（%）',
+        missedTicks: '超时未完成的检测',
+        missedDelay: '补检一次，之后间隔重新计时',
+        missedSkip: '补检一次，保持原有节奏',
+        missedBurst: '补齐所有错过的检测',
+        rawDays: '原始记录（天）',
+        maxRows: '每台服务器最多记录数',
+        phMaxRows: '不限',
+        hourlyDays: '小时汇总（天）',
+        dailyDays: '日汇总（天）',
+        cleanupAt: '每日清理时间（UTC）',
         error: '错误：',
         deleteConfirm: '删除服务器',
         invalidHost: '主机地址无效：请输入有效的 IP 地址或域名',
         invalidPort: '端口无效：必须在 1 到 65535 之间',
+        invalidFields: '请修正以下字段：',
         switchLight: '切换到浅色模式',
         switchDark: '切换到深色模式',
         aeadGroup: 'AEAD',
@@ -130,6 +234,37 @@ const i18n = {
         phHost: '例如 103.45.67.89',
         phPassword: 'SS 密码',
         phTags: '例如 jp, premium',
+        phDisplayName: '默认使用名称',
+        phGroup: '例如 亚洲',
+        phPlugin: '例如 obfs-local',
+        phRetention: '默认',
+        importTitle: '导入服务器',
+        importContent: 'ss:// 链接、SIP008 JSON 或 base64 订阅内容',
+        importGroup: '分组',
+        phOptional: '可选',
+        preview: '预览',
+        importNew: '新增',
+        importDuplicate: '重复',
+        importInvalid: '无效',
+        importSummary: '新增 {new} 台，重复 {duplicates} 台，无效 {invalid} 台',
+        importDone: '已导入 {new} 台服务器',
+        subscriptions: '订阅',
+        subName: '名称',
+        subUrl: '订阅地址',
+        subInterval: '同步间隔（秒）',
+        subAdd: '添加',
+        subSync: '同步',
+        subRemove: '移除',
+        subEnable: '启用',
+        subDisable: '停用',
+        subEmpty: '暂无订阅。',
+        subNeverSynced: '从未同步',
+        subServers: '{n} 台服务器',
+        subResult: '新增 {added}，更新 {updated}，停用 {disabled}，重复 {duplicates}，无效 {invalid}',
+        subRemoveConfirm: '移除订阅“{name}”？其服务器会保留。',
+        via: '来自',
+        user: '用户',
+        pluginFail: '插件失败',
     }
 };
 
@@ -158,6 +293,7 @@ function applyI18nToHTML() {
     document.title = t('title');
     document.querySelector('header h1').textContent = t('title');
     btnAddServer.textContent = t('addServer');
+    btnImport.textContent = t('import');
     btnLogin.textContent = t('login');
     btnLogout.textContent = t('logout');
     document.querySelector('#empty-state p').textContent = t('emptyState');
@@ -175,12 +311,36 @@ function applyI18nToHTML() {
     document.querySelector('label[for="form-port"]').textContent = t('port');
     document.querySelector('label[for="form-password"]').textContent = t('ssPassword');
     document.querySelector('label[for="form-method"]').textContent = t('encMethod');
+    document.querySelector('label[for="form-plugin"]').textContent = t('plugin');
+    document.querySelector('label[for="form-plugin-opts"]').textContent = t('pluginOpts');
+    document.querySelector('label[for="form-identity-keys"]').textContent = t('identityKeys');
+    document.querySelector('label[for="form-users"]').textContent = t('users');
     document.querySelector('label[for="form-tags"]').textContent = t('tags');
+    document.querySelector('label[for="form-display-name"]').textContent = t('displayName');
+    document.querySelector('label[for="form-group"]').textContent = t('group');
+    document.querySelector('label[for="form-visibility"]').textContent = t('visibility');
+    const visOptions = document.querySelectorAll('#form-visibility option');
+    visOptions[0].textContent = t('visPublic');
+    visOptions[1].textContent = t('visUnlisted');
+    visOptions[2].textContent = t('visHidden');
+    document.querySelector('label[for="form-retention"]').textContent = t('retentionDays');
     document.querySelector('#form-enabled').parentElement.childNodes[1].textContent = ' ' + t('enabled');
     document.getElementById('form-name').placeholder = t('phName');
     document.getElementById('form-host').placeholder = t('phHost');
     document.getElementById('form-password').placeholder = t('phPassword');
     document.getElementById('form-tags').placeholder = t('phTags');
+    document.getElementById('form-display-name').placeholder = t('phDisplayName');
+    document.getElementById('form-group').placeholder = t('phGroup');
+    document.getElementById('form-plugin').placeholder = t('phPlugin');
+    document.getElementById('form-retention').placeholder = t('phRetention');
+    document.querySelector('label[for="form-interval"]').textContent = t('serverInterval');
+    document.querySelector('label[for="form-retries"]').textContent = t('retries');
+    document.querySelector('label[for="form-tcp-timeout"]').textContent = t('tcpTimeout');
+    document.querySelector('label[for="form-ss-timeout"]').textContent = t('ssTimeout');
+    document.querySelector('label[for="form-test-target"]').textContent = t('testTarget');
+    for (const id of ['form-interval', 'form-retries', 'form-tcp-timeout', 'form-ss-timeout', 'form-test-target']) {
+        document.getElementById(id).placeholder = t('phRetention');
+    }
     document.getElementById('btn-cancel').textContent = t('cancel');
     document.querySelector('#server-form button[type="submit"]').textContent = t('save');
 
@@ -194,9 +354,48 @@ function applyI18nToHTML() {
     document.querySelector('#settings-overlay .modal-header h2').textContent = t('settingsTitle');
     document.querySelector('label[for="settings-interval"]').textContent = t('checkInterval');
     document.querySelector('#settings-form .form-hint').textContent = t('checkIntervalHint');
+    const settingsLabels = {
+        'settings-tcp-timeout': 'tcpTimeout',
+        'settings-ss-timeout': 'ssTimeout',
+        'settings-test-target': 'testTarget',
+        'settings-retries': 'retries',
+        'settings-concurrency': 'maxConcurrent',
+        'settings-jitter': 'jitter',
+        'settings-missed-ticks': 'missedTicks',
+        'settings-raw-days': 'rawDays',
+        'settings-max-rows': 'maxRows',
+        'settings-hourly-days': 'hourlyDays',
+        'settings-daily-days': 'dailyDays',
+        'settings-cleanup-at': 'cleanupAt',
+    };
+    for (const [id, key] of Object.entries(settingsLabels)) {
+        document.querySelector(`label[for="${id}"]`).textContent = t(key);
+    }
+    const missedOptions = document.querySelectorAll('#settings-missed-ticks option');
+    missedOptions[0].textContent = t('missedDelay');
+    missedOptions[1].textContent = t('missedSkip');
+    missedOptions[2].textContent = t('missedBurst');
+    document.getElementById('settings-max-rows').placeholder = t('phMaxRows');
     document.getElementById('btn-settings-cancel').textContent = t('cancel');
     document.querySelector('#settings-form button[type="submit"]').textContent = t('save');
 
+    document.querySelector('#import-overlay .modal-header h2').textContent = t('importTitle');
+    document.querySelector('label[for="import-content"]').textContent = t('importContent');
+    document.querySelector('label[for="import-group"]').textContent = t('importGroup');
+    document.getElementById('import-group').placeholder = t('phOptional');
+    document.getElementById('btn-import-preview').textContent = t('preview');
+    document.querySelector('#import-form button[type="submit"]').textContent = t('import');
+
+    btnSubscriptions.textContent = t('subscriptions');
+    document.querySelector('#subscriptions-overlay .modal-header h2').textContent = t('subscriptions');
+    document.querySelector('label[for="sub-name"]').textContent = t('subName');
+    document.querySelector('label[for="sub-url"]').textContent = t('subUrl');
+    document.querySelector('label[for="sub-interval"]').textContent = t('subInterval');
+    document.querySelector('label[for="sub-group"]').textContent = t('importGroup');
+    document.getElementById('sub-group').placeholder = t('phOptional');
+    document.querySelector('#subscription-form button[type="submit"]').textContent = t('subAdd');
+    if (!subscriptionsOverlay.classList.contains('hidden')) renderSubscriptions();
+
     // Settings button if exists
     const btnSettings = document.getElementById('btn-settings');
     if (btnSettings) btnSettings.textContent = t('settings');
@@ -217,9 +416,11 @@ let isAuthed = false;
 
 // ---- Data State ----
 let servers = new Map(); // id -> ServerStatus
+let groups = []; // GroupStatus[], ordered
 
 // ---- DOM refs ----
 const grid = document.getElementById('server-grid');
+const groupList = document.getElementById('group-list');
 const emptyState = document.getElementById('empty-state');
 const modal = document.getElementById('modal-overlay');
 const form = document.getElementById('server-form');
@@ -233,6 +434,12 @@ const loginForm = document.getElementById('login-form');
 const loginError = document.getElementById('login-error');
 const settingsOverlay = document.getElementById('settings-overlay');
 const settingsForm = document.getElementById('settings-form');
+const btnImport = document.getElementById('btn-import');
+const importOverlay = document.getElementById('import-overlay');
+const importForm = document.getElementById('import-form');
+const btnSubscriptions = document.getElementById('btn-subscriptions');
+const subscriptionsOverlay = document.getElementById('subscriptions-overlay');
+const subscriptionForm = document.getElementById('subscription-form');
 const headerActions = document.querySelector('.header-actions');
 const btnTheme = document.getElementById('btn-theme');
 const btnLang = document.getElementById('btn-lang');
@@ -252,6 +459,15 @@ document.getElementById('btn-settings-close').addEventListener('click', () => se
 document.getElementById('btn-settings-cancel').addEventListener('click', () => settingsOverlay.classList.add('hidden'));
 settingsOverlay.addEventListener('click', (e) => { if (e.target === settingsOverlay) settingsOverlay.classList.add('hidden'); });
 settingsForm.addEventListener('submit', handleSettingsSave);
+btnImport.addEventListener('click', openImport);
+document.getElementById('btn-import-close').addEventListener('click', () => importOverlay.classList.add('hidden'));
+importOverlay.addEventListener('click', (e) => { if (e.target === importOverlay) importOverlay.classList.add('hidden'); });
+document.getElementById('btn-import-preview').addEventListener('click', () => runImport(true));
+importForm.addEventListener('submit', (e) => { e.preventDefault(); runImport(false); });
+btnSubscriptions.addEventListener('click', openSubscriptions);
+document.getElementById('btn-subscriptions-close').addEventListener('click', () => subscriptionsOverlay.classList.add('hidden'));
+subscriptionsOverlay.addEventListener('click', (e) => { if (e.target === subscriptionsOverlay) subscriptionsOverlay.classList.add('hidden'); });
+subscriptionForm.addEventListener('submit', handleSubscriptionAdd);
 btnTheme.addEventListener('click', toggleTheme);
 btnLang.addEventListener('click', () => setLang(currentLang === 'zh' ? 'en' : 'zh'));
 updateThemeIcon();
@@ -263,6 +479,7 @@ checkAuthStatus().then(() => {
     fetchServers();
     connectSSE();
 });
+fetchMethods();
 
 // ---- Auth ----
 
@@ -293,6 +510,8 @@ async function checkAuthStatus() {
 function setAuthUI(authed) {
     isAuthed = authed;
     btnAddServer.classList.toggle('hidden', !authed);
+    btnImport.classList.toggle('hidden', !authed);
+    btnSubscriptions.classList.toggle('hidden', !authed);
     btnLogin.classList.toggle('hidden', authed);
     btnLogout.classList.toggle('hidden', !authed);
 
@@ -364,12 +583,48 @@ async function fetchServers() {
         const data = await res.json();
         servers.clear();
         data.forEach(s => servers.set(s.server.id, s));
-        renderAll();
+        await fetchGroups();
     } catch (e) {
         console.error('Failed to fetch servers:', e);
     }
 }
 
+// Fill the method dropdown with what the server supports; the options in
+// the HTML stay as a fallback if this fails
+async function fetchMethods() {
+    try {
+        const res = await fetch('/api/methods');
+        if (!res.ok) return;
+        const methods = await res.json();
+        const groupIndex = { 'aead': 0, 'aead-2022': 1, 'stream': 2 };
+        const optgroups = document.querySelectorAll('#form-method optgroup');
+        const select = document.getElementById('form-method');
+        const current = select.value;
+        optgroups.forEach(g => { g.innerHTML = ''; });
+        for (const m of methods) {
+            const group = optgroups[groupIndex[m.category]];
+            if (!group) continue;
+            const opt = document.createElement('option');
+            opt.value = m.name;
+            opt.textContent = m.name;
+            group.appendChild(opt);
+        }
+        select.value = current;
+    } catch (e) {
+        console.error('Failed to fetch methods:', e);
+    }
+}
+
+async function fetchGroups() {
+    try {
+        const res = await fetch('/api/groups', { headers: authHeaders() });
+        groups = await res.json();
+    } catch (e) {
+        console.error('Failed to fetch groups:', e);
+    }
+    renderAll();
+}
+
 function connectSSE() {
     const evtSource = new EventSource('/api/events');
 
@@ -402,6 +657,15 @@ function connectSSE() {
                 servers.delete(event.server_id);
                 renderAll();
                 break;
+            case 'GroupsUpdated':
+                // SSE groups only cover public servers; authed users re-fetch the full view
+                if (isAuthed) {
+                    fetchGroups();
+                } else {
+                    groups = event.groups;
+                    renderGroups();
+                }
+                break;
         }
     };
 }
@@ -440,17 +704,31 @@ function handleServerUpdated(server) {
 async function createServer(data) {
     const res = await fetch('/api/servers', { method: 'POST', headers: authHeaders(), body: JSON.stringify(data) });
     if (res.status === 401) { setAuthUI(false); throw new Error('Unauthorized'); }
-    if (!res.ok) throw new Error(await res.text());
+    if (!res.ok) throw await serverFormError(res);
     return res.json();
 }
 
 async function updateServer(id, data) {
     const res = await fetch(`/api/servers/${id}`, { method: 'PUT', headers: authHeaders(), body: JSON.stringify(data) });
     if (res.status === 401) { setAuthUI(false); throw new Error('Unauthorized'); }
-    if (!res.ok) throw new Error(await res.text());
+    if (!res.ok) throw await serverFormError(res);
     return res.json();
 }
 
+// Turn a rejected create/update into an Error, carrying the invalid fields
+// (`[{field, message}]`) when the server names them
+async function serverFormError(res) {
+    const text = await res.text();
+    try {
+        const body = JSON.parse(text);
+        const err = new Error(body.error || text);
+        err.fields = body.fields || [];
+        return err;
+    } catch (e) {
+        return new Error(text);
+    }
+}
+
 async function deleteServer(id) {
     const res = await fetch(`/api/servers/${id}`, { method: 'DELETE', headers: authHeaders() });
     if (res.status === 401) { setAuthUI(false); throw new Error('Unauthorized'); }
@@ -461,7 +739,7 @@ async function triggerCheck(id) {
     const btn = document.querySelector(`[data-check-id="${id}"]`);
     if (btn) { btn.disabled = true; btn.textContent = '...'; }
     try {
-        const res = await fetch(`/api/servers/${id}/check`, { method: 'POST' });
+        const res = await fetch(`/api/servers/${id}/check`, { method: 'POST', headers: authHeaders() });
         if (!res.ok) throw new Error(await res.text());
         await fetchServers();
     } catch (e) {
@@ -475,6 +753,7 @@ async function triggerCheck(id) {
 
 function renderAll() {
     if (servers.size === 0) {
+        groupList.innerHTML = '';
         grid.innerHTML = '';
         grid.appendChild(emptyState);
         emptyState.style.display = '';
@@ -482,36 +761,100 @@ function renderAll() {
     }
 
     emptyState.style.display = 'none';
+    renderGroups();
 
-    const existingIds = new Set();
-    grid.querySelectorAll('.server-card').forEach(el => existingIds.add(el.dataset.id));
+    document.querySelectorAll('.server-card').forEach(el => {
+        if (!servers.has(el.dataset.id)) el.remove();
+    });
 
-    existingIds.forEach(id => {
-        if (!servers.has(id)) {
-            const el = grid.querySelector(`[data-id="${id}"]`);
-            if (el) el.remove();
+    servers.forEach(status => renderCard(status));
+}
+
+function renderGroups() {
+    const names = new Set(groups.map(g => g.name));
+    groupList.querySelectorAll('.group-section').forEach(el => {
+        if (!names.has(el.dataset.group)) {
+            // Move cards out before dropping the section; renderCard re-homes them
+            el.querySelectorAll('.server-card').forEach(card => grid.appendChild(card));
+            el.remove();
         }
     });
 
-    servers.forEach(status => renderCard(status));
+    groups.forEach(group => {
+        let section = groupSection(group.name);
+        if (!section) {
+            section = document.createElement('section');
+            section.className = 'group-section';
+            section.dataset.group = group.name;
+            section.innerHTML = `
+                <div class="group-header"></div>
+                <div class="group-description"></div>
+                <div class="server-grid"></div>
+            `;
+        }
+        // Keep sections in server-provided order
+        groupList.appendChild(section);
+
+        const badge = {
+            up: ['badge-green', t('groupUp')],
+            partial: ['badge-yellow', t('groupPartial')],
+            down: ['badge-red', t('groupDown')],
+        }[group.status] || ['badge-gray', t('groupUnknown')];
+        const uptimeStr = group.uptime_pct != null ? ` · ${group.uptime_pct.toFixed(2)}%` : '';
+
+        section.querySelector('.group-header').innerHTML = `
+            <h2>${esc(group.name)}</h2>
+            <span class="badge ${badge[0]}">${badge[1]}</span>
+            <span class="group-meta">${group.servers_up}/${group.servers_total} ${t('serversUp')}${uptimeStr}</span>
+        `;
+        const desc = section.querySelector('.group-description');
+        desc.textContent = group.description || '';
+        desc.style.display = group.description ? '' : 'none';
+    });
+}
+
+function groupSection(name) {
+    return [...groupList.querySelectorAll('.group-section')].find(el => el.dataset.group === name);
+}
+
+// One SS check line with its badge, plus the error when authed
+function ssStatusHtml(label, ss) {
+    const badge = ss.success
+        ? `<span class="badge badge-green">OK${ss.latency_ms ? ` ${ss.latency_ms.toFixed(0)}ms` : ''}</span>`
+        : ss.error_kind === 'plugin'
+            ? `<span class="badge badge-red">${t('pluginFail')}</span>`
+            : `<span class="badge badge-red">FAIL</span>`;
+    const error = (!ss.success && ss.error && isAuthed) ? `<div class="ss-error">${esc(ss.error)}</div>` : '';
+    return `
+        <div class="ss-status">
+            <span>${label}</span>
+            ${badge}
+        </div>
+        ${error}
+    `;
 }
 
 function renderCard(status) {
     const { server } = status;
-    let card = grid.querySelector(`[data-id="${server.id}"]`);
+    let card = document.querySelector(`.server-card[data-id="${server.id}"]`);
 
     if (!card) {
         card = document.createElement('div');
         card.className = 'server-card';
         card.dataset.id = server.id;
-        grid.appendChild(card);
     }
 
+    const section = server.group ? groupSection(server.group) : null;
+    const container = section ? section.querySelector('.server-grid') : grid;
+    if (card.parentElement !== container) container.appendChild(card);
+
     const tcp = status.latest_result?.tcp_check;
     const ss = status.latest_result?.ss_check;
 
     const isUp = tcp?.reachable ?? false;
-    const ssOk = ss?.success ?? false;
+    // A failed user check degrades the server just like its own key failing
+    const usersOk = (status.latest_result?.user_checks || []).every(u => u.success);
+    const ssOk = (ss?.success ?? false) && usersOk;
 
     let statusClass;
     if (!server.enabled) {
@@ -534,6 +877,12 @@ function renderCard(status) {
     const uptimeClass = status.uptime_pct >= 95 ? 'good' :
         status.uptime_pct >= 80 ? 'warn' : (status.history.length > 0 ? 'bad' : 'na');
 
+    const lat = status.tcp_latency;
+    const fmtMs = v => v != null ? `${v.toFixed(1)}ms` : '--';
+    const avgTitle = lat
+        ? `p50 ${fmtMs(lat.p50_ms)} · p90 ${fmtMs(lat.p90_ms)} · p99 ${fmtMs(lat.p99_ms)} · jitter ${fmtMs(lat.jitter_ms)}`
+        : '';
+
     const avgStr = status.avg_latency_ms != null ? `${status.avg_latency_ms.toFixed(1)}` : '--';
     const avgClass = status.avg_latency_ms == null ? 'na' :
         status.avg_latency_ms < 200 ? 'good' : status.avg_latency_ms < 500 ? 'warn' : 'bad';
@@ -541,25 +890,22 @@ function renderCard(status) {
     const tagsHtml = server.tags.map(t => `<span class="tag">${esc(t)}</span>`).join('');
 
     // Address line: only show when authed
+    const sourceHtml = server.source ? ` <span class="card-source">${t('via')} ${esc(server.source)}</span>` : '';
     const addressHtml = isAuthed && server.host
-        ? `<div class="card-address">${esc(server.host)}:${server.port}</div>`
+        ? `<div class="card-address">${esc(server.host)}:${server.port}${sourceHtml}</div>`
         : '';
 
     // SS protocol check result display (only show method when authed)
     let ssHtml = '';
     if (ss) {
         const methodLabel = isAuthed && server.method ? esc(server.method) : t('protocol');
-        const ssBadge = ss.success
-            ? `<span class="badge badge-green">OK${ss.latency_ms ? ` ${ss.latency_ms.toFixed(0)}ms` : ''}</span>`
-            : `<span class="badge badge-red">FAIL</span>`;
-        const ssError = (!ss.success && ss.error && isAuthed) ? `<div class="ss-error">${esc(ss.error)}</div>` : '';
-        ssHtml = `
-            <div class="ss-status">
-                <span>SS ${methodLabel}</span>
-                ${ssBadge}
-            </div>
-            ${ssError}
-        `;
+        ssHtml = ssStatusHtml(`SS ${methodLabel}`, ss);
+    }
+    // Per-user checks of a multi-user server (numbered instead of named when not authed)
+    const userMethods = new Map((server.users || []).map(u => [u.name, u.method]));
+    for (const u of status.latest_result?.user_checks || []) {
+        const method = userMethods.get(u.user);
+        ssHtml += ssStatusHtml(`${t('user')} ${esc(u.user)}${method ? ' ' + esc(method) : ''}`, u);
     }
 
     // Action buttons: only show edit/delete when authed
@@ -591,6 +937,10 @@ function renderCard(status) {
 
     const totalChecks = status.total_checks ?? status.history.length;
 
+    const windowsHtml = (status.windows || []).map(w =>
+        `<span>${w.window} <b>${w.uptime_pct != null ? w.uptime_pct.toFixed(2) + '%' : '--'}</b></span>`
+    ).join('');
+
     card.innerHTML = `
         <div class="card-header">
             <div class="card-title">
@@ -610,13 +960,14 @@ function renderCard(status) {
                 <div class="stat-value ${uptimeClass}">${uptimeStr}</div>
                 <div class="stat-label">${t('uptime')}</div>
             </div>
-            <div class="stat">
+            <div class="stat" title="${avgTitle}">
                 <div class="stat-value ${avgClass}">${avgStr}<small>${status.avg_latency_ms != null ? 'ms' : ''}</small></div>
                 <div class="stat-label">${t('avg')}</div>
             </div>
         </div>
         ${ssHtml}
         ${chartEntries.length > 0 ? `<div class="latency-chart">${barsHtml}</div>` : ''}
+        ${windowsHtml ? `<div class="uptime-windows">${windowsHtml}</div>` : ''}
         <div class="card-footer">
             <span>${t('lastCheck')}: ${lastCheck}</span>
             <span>${totalChecks} ${t('checks')}</span>
@@ -633,6 +984,7 @@ function openModal() {
     document.getElementById('form-id').value = '';
     document.getElementById('form-port').value = '8388';
     document.getElementById('form-method').value = 'aes-256-gcm';
+    document.getElementById('form-visibility').value = 'public';
     document.getElementById('form-enabled').checked = true;
     modal.classList.remove('hidden');
 }
@@ -649,23 +1001,73 @@ function openEditModal(id) {
     document.getElementById('form-host').value = s.host;
     document.getElementById('form-port').value = s.port;
     document.getElementById('form-password').value = s.password;
-    document.getElementById('form-method').value = s.method;
+    setMethodOption(s.method);
+    document.getElementById('form-plugin').value = s.plugin || '';
+    document.getElementById('form-plugin-opts').value = s.plugin_opts || '';
+    document.getElementById('form-identity-keys').value = (s.identity_keys || []).join('\n');
+    document.getElementById('form-users').value = (s.users || [])
+        .map(u => u.method ? `${u.name}:${u.method}:${u.password}` : `${u.name}:${u.password}`)
+        .join('\n');
     document.getElementById('form-tags').value = s.tags.join(', ');
+    document.getElementById('form-display-name').value = s.display_name || '';
+    document.getElementById('form-group').value = s.group || '';
+    document.getElementById('form-visibility').value = s.visibility || 'public';
+    document.getElementById('form-retention').value = s.retention_days || '';
+    document.getElementById('form-interval').value = s.check_interval_secs ?? '';
+    document.getElementById('form-retries').value = s.retries ?? '';
+    document.getElementById('form-tcp-timeout').value = s.tcp_timeout_secs ?? '';
+    document.getElementById('form-ss-timeout').value = s.ss_timeout_secs ?? '';
+    document.getElementById('form-test-target').value = (s.test_targets ?? []).join(', ');
     document.getElementById('form-enabled').checked = s.enabled;
     modal.classList.remove('hidden');
 }
 
 function closeModal() {
+    clearFieldErrors();
     modal.classList.add('hidden');
 }
 
+// Form inputs for the field names used in server validation errors
+const FIELD_INPUTS = {
+    'name': 'form-name',
+    'host': 'form-host',
+    'port': 'form-port',
+    'password': 'form-password',
+    'method': 'form-method',
+    'plugin': 'form-plugin',
+    'plugin_opts': 'form-plugin-opts',
+    'identity_keys': 'form-identity-keys',
+    'users': 'form-users',
+    'retention_days': 'form-retention',
+    'check_interval_secs': 'form-interval',
+    'retries': 'form-retries',
+    'tcp_timeout_secs': 'form-tcp-timeout',
+    'ss_timeout_secs': 'form-ss-timeout',
+    'test_targets': 'form-test-target',
+};
+
+function showFieldErrors(fields) {
+    clearFieldErrors();
+    for (const f of fields) {
+        // `users[1].password` belongs to the users textarea
+        const id = FIELD_INPUTS[f.field] || FIELD_INPUTS[f.field.split('[')[0]];
+        const input = id && document.getElementById(id);
+        if (input) input.classList.add('field-invalid');
+    }
+}
+
+function clearFieldErrors() {
+    form.querySelectorAll('.field-invalid').forEach(el => el.classList.remove('field-invalid'));
+}
+
 async function handleFormSubmit(e) {
     e.preventDefault();
     const id = document.getElementById('form-id').value;
     const tagsRaw = document.getElementById('form-tags').value;
     const tags = tagsRaw ? tagsRaw.split(',').map(t => t.trim()).filter(t => t) : [];
 
-    const host = document.getElementById('form-host').value.trim();
+    // IPv6 addresses are stored without brackets
+    const host = document.getElementById('form-host').value.trim().replace(/^\[(.*)\]$/, '$1');
     const port = parseInt(document.getElementById('form-port').value, 10);
 
     if (!isValidHost(host)) {
@@ -683,8 +1085,21 @@ async function handleFormSubmit(e) {
         port,
         password: document.getElementById('form-password').value,
         method: document.getElementById('form-method').value,
+        plugin: document.getElementById('form-plugin').value.trim() || null,
+        plugin_opts: document.getElementById('form-plugin-opts').value.trim() || null,
+        identity_keys: formLines('form-identity-keys'),
+        users: formLines('form-users').map(parseUserLine),
         enabled: document.getElementById('form-enabled').checked,
         tags,
+        display_name: document.getElementById('form-display-name').value.trim() || null,
+        group: document.getElementById('form-group').value.trim() || null,
+        visibility: document.getElementById('form-visibility').value,
+        retention_days: parseInt(document.getElementById('form-retention').value, 10) || null,
+        check_interval_secs: optionalInt('form-interval'),
+        retries: optionalInt('form-retries'),
+        tcp_timeout_secs: optionalInt('form-tcp-timeout'),
+        ss_timeout_secs: optionalInt('form-ss-timeout'),
+        test_targets: hostList('form-test-target'),
     };
 
     try {
@@ -695,7 +1110,12 @@ async function handleFormSubmit(e) {
         }
         closeModal();
     } catch (err) {
-        alert(t('error') + err.message);
+        if (err.fields && err.fields.length) {
+            showFieldErrors(err.fields);
+            alert(t('invalidFields') + '\n' + err.fields.map(f => `${f.field}: ${f.message}`).join('\n'));
+        } else {
+            alert(t('error') + err.message);
+        }
     }
 }
 
@@ -736,6 +1156,18 @@ async function openSettings() {
         if (res.status === 401) { setAuthUI(false); return; }
         const data = await res.json();
         document.getElementById('settings-interval').value = data.check_interval_secs;
+        document.getElementById('settings-tcp-timeout').value = data.tcp_timeout_secs;
+        document.getElementById('settings-ss-timeout').value = data.ss_timeout_secs;
+        document.getElementById('settings-test-target').value = data.test_targets.join(', ');
+        document.getElementById('settings-retries').value = data.retries;
+        document.getElementById('settings-concurrency').value = data.max_concurrent_checks;
+        document.getElementById('settings-jitter').value = data.jitter_pct;
+        document.getElementById('settings-missed-ticks').value = data.missed_ticks;
+        document.getElementById('settings-raw-days').value = data.retention.raw_days;
+        document.getElementById('settings-max-rows').value = data.retention.max_rows_per_server ?? '';
+        document.getElementById('settings-hourly-days').value = data.retention.hourly_days;
+        document.getElementById('settings-daily-days').value = data.retention.daily_days;
+        document.getElementById('settings-cleanup-at').value = data.retention.cleanup_at;
     } catch (e) {
         console.error('Failed to fetch settings:', e);
     }
@@ -749,11 +1181,30 @@ async function handleSettingsSave(e) {
         alert(t('intervalError'));
         return;
     }
+    const num = (id) => parseInt(document.getElementById(id).value, 10);
+    const maxRows = num('settings-max-rows');
+    const payload = {
+        check_interval_secs: interval,
+        tcp_timeout_secs: num('settings-tcp-timeout'),
+        ss_timeout_secs: num('settings-ss-timeout'),
+        test_targets: hostList('settings-test-target') ?? [],
+        retries: num('settings-retries'),
+        max_concurrent_checks: num('settings-concurrency'),
+        jitter_pct: num('settings-jitter'),
+        missed_ticks: document.getElementById('settings-missed-ticks').value,
+        retention: {
+            raw_days: num('settings-raw-days'),
+            hourly_days: num('settings-hourly-days'),
+            daily_days: num('settings-daily-days'),
+            max_rows_per_server: isNaN(maxRows) ? null : maxRows,
+            cleanup_at: document.getElementById('settings-cleanup-at').value,
+        },
+    };
     try {
         const res = await fetch('/api/settings', {
             method: 'PUT',
             headers: authHeaders(),
-            body: JSON.stringify({ check_interval_secs: interval }),
+            body: JSON.stringify(payload),
         });
         if (res.status === 401) { setAuthUI(false); return; }
         if (!res.ok) { alert(t('error') + await res.text()); return; }
@@ -763,8 +1214,226 @@ async function handleSettingsSave(e) {
     }
 }
 
+// ---- Import ----
+
+function openImport() {
+    if (!isAuthed) return;
+    importForm.reset();
+    document.getElementById('import-summary').classList.add('hidden');
+    document.getElementById('import-results').innerHTML = '';
+    importOverlay.classList.remove('hidden');
+}
+
+async function runImport(dryRun) {
+    const content = document.getElementById('import-content').value.trim();
+    if (!content) return;
+    const group = document.getElementById('import-group').value.trim();
+    try {
+        const res = await fetch('/api/servers/import', {
+            method: 'POST',
+            headers: authHeaders(),
+            body: JSON.stringify({ content, dry_run: dryRun, group: group || null }),
+        });
+        if (res.status === 401) { setAuthUI(false); return; }
+        if (!res.ok) { alert(t('error') + await res.text()); return; }
+        const data = await res.json();
+        renderImportResult(data);
+        if (!dryRun && data.new > 0) fetchServers();
+    } catch (err) {
+        alert(t('error') + err.message);
+    }
+}
+
+function renderImportResult(data) {
+    const summary = document.getElementById('import-summary');
+    const template = data.dry_run ? t('importSummary') : t('importDone') + ' · ' + t('importSummary');
+    summary.textContent = template
+        .replaceAll('{new}', data.new)
+        .replaceAll('{duplicates}', data.duplicates)
+        .replaceAll('{invalid}', data.invalid);
+    summary.classList.remove('hidden');
+
+    const badges = {
+        new: ['badge-green', 'importNew'],
+        duplicate: ['badge-yellow', 'importDuplicate'],
+        invalid: ['badge-red', 'importInvalid'],
+    };
+    document.getElementById('import-results').innerHTML = data.entries.map(entry => {
+        const [cls, key] = badges[entry.status];
+        const addr = entry.host ? `${esc(entry.host)}:${entry.port}` : '';
+        const name = entry.name ? `${esc(entry.name)} ` : '';
+        const error = entry.error ? `<span class="import-error">${esc(entry.error)}</span>` : '';
+        return `<li><span class="badge ${cls}">${t(key)}</span><span>${name}${addr}</span>${error}</li>`;
+    }).join('');
+}
+
+// ---- Subscriptions ----
+
+let subscriptions = []; // SubscriptionStatus[]
+
+async function openSubscriptions() {
+    if (!isAuthed) return;
+    subscriptionForm.reset();
+    subscriptionsOverlay.classList.remove('hidden');
+    await fetchSubscriptions();
+}
+
+async function fetchSubscriptions() {
+    try {
+        const res = await fetch('/api/subscriptions', { headers: authHeaders() });
+        if (res.status === 401) { setAuthUI(false); return; }
+        if (!res.ok) return;
+        subscriptions = await res.json();
+        renderSubscriptions();
+    } catch (err) {
+        console.error('Failed to fetch subscriptions:', err);
+    }
+}
+
+function renderSubscriptions() {
+    const list = document.getElementById('subscription-list');
+    if (subscriptions.length === 0) {
+        list.innerHTML = `<li class="form-hint">${t('subEmpty')}</li>`;
+        return;
+    }
+    list.innerHTML = subscriptions.map((sub, i) => {
+        const last = sub.last_sync;
+        let lastHtml = `<span class="form-hint">${t('subNeverSynced')}</span>`;
+        if (last && last.error) {
+            lastHtml = `<span class="badge badge-red">${timeAgo(new Date(last.timestamp))}</span>
+                <span class="import-error">${esc(last.error)}</span>`;
+        } else if (last) {
+            const result = t('subResult').replace(/\{(\w+)\}/g, (_, k) => last[k]);
+            lastHtml = `<span class="badge badge-green">${timeAgo(new Date(last.timestamp))}</span>
+                <span class="import-error">${result}</span>`;
+        }
+        return `
+            <li class="${sub.enabled ? '' : 'disabled'}">
+                <div class="subscription-head">
+                    <b>${esc(sub.name)}</b>
+                    <span class="form-hint">${t('subServers').replace('{n}', sub.servers)} · ${sub.interval_secs}s${sub.group ? ' · ' + esc(sub.group) : ''}</span>
+                    <div class="card-actions">
+                        <button class="btn btn-sm" onclick="syncSubscription(${i})">${t('subSync')}</button>
+                        <button class="btn btn-sm" onclick="toggleSubscription(${i})">${t(sub.enabled ? 'subDisable' : 'subEnable')}</button>
+                        <button class="btn btn-sm btn-danger" onclick="removeSubscription(${i})">${t('subRemove')}</button>
+                    </div>
+                </div>
+                <div class="subscription-url">${esc(sub.url)}</div>
+                <div class="subscription-last">${lastHtml}</div>
+            </li>
+        `;
+    }).join('');
+}
+
+// PUT the whole list; the response carries the fresh status of each
+async function saveSubscriptions(list) {
+    const body = list.map(({ name, url, interval_secs, enabled, group }) => ({ name, url, interval_secs, enabled, group }));
+    try {
+        const res = await fetch('/api/subscriptions', {
+            method: 'PUT',
+            headers: authHeaders(),
+            body: JSON.stringify(body),
+        });
+        if (res.status === 401) { setAuthUI(false); return false; }
+        if (!res.ok) { alert(t('error') + await res.text()); return false; }
+        subscriptions = await res.json();
+        renderSubscriptions();
+        return true;
+    } catch (err) {
+        alert(t('error') + err.message);
+        return false;
+    }
+}
+
+async function handleSubscriptionAdd(e) {
+    e.preventDefault();
+    const group = document.getElementById('sub-group').value.trim();
+    const sub = {
+        name: document.getElementById('sub-name').value.trim(),
+        url: document.getElementById('sub-url').value.trim(),
+        interval_secs: parseInt(document.getElementById('sub-interval').value, 10),
+        enabled: true,
+        group: group || null,
+    };
+    if (await saveSubscriptions([...subscriptions, sub])) {
+        subscriptionForm.reset();
+        syncSubscription(subscriptions.findIndex(s => s.name === sub.name));
+    }
+}
+
+function toggleSubscription(i) {
+    const list = subscriptions.map((s, j) => j === i ? { ...s, enabled: !s.enabled } : s);
+    saveSubscriptions(list);
+}
+
+function removeSubscription(i) {
+    if (!confirm(t('subRemoveConfirm').replace('{name}', subscriptions[i].name))) return;
+    saveSubscriptions(subscriptions.filter((_, j) => j !== i));
+}
+
+async function syncSubscription(i) {
+    const sub = subscriptions[i];
+    if (!sub) return;
+    try {
+        const res = await fetch(`/api/subscriptions/${encodeURIComponent(sub.name)}/sync`, {
+            method: 'POST',
+            headers: authHeaders(),
+        });
+        if (res.status === 401) { setAuthUI(false); return; }
+        if (!res.ok) { alert(t('error') + await res.text()); return; }
+        await fetchSubscriptions();
+        fetchServers();
+    } catch (err) {
+        alert(t('error') + err.message);
+    }
+}
+
 // ---- Utils ----
 
+// Integer from a number input, or null when left empty
+function optionalInt(id) {
+    const n = parseInt(document.getElementById(id).value, 10);
+    return isNaN(n) ? null : n;
+}
+
+// Comma- or space-separated host names, or null when the field is empty
+function hostList(id) {
+    const hosts = document.getElementById(id).value.split(/[\s,]+/).filter(Boolean);
+    return hosts.length ? hosts : null;
+}
+
+// Select `method`, adding it to the dropdown if it is not listed (e.g.
+// `none` or `aes-128-cfb8`), so editing a server keeps its method
+function setMethodOption(method) {
+    const select = document.getElementById('form-method');
+    if (method && !Array.from(select.options).some(o => o.value === method)) {
+        const opt = document.createElement('option');
+        opt.value = method;
+        opt.textContent = method;
+        select.appendChild(opt);
+    }
+    select.value = method;
+}
+
+// A users line is `name:password`, or `name:method:password` for a user
+// with a method of its own; the name ends at the first colon
+function parseUserLine(line) {
+    const at = line.indexOf(':');
+    const name = line.slice(0, at).trim();
+    const rest = line.slice(at + 1);
+    const sep = rest.indexOf(':');
+    const method = rest.slice(0, sep).trim();
+    const known = Array.from(document.querySelectorAll('#form-method option'), o => o.value);
+    if (sep > 0 && known.includes(method)) {
+        return { name, password: rest.slice(sep + 1).trim(), method };
+    }
+    return { name, password: rest.trim(), method: null };
+}
+
+function formLines(id) {
+    return document.getElementById(id).value.split('\n').map(l => l.trim()).filter(l => l);
+}
+
 function isValidHost(host) {
     if (!host) return false;
     // IPv4
@@ -27,7 +27,11 @@ pub async fn login(
     State(state): State<SharedState>,
     Json(req): Json<LoginRequest>,
 ) -> Result<Json<LoginResponse>, AppError> {
-    if req.username == state.auth.username && req.password == state.auth.password {
+    let valid = {
+        let auth = state.auth.read().await;
+        req.username == auth.username && req.password == auth.password
+    };
+    if valid {
         let token = Uuid::new_v4().to_string();
         {
             let mut sessions = state.sessions.write().await;
@@ -72,6 +76,14 @@ pub fn extract_token(headers: &HeaderMap) -> Option<String> {
         .map(|s| s.to_string())
 }
 
+/// Whether the request carries a valid session token
+pub async fn is_authed(state: &SharedState, headers: &HeaderMap) -> bool {
+    match extract_token(headers) {
+        Some(token) => is_authenticated(state, &token).await,
+        None => false,
+    }
+}
+
 /// Check auth from headers, return Err(Unauthorized) if not authenticated
 pub async fn require_auth(state: &SharedState, headers: &HeaderMap) -> Result<(), AppError> {
     match extract_token(headers) {
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::io;
+
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::auth::require_auth;
+use crate::error::AppError;
+use crate::links;
+use crate::models::{CheckResult, Server};
+use crate::state::{get_server_statuses, is_fully_up, SharedState};
+
+/// Rows fetched per DB round-trip; the lock is released between batches
+const EXPORT_BATCH: usize = 1000;
+
+const CSV_HEADER: &str = "server_id,server_name,timestamp,tcp_reachable,tcp_latency_ms,tcp_error,ss_success,ss_latency_ms,ss_error,user_failures\n";
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// Inclusive RFC 3339 lower bound
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive RFC 3339 upper bound
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Client config formats servers can be exported as
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerExportFormat {
+    /// `ss://` URIs one per line
+    #[default]
+    Ss,
+    /// Same, base64-encoded, as subscription URLs serve it
+    Base64,
+    Sip008,
+    Clash,
+    #[serde(rename = "sing-box")]
+    SingBox,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ServerExportQuery {
+    #[serde(default)]
+    pub format: ServerExportFormat,
+    /// Only servers with this tag
+    pub tag: Option<String>,
+    /// Only servers whose latest check passed
+    #[serde(default)]
+    pub healthy: bool,
+}
+
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    server_name: Option<&'a str>,
+    #[serde(flatten)]
+    result: &'a CheckResult,
+}
+
+/// GET /api/results/:id/export?format=csv|ndjson&from=&to= — requires auth
+pub async fn export_server(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    require_auth(&state, &headers).await?;
+    if !state.servers.read().await.contains_key(&id) {
+        return Err(AppError::NotFound(id));
+    }
+    export(state, Some(id), query).await
+}
+
+/// GET /api/results/export?format=csv|ndjson&from=&to= — all servers, requires auth
+pub async fn export_all(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, AppError> {
+    require_auth(&state, &headers).await?;
+    export(state, None, query).await
+}
+
+/// GET /api/servers/export?format=ss|base64|sip008|clash|sing-box&tag=&healthy=
+/// — enabled servers as client config, sorted by name, requires auth
+pub async fn export_servers(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(query): Query<ServerExportQuery>,
+) -> Result<Response, AppError> {
+    require_auth(&state, &headers).await?;
+
+    let mut servers: Vec<Server> = get_server_statuses(&state)
+        .await
+        .into_iter()
+        .filter(|s| s.server.enabled)
+        .filter(|s| {
+            query
+                .tag
+                .as_ref()
+                .is_none_or(|tag| s.server.tags.contains(tag))
+        })
+        .filter(|s| !query.healthy || is_fully_up(s))
+        .map(|s| s.server)
+        .collect();
+    servers.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+
+    let (body, content_type, filename) = match query.format {
+        ServerExportFormat::Ss => (
+            links::to_uri_list(&servers),
+            "text/plain; charset=utf-8",
+            "servers.txt",
+        ),
+        ServerExportFormat::Base64 => (
+            base64::engine::general_purpose::STANDARD.encode(links::to_uri_list(&servers)),
+            "text/plain; charset=utf-8",
+            "servers.b64",
+        ),
+        ServerExportFormat::Sip008 => (
+            links::to_sip008(&servers)?,
+            "application/json",
+            "servers.json",
+        ),
+        ServerExportFormat::Clash => (links::to_clash(&servers)?, "application/yaml", "clash.yaml"),
+        ServerExportFormat::SingBox => (
+            links::to_sing_box(&servers)?,
+            "application/json",
+            "sing-box.json",
+        ),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+async fn export(
+    state: SharedState,
+    server_id: Option<Uuid>,
+    query: ExportQuery,
+) -> Result<Response, AppError> {
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from >= to {
+            return Err(AppError::BadRequest("from must be earlier than to".into()));
+        }
+    }
+
+    let names: HashMap<Uuid, String> = {
+        let servers = state.servers.read().await;
+        servers.values().map(|s| (s.id, s.name.clone())).collect()
+    };
+    let format = query.format;
+
+    // Stream rows in id order, one batch per poll, so memory stays bounded
+    // regardless of how much history matches.
+    let header = match format {
+        ExportFormat::Csv => Some(Bytes::from_static(CSV_HEADER.as_bytes())),
+        ExportFormat::Ndjson => None,
+    };
+    let rows = stream::unfold(Some(0i64), move |cursor| {
+        let state = state.clone();
+        let names = names.clone();
+        async move {
+            let after_id = cursor?;
+            let batch = state
+                .db
+                .export_batch(server_id, query.from, query.to, after_id, EXPORT_BATCH)
+                .await;
+            let batch = match batch {
+                Ok(batch) => batch,
+                Err(e) => {
+                    // Fail the body rather than end it, so the client sees an
+                    // aborted transfer instead of a clean but truncated file
+                    tracing::error!("Export query failed: {}", e);
+                    return Some((Err(io::Error::other(e.to_string())), None));
+                }
+            };
+            if batch.is_empty() {
+                return None;
+            }
+
+            let next = if batch.len() == EXPORT_BATCH {
+                batch.last().map(|(id, _)| *id)
+            } else {
+                None
+            };
+            let mut chunk = String::new();
+            for (_, result) in &batch {
+                let name = names.get(&result.server_id).map(String::as_str);
+                match format {
+                    ExportFormat::Csv => write_csv_row(&mut chunk, name, result),
+                    ExportFormat::Ndjson => {
+                        let row = ExportRow {
+                            server_name: name,
+                            result,
+                        };
+                        if let Ok(line) = serde_json::to_string(&row) {
+                            chunk.push_str(&line);
+                            chunk.push('\n');
+                        }
+                    }
+                }
+            }
+            Some((Ok(Bytes::from(chunk)), next))
+        }
+    });
+    let body = stream::iter(header.map(Ok::<_, io::Error>)).chain(rows);
+
+    let (content_type, ext) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+        ExportFormat::Ndjson => ("application/x-ndjson", "ndjson"),
+    };
+    let filename = match server_id {
+        Some(id) => format!("check-results-{}.{}", id, ext),
+        None => format!("check-results.{}", ext),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+fn write_csv_row(out: &mut String, name: Option<&str>, r: &CheckResult) {
+    let opt_num = |v: Option<f64>| v.map(|v| format!("{:.3}", v)).unwrap_or_default();
+    let fields = [
+        r.server_id.to_string(),
+        csv_escape(name.unwrap_or_default()),
+        r.timestamp.to_rfc3339(),
+        r.tcp_check.reachable.to_string(),
+        opt_num(r.tcp_check.latency_ms),
+        csv_escape(r.tcp_check.error.as_deref().unwrap_or_default()),
+        r.ss_check
+            .as_ref()
+            .map(|ss| ss.success.to_string())
+            .unwrap_or_default(),
+        opt_num(r.ss_check.as_ref().and_then(|ss| ss.latency_ms)),
+        csv_escape(
+            r.ss_check
+                .as_ref()
+                .and_then(|ss| ss.error.as_deref())
+                .unwrap_or_default(),
+        ),
+        // Failed user checks; empty for servers without users
+        if r.user_checks.is_empty() {
+            String::new()
+        } else {
+            let failed = r.user_checks.iter().filter(|u| !u.ss_check.success);
+            failed.count().to_string()
+        },
+    ];
+    out.push_str(&fields.join(","));
+    out.push('\n');
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or line break (RFC 4180)
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
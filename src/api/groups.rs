@@ -0,0 +1,25 @@
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::Json;
+
+use crate::api::auth::is_authed;
+use crate::models::GroupStatus;
+use crate::state::{
+    compute_group_statuses, get_server_statuses, public_group_statuses, SharedState,
+};
+
+/// GET /api/groups
+/// Unauthenticated: groups aggregated over publicly listed servers only
+/// Authenticated: groups aggregated over all servers
+pub async fn list(State(state): State<SharedState>, headers: HeaderMap) -> Json<Vec<GroupStatus>> {
+    let statuses = get_server_statuses(&state).await;
+
+    if is_authed(&state, &headers).await {
+        Json(compute_group_statuses(
+            &state.groups.read().await,
+            &statuses,
+        ))
+    } else {
+        Json(public_group_statuses(&state, &statuses).await)
+    }
+}
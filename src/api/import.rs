@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::auth::require_auth;
+use crate::config;
+use crate::error::AppError;
+use crate::links::{self, SharedServer};
+use crate::models::{CheckOverrides, Server, SseEvent, Visibility};
+use crate::state::{broadcast_groups, SharedState};
+
+#[derive(Debug, Deserialize)]
+pub struct ImportRequest {
+    /// `ss://` URIs one per line, a SIP008 document, or either base64-encoded
+    pub content: String,
+    /// Report what would be imported without changing anything
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Applied to every imported server
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    /// Imported, or would be on a dry run
+    New,
+    /// Same host and port as an existing server or an earlier entry
+    Duplicate,
+    /// Unparseable, or rejected by the same checks as a server added by hand
+    Invalid,
+}
+
+/// Outcome for one entry of the imported content, in input order.
+/// Passwords are never echoed back.
+#[derive(Debug, Serialize)]
+pub struct ImportEntry {
+    pub status: ImportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// The new server's id, or the existing server's for a duplicate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportResponse {
+    pub dry_run: bool,
+    pub new: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+    pub entries: Vec<ImportEntry>,
+}
+
+/// POST /api/servers/import — requires auth
+pub async fn import(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(req): Json<ImportRequest>,
+) -> Result<Json<ImportResponse>, AppError> {
+    require_auth(&state, &headers).await?;
+
+    let parsed = links::parse(&req.content).map_err(|e| AppError::BadRequest(e.to_string()))?;
+    if parsed.is_empty() {
+        return Err(AppError::BadRequest("no servers found".into()));
+    }
+
+    let mut entries = Vec::with_capacity(parsed.len());
+    let mut added = Vec::new();
+    {
+        let mut servers = state.servers.write().await;
+        let mut by_addr: HashMap<(String, u16), Uuid> = servers
+            .values()
+            .map(|s| ((s.host.to_lowercase(), s.port), s.id))
+            .collect();
+        let mut ids: HashSet<Uuid> = servers.keys().copied().collect();
+
+        for item in parsed {
+            let shared = match item {
+                Ok(shared) => shared,
+                Err(e) => {
+                    entries.push(invalid_entry(None, e));
+                    continue;
+                }
+            };
+            let key = (shared.host.to_lowercase(), shared.port);
+            if let Some(&existing) = by_addr.get(&key) {
+                entries.push(entry(&shared, ImportStatus::Duplicate, existing));
+                continue;
+            }
+
+            // Keep a SIP008 id so later imports of the same document line up
+            let id = match shared.id {
+                Some(id) if !ids.contains(&id) => id,
+                _ => Uuid::new_v4(),
+            };
+            let server = to_server(shared.clone(), id, &req);
+            let errors = config::validate_server(&server);
+            if !errors.is_empty() {
+                let error = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+                entries.push(invalid_entry(Some(&shared), error.join("; ")));
+                continue;
+            }
+            ids.insert(id);
+            by_addr.insert(key, id);
+            entries.push(entry(&shared, ImportStatus::New, id));
+            added.push(server);
+        }
+
+        if !req.dry_run && !added.is_empty() {
+            config::save_servers(&state, &mut servers, &added, &[]).await?;
+        }
+    }
+
+    if !req.dry_run && !added.is_empty() {
+        for server in &added {
+            let _ = state.sse_tx.send(SseEvent::ServerUpdated {
+                server: Box::new(server.clone()),
+                was_listed: false,
+            });
+        }
+        broadcast_groups(&state).await;
+        tracing::info!("Imported {} server(s)", added.len());
+    }
+
+    let count = |status| entries.iter().filter(|e| e.status == status).count();
+    Ok(Json(ImportResponse {
+        dry_run: req.dry_run,
+        new: count(ImportStatus::New),
+        duplicates: count(ImportStatus::Duplicate),
+        invalid: count(ImportStatus::Invalid),
+        entries,
+    }))
+}
+
+fn to_server(shared: SharedServer, id: Uuid, req: &ImportRequest) -> Server {
+    let name = shared
+        .name
+        .filter(|n| !n.trim().is_empty())
+        .unwrap_or_else(|| format!("{}:{}", shared.host, shared.port));
+    Server {
+        id,
+        name,
+        host: shared.host,
+        port: shared.port,
+        password: shared.password,
+        method: shared.method,
+        plugin: shared.plugin,
+        plugin_opts: shared.plugin_opts,
+        identity_keys: shared.identity_keys,
+        users: Vec::new(),
+        enabled: true,
+        tags: req.tags.clone(),
+        visibility: req.visibility,
+        display_name: None,
+        group: req.group.clone(),
+        retention_days: None,
+        checks: CheckOverrides::default(),
+        source: None,
+        disabled_by_source: false,
+    }
+}
+
+fn entry(shared: &SharedServer, status: ImportStatus, server_id: Uuid) -> ImportEntry {
+    ImportEntry {
+        status,
+        name: shared.name.clone(),
+        host: Some(shared.host.clone()),
+        port: Some(shared.port),
+        method: Some(shared.method.clone()),
+        server_id: Some(server_id),
+        error: None,
+    }
+}
+
+fn invalid_entry(shared: Option<&SharedServer>, error: String) -> ImportEntry {
+    ImportEntry {
+        status: ImportStatus::Invalid,
+        name: shared.and_then(|s| s.name.clone()),
+        host: shared.map(|s| s.host.clone()),
+        port: shared.map(|s| s.port),
+        method: shared.map(|s| s.method.clone()),
+        server_id: None,
+        error: Some(error),
+    }
+}
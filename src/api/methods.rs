@@ -0,0 +1,8 @@
+use axum::Json;
+
+use crate::ciphers::{self, MethodInfo};
+
+/// GET /api/methods — encryption methods servers can use
+pub async fn list() -> Json<Vec<MethodInfo>> {
+    Json(ciphers::supported())
+}
@@ -4,10 +4,16 @@ use axum::Router;
 use crate::state::SharedState;
 
 pub mod auth;
+mod export;
+mod groups;
+mod import;
+mod methods;
 mod results;
 pub mod servers;
 mod settings;
 mod sse;
+mod stats;
+mod subscriptions;
 
 pub fn router(state: SharedState) -> Router {
     Router::new()
@@ -15,16 +21,30 @@ pub fn router(state: SharedState) -> Router {
         .route("/api/auth/logout", post(auth::logout))
         .route("/api/auth/status", get(auth::status))
         .route("/api/servers", get(servers::list).post(servers::create))
+        .route("/api/servers/import", post(import::import))
+        .route("/api/servers/export", get(export::export_servers))
         .route(
             "/api/servers/{id}",
             put(servers::update).delete(servers::delete),
         )
         .route("/api/servers/{id}/check", post(results::trigger_check))
+        .route("/api/servers/{id}/stats", get(stats::server_stats))
+        .route("/api/groups", get(groups::list))
+        .route("/api/methods", get(methods::list))
+        .route(
+            "/api/subscriptions",
+            get(subscriptions::list).put(subscriptions::update),
+        )
+        .route("/api/subscriptions/log", get(subscriptions::log))
+        .route("/api/subscriptions/{name}/sync", post(subscriptions::sync))
+        .route("/api/results/export", get(export::export_all))
         .route("/api/results/{id}", get(results::history))
+        .route("/api/results/{id}/export", get(export::export_server))
         .route(
             "/api/settings",
             get(settings::get_settings).put(settings::update_settings),
         )
+        .route("/api/scheduler", get(stats::scheduler_stats))
         .route("/api/events", get(sse::event_stream))
         .fallback(crate::frontend::static_handler)
         .with_state(state)
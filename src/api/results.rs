@@ -1,73 +1,124 @@
-use std::time::Duration;
-
 use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue};
 use axum::Json;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use uuid::Uuid;
 
-use crate::checker::check_server;
+use crate::api::auth::is_authed;
+use crate::api::servers::visible_server;
+use crate::db::ResultQuery;
 use crate::error::AppError;
-use crate::models::{CheckResult, SseEvent};
-use crate::state::{SharedState, MAX_HISTORY};
+use crate::models::{CheckResult, ResultFilter};
+use crate::state::{begin_check, queue_check, SharedState, MAX_HISTORY};
+
+const MAX_PAGE_SIZE: usize = 1000;
+/// Response header holding the cursor of the next (older) page
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
 
 #[derive(Debug, Deserialize)]
 pub struct HistoryQuery {
     pub limit: Option<usize>,
+    /// Inclusive RFC 3339 lower bound
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive RFC 3339 upper bound
+    pub to: Option<DateTime<Utc>>,
+    /// `X-Next-Cursor` from the previous page
+    pub cursor: Option<i64>,
+    pub filter: Option<ResultFilter>,
 }
 
+/// GET /api/results/:id?from=&to=&cursor=&limit=&filter=failures|ss_failures
+/// Results newest first; unless this is the last page, the `X-Next-Cursor`
+/// header holds the `cursor` for the next one
 pub async fn history(
     State(state): State<SharedState>,
+    headers: HeaderMap,
     Path(id): Path<Uuid>,
     Query(query): Query<HistoryQuery>,
-) -> Result<Json<Vec<CheckResult>>, AppError> {
-    {
-        let servers = state.servers.read().await;
-        if !servers.contains_key(&id) {
-            return Err(AppError::NotFound(id));
+) -> Result<(HeaderMap, Json<Vec<CheckResult>>), AppError> {
+    let authed = is_authed(&state, &headers).await;
+    visible_server(&state, id, authed).await?;
+
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from >= to {
+            return Err(AppError::BadRequest("from must be earlier than to".into()));
         }
     }
 
-    let results = state.results.read().await;
-    let history = results.get(&id).cloned().unwrap_or_default();
+    let limit = query.limit.unwrap_or(MAX_HISTORY).clamp(1, MAX_PAGE_SIZE);
+    let rows = state
+        .db
+        .query_results(&ResultQuery {
+            server_id: id,
+            from: query.from,
+            to: query.to,
+            before_id: query.cursor,
+            filter: query.filter,
+            limit,
+        })
+        .await?;
 
-    let limit = query.limit.unwrap_or(MAX_HISTORY);
-    let history: Vec<CheckResult> = history.into_iter().take(limit).collect();
+    let next_cursor = next_cursor(&rows, limit);
+    let public = state.public.read().await;
+    let results = rows
+        .into_iter()
+        .map(|(_, r)| if authed { r } else { r.public(&public) })
+        .collect();
 
-    Ok(Json(history))
+    let mut response_headers = HeaderMap::new();
+    if let Some(cursor) = next_cursor {
+        response_headers.insert(NEXT_CURSOR_HEADER, HeaderValue::from(cursor));
+    }
+    Ok((response_headers, Json(results)))
+}
+
+/// Cursor of the page after `rows`: the oldest row's id, unless the page
+/// came back short and so was the last one
+fn next_cursor<T>(rows: &[(i64, T)], limit: usize) -> Option<i64> {
+    if rows.len() == limit {
+        rows.last().map(|(row_id, _)| *row_id)
+    } else {
+        None
+    }
 }
 
 pub async fn trigger_check(
     State(state): State<SharedState>,
+    headers: HeaderMap,
     Path(id): Path<Uuid>,
 ) -> Result<Json<CheckResult>, AppError> {
-    let server = {
-        let servers = state.servers.read().await;
-        servers.get(&id).cloned().ok_or(AppError::NotFound(id))?
-    };
+    let authed = is_authed(&state, &headers).await;
+    let server = visible_server(&state, id, authed).await?;
 
-    let tcp_timeout = Duration::from_secs(state.tcp_timeout_secs);
-    let ss_timeout = Duration::from_secs(state.ss_timeout_secs);
-    let result = check_server(&server, tcp_timeout, ss_timeout, &state.test_target).await;
+    let Some(guard) = begin_check(&state, id) else {
+        return Err(AppError::Conflict(
+            "A check of this server is already running".into(),
+        ));
+    };
+    let result = queue_check(&state, server, guard)
+        .await
+        .map_err(|_| AppError::Internal(anyhow::anyhow!("Check cancelled by shutdown")))?;
 
-    // Save to SQLite
-    {
-        let db = state.db.lock().await;
-        if let Err(e) = crate::db::insert_result(&db, &result) {
-            tracing::error!("Failed to save result to db: {}", e);
-        }
+    if authed {
+        Ok(Json(result))
+    } else {
+        Ok(Json(result.public(&*state.public.read().await)))
     }
+}
 
-    // Update in-memory cache
-    {
-        let mut results_map = state.results.write().await;
-        let history = results_map.entry(id).or_default();
-        history.insert(0, result.clone());
-        history.truncate(MAX_HISTORY);
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let _ = state.sse_tx.send(SseEvent::CheckComplete {
-        result: result.clone(),
-    });
+    #[test]
+    fn next_cursor_is_oldest_row_of_a_full_page() {
+        assert_eq!(next_cursor(&[(9, ()), (7, ()), (4, ())], 3), Some(4));
+    }
 
-    Ok(Json(result))
+    #[test]
+    fn short_page_is_the_last() {
+        assert_eq!(next_cursor(&[(9, ()), (7, ())], 3), None);
+        assert_eq!(next_cursor::<()>(&[], 3), None);
+    }
 }
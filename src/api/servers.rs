@@ -4,17 +4,15 @@ use axum::response::IntoResponse;
 use axum::Json;
 use uuid::Uuid;
 
-use std::time::Duration;
-
-use crate::api::auth::{extract_token, require_auth};
-use crate::checker::check_server;
+use crate::api::auth::{is_authed, require_auth};
 use crate::config;
 use crate::error::AppError;
 use crate::models::{CreateServerRequest, PublicServerStatus, Server, SseEvent};
-use crate::state::{get_server_statuses, is_authenticated, SharedState, MAX_HISTORY};
+use crate::state::{begin_check, broadcast_groups, get_server_statuses, queue_check, SharedState};
 
 /// GET /api/servers
-/// Unauthenticated: returns PublicServerStatus (no host/port/password/method)
+/// Unauthenticated: returns PublicServerStatus for publicly listed servers only
+/// (no host/port/password/method, errors per `public.show_errors`)
 /// Authenticated: returns full ServerStatus
 pub async fn list(
     State(state): State<SharedState>,
@@ -22,19 +20,33 @@ pub async fn list(
 ) -> Result<impl IntoResponse, AppError> {
     let statuses = get_server_statuses(&state).await;
 
-    let authed = match extract_token(&headers) {
-        Some(token) => is_authenticated(&state, &token).await,
-        None => false,
-    };
-
-    if authed {
+    if is_authed(&state, &headers).await {
         Ok(Json(serde_json::to_value(&statuses).unwrap()).into_response())
     } else {
-        let public: Vec<PublicServerStatus> = statuses.iter().map(PublicServerStatus::from).collect();
+        let public_config = state.public.read().await;
+        let public: Vec<PublicServerStatus> = statuses
+            .iter()
+            .filter(|s| s.server.visibility.is_listed())
+            .map(|s| PublicServerStatus::new(s, &public_config))
+            .collect();
         Ok(Json(serde_json::to_value(&public).unwrap()).into_response())
     }
 }
 
+/// Look up a server the caller may see; hidden servers are reported as
+/// not found to anonymous users.
+pub async fn visible_server(
+    state: &SharedState,
+    id: Uuid,
+    authed: bool,
+) -> Result<Server, AppError> {
+    let servers = state.servers.read().await;
+    match servers.get(&id) {
+        Some(server) if authed || server.visibility.is_reachable() => Ok(server.clone()),
+        _ => Err(AppError::NotFound(id)),
+    }
+}
+
 /// POST /api/servers — requires auth
 pub async fn create(
     State(state): State<SharedState>,
@@ -43,47 +55,29 @@ pub async fn create(
 ) -> Result<Json<Server>, AppError> {
     require_auth(&state, &headers).await?;
 
-    if req.name.is_empty() || req.host.is_empty() || req.password.is_empty() {
-        return Err(AppError::BadRequest(
-            "name, host, and password are required".into(),
-        ));
-    }
-
     let server = req.into_server();
+    validate_server(&server)?;
     {
         let mut servers = state.servers.write().await;
-        servers.insert(server.id, server.clone());
+        config::save_servers(&state, &mut servers, std::slice::from_ref(&server), &[]).await?;
     }
 
     let _ = state.sse_tx.send(SseEvent::ServerUpdated {
-        server: server.clone(),
+        server: Box::new(server.clone()),
+        was_listed: false,
     });
-
-    if let Err(e) = config::persist(&state).await {
-        tracing::error!("Failed to persist config: {}", e);
-    }
+    broadcast_groups(&state).await;
 
     // Trigger immediate check in background
-    if server.enabled {
-        let state = state.clone();
-        let server = server.clone();
-        tokio::spawn(async move {
-            let tcp_timeout = Duration::from_secs(state.tcp_timeout_secs);
-            let ss_timeout = Duration::from_secs(state.ss_timeout_secs);
-            let result =
-                check_server(&server, tcp_timeout, ss_timeout, &state.test_target).await;
-            {
-                let db = state.db.lock().await;
-                let _ = crate::db::insert_result(&db, &result);
-            }
-            {
-                let mut results_map = state.results.write().await;
-                let history = results_map.entry(server.id).or_default();
-                history.insert(0, result.clone());
-                history.truncate(MAX_HISTORY);
-            }
-            let _ = state.sse_tx.send(SseEvent::CheckComplete { result });
-        });
+    // Skipped if the scheduler has already picked up the new server
+    let guard = if server.enabled {
+        begin_check(&state, server.id)
+    } else {
+        None
+    };
+    if let Some(guard) = guard {
+        // Not waited for; the scheduler stores and announces the result
+        drop(queue_check(&state, server.clone(), guard));
     }
 
     Ok(Json(server))
@@ -108,24 +102,47 @@ pub async fn update(
         port: req.port,
         password: req.password,
         method: req.method,
+        plugin: req.plugin,
+        plugin_opts: req.plugin_opts,
+        identity_keys: req.identity_keys,
+        users: req.users,
         enabled: req.enabled,
         tags: req.tags,
+        visibility: req.visibility,
+        display_name: req.display_name,
+        group: req.group,
+        retention_days: req.retention_days,
+        checks: req.checks,
+        source: existing.source.clone(),
+        // Enabling it by hand takes it back from the subscription
+        disabled_by_source: existing.disabled_by_source && !req.enabled,
     };
+    validate_server(&server)?;
 
-    servers.insert(id, server.clone());
+    let was_listed = existing.visibility.is_listed();
+    config::save_servers(&state, &mut servers, std::slice::from_ref(&server), &[]).await?;
     drop(servers);
 
     let _ = state.sse_tx.send(SseEvent::ServerUpdated {
-        server: server.clone(),
+        server: Box::new(server.clone()),
+        was_listed,
     });
-
-    if let Err(e) = config::persist(&state).await {
-        tracing::error!("Failed to persist config: {}", e);
-    }
+    broadcast_groups(&state).await;
 
     Ok(Json(server))
 }
 
+/// Collect every invalid field of a created or updated server, so the UI
+/// can mark them all at once
+fn validate_server(server: &Server) -> Result<(), AppError> {
+    let errors = config::validate_server(server);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Validation(errors))
+    }
+}
+
 /// DELETE /api/servers/:id — requires auth
 pub async fn delete(
     State(state): State<SharedState>,
@@ -134,23 +151,23 @@ pub async fn delete(
 ) -> Result<axum::http::StatusCode, AppError> {
     require_auth(&state, &headers).await?;
 
-    {
+    let removed = {
         let mut servers = state.servers.write().await;
-        if servers.remove(&id).is_none() {
-            return Err(AppError::NotFound(id));
-        }
-    }
+        let removed = servers.get(&id).cloned().ok_or(AppError::NotFound(id))?;
+        config::save_servers(&state, &mut servers, &[], &[id]).await?;
+        removed
+    };
 
     {
         let mut results = state.results.write().await;
         results.remove(&id);
     }
 
-    let _ = state.sse_tx.send(SseEvent::ServerRemoved { server_id: id });
-
-    if let Err(e) = config::persist(&state).await {
-        tracing::error!("Failed to persist config: {}", e);
-    }
+    let _ = state.sse_tx.send(SseEvent::ServerRemoved {
+        server_id: id,
+        was_listed: removed.visibility.is_listed(),
+    });
+    broadcast_groups(&state).await;
 
     Ok(axum::http::StatusCode::NO_CONTENT)
 }
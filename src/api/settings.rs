@@ -3,19 +3,43 @@ use axum::http::HeaderMap;
 use axum::Json;
 use serde::{Deserialize, Serialize};
 
+use crate::config::{validate_settings, MissedTicks, RetentionConfig};
 use crate::error::AppError;
-use crate::state::SharedState;
+use crate::models::CheckOverrides;
+use crate::state::{set_retention, SharedState};
 
 use super::auth::require_auth;
 
 #[derive(Debug, Serialize)]
 pub struct SettingsResponse {
     pub check_interval_secs: u64,
+    pub tcp_timeout_secs: u64,
+    pub ss_timeout_secs: u64,
+    pub test_targets: Vec<String>,
+    pub retries: u32,
+    pub max_concurrent_checks: usize,
+    pub jitter_pct: u32,
+    pub missed_ticks: MissedTicks,
+    pub retention: RetentionConfig,
 }
 
+/// Omitted fields are left unchanged; `retention` replaces the whole policy
 #[derive(Debug, Deserialize)]
 pub struct UpdateSettingsRequest {
     pub check_interval_secs: Option<u64>,
+    pub tcp_timeout_secs: Option<u64>,
+    pub ss_timeout_secs: Option<u64>,
+    #[serde(
+        default,
+        alias = "test_target",
+        deserialize_with = "crate::models::opt_one_or_many"
+    )]
+    pub test_targets: Option<Vec<String>>,
+    pub retries: Option<u32>,
+    pub max_concurrent_checks: Option<usize>,
+    pub jitter_pct: Option<u32>,
+    pub missed_ticks: Option<MissedTicks>,
+    pub retention: Option<RetentionConfig>,
 }
 
 pub async fn get_settings(
@@ -23,10 +47,7 @@ pub async fn get_settings(
     headers: HeaderMap,
 ) -> Result<Json<SettingsResponse>, AppError> {
     require_auth(&state, &headers).await?;
-    let interval = *state.check_interval_secs.read().await;
-    Ok(Json(SettingsResponse {
-        check_interval_secs: interval,
-    }))
+    Ok(Json(current_settings(&state).await))
 }
 
 pub async fn update_settings(
@@ -35,18 +56,34 @@ pub async fn update_settings(
     Json(req): Json<UpdateSettingsRequest>,
 ) -> Result<Json<SettingsResponse>, AppError> {
     require_auth(&state, &headers).await?;
+    validate(&req)?;
 
     if let Some(interval) = req.check_interval_secs {
-        if interval < 5 {
-            return Err(AppError::BadRequest(
-                "check_interval_secs must be >= 5".into(),
-            ));
-        }
-        {
-            let mut current = state.check_interval_secs.write().await;
-            *current = interval;
-        }
-        let _ = state.interval_tx.send(interval);
+        *state.check_interval_secs.write().await = interval;
+    }
+    if let Some(timeout) = req.tcp_timeout_secs {
+        *state.tcp_timeout_secs.write().await = timeout;
+    }
+    if let Some(timeout) = req.ss_timeout_secs {
+        *state.ss_timeout_secs.write().await = timeout;
+    }
+    if let Some(targets) = req.test_targets {
+        *state.test_targets.write().await = targets;
+    }
+    if let Some(retries) = req.retries {
+        *state.retries.write().await = retries;
+    }
+    if let Some(max) = req.max_concurrent_checks {
+        *state.max_concurrent_checks.write().await = max;
+    }
+    if let Some(jitter) = req.jitter_pct {
+        *state.jitter_pct.write().await = jitter;
+    }
+    if let Some(missed_ticks) = req.missed_ticks {
+        *state.missed_ticks.write().await = missed_ticks;
+    }
+    if let Some(retention) = req.retention {
+        set_retention(&state, retention).await;
     }
 
     // Persist to config file
@@ -54,8 +91,37 @@ pub async fn update_settings(
         tracing::error!("Failed to persist config: {}", e);
     }
 
-    let interval = *state.check_interval_secs.read().await;
-    Ok(Json(SettingsResponse {
-        check_interval_secs: interval,
-    }))
+    Ok(Json(current_settings(&state).await))
+}
+
+/// Check every field up front so a bad request changes nothing
+fn validate(req: &UpdateSettingsRequest) -> Result<(), AppError> {
+    let checks = CheckOverrides {
+        check_interval_secs: req.check_interval_secs,
+        tcp_timeout_secs: req.tcp_timeout_secs,
+        ss_timeout_secs: req.ss_timeout_secs,
+        test_targets: req.test_targets.clone(),
+        retries: req.retries,
+    };
+    validate_settings(&checks, req.max_concurrent_checks, req.jitter_pct)
+        .and_then(|()| {
+            req.retention
+                .as_ref()
+                .map_or(Ok(()), RetentionConfig::validate)
+        })
+        .map_err(|e| AppError::BadRequest(e.to_string()))
+}
+
+async fn current_settings(state: &SharedState) -> SettingsResponse {
+    SettingsResponse {
+        check_interval_secs: *state.check_interval_secs.read().await,
+        tcp_timeout_secs: *state.tcp_timeout_secs.read().await,
+        ss_timeout_secs: *state.ss_timeout_secs.read().await,
+        test_targets: state.test_targets.read().await.clone(),
+        retries: *state.retries.read().await,
+        max_concurrent_checks: *state.max_concurrent_checks.read().await,
+        jitter_pct: *state.jitter_pct.read().await,
+        missed_ticks: *state.missed_ticks.read().await,
+        retention: state.retention.read().await.clone(),
+    }
 }
@@ -5,20 +5,23 @@ use axum::response::sse::{Event, KeepAlive, Sse};
 use futures::stream::{self, Stream, StreamExt};
 use tokio_stream::wrappers::BroadcastStream;
 
-use crate::models::PublicSseEvent;
-use crate::state::{get_server_statuses, SharedState};
+use crate::models::{PublicServerStatus, PublicSseEvent, SseEvent, Visibility};
+use crate::state::{get_server_statuses, public_group_statuses, shutdown_requested, SharedState};
 
 pub async fn event_stream(
     State(state): State<SharedState>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     // Build initial snapshot — always public (no sensitive data)
     let statuses = get_server_statuses(&state).await;
+    let public = state.public.read().await.clone();
     let public_statuses = statuses
         .iter()
-        .map(crate::models::PublicServerStatus::from)
+        .filter(|s| s.server.visibility.is_listed())
+        .map(|s| PublicServerStatus::new(s, &public))
         .collect();
     let snapshot = PublicSseEvent::Snapshot {
         statuses: public_statuses,
+        groups: public_group_statuses(&state, &statuses).await,
     };
     let snapshot_event = Event::default()
         .json_data(&snapshot)
@@ -26,20 +29,43 @@ pub async fn event_stream(
 
     let initial = stream::once(async move { Ok::<_, Infallible>(snapshot_event) });
 
+    // Ends the stream on shutdown so graceful shutdown is not held up by
+    // clients that never disconnect
+    let shutdown = {
+        let state = state.clone();
+        async move { shutdown_requested(&state).await }
+    };
+
     // Subscribe to broadcast channel, convert to public events
     let rx = state.sse_tx.subscribe();
-    let live = BroadcastStream::new(rx).filter_map(|result| async {
-        match result {
-            Ok(event) => {
-                let public_event = PublicSseEvent::from(&event);
-                let sse_event = Event::default()
-                    .json_data(&public_event)
-                    .unwrap_or_else(|_| Event::default().data("{}"));
-                Some(Ok(sse_event))
-            }
-            Err(_) => None,
+    let live = BroadcastStream::new(rx).filter_map(move |result| {
+        let state = state.clone();
+        async move {
+            let event = result.ok()?;
+            let visibility = event_visibility(&state, &event).await;
+            let public = state.public.read().await.clone();
+            let public_event = PublicSseEvent::new(&event, visibility, &public)?;
+            let sse_event = Event::default()
+                .json_data(&public_event)
+                .unwrap_or_else(|_| Event::default().data("{}"));
+            Some(Ok(sse_event))
         }
     });
 
-    Sse::new(initial.chain(live)).keep_alive(KeepAlive::default())
+    Sse::new(initial.chain(live).take_until(shutdown)).keep_alive(KeepAlive::default())
+}
+
+/// Current visibility of the server an event refers to
+async fn event_visibility(state: &SharedState, event: &SseEvent) -> Visibility {
+    match event {
+        SseEvent::CheckComplete { result } => {
+            let servers = state.servers.read().await;
+            servers
+                .get(&result.server_id)
+                .map(|s| s.visibility)
+                .unwrap_or(Visibility::Hidden)
+        }
+        SseEvent::ServerUpdated { server, .. } => server.visibility,
+        _ => Visibility::Public,
+    }
 }
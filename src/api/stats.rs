@@ -0,0 +1,65 @@
+use axum::extract::{Path, Query, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::auth::{is_authed, require_auth};
+use crate::api::servers::visible_server;
+use crate::error::AppError;
+use crate::models::{ServerStats, StatsWindow};
+use crate::state::{SchedulerStats, SharedState};
+
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    /// One of 24h, 7d, 30d, 90d, 365d (default 24h)
+    pub window: Option<StatsWindow>,
+}
+
+/// GET /api/servers/:id/stats?window=30d
+pub async fn server_stats(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(id): Path<Uuid>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<ServerStats>, AppError> {
+    let authed = is_authed(&state, &headers).await;
+    visible_server(&state, id, authed).await?;
+
+    let window = query.window.unwrap_or(StatsWindow::Day);
+    let uptime = state.db.uptime_stats(id, window).await?;
+    let (tcp_latency, ss_latency) = state
+        .db
+        .latency_stats_since(id, Utc::now() - window.duration())
+        .await?;
+
+    Ok(Json(ServerStats {
+        uptime,
+        tcp_latency,
+        ss_latency,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchedulerStatsResponse {
+    #[serde(flatten)]
+    pub stats: SchedulerStats,
+    /// Checks running right now, scheduled or manual
+    pub in_flight: usize,
+}
+
+/// GET /api/scheduler — requires auth
+pub async fn scheduler_stats(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Json<SchedulerStatsResponse>, AppError> {
+    require_auth(&state, &headers).await?;
+    let stats = state.scheduler_stats.read().await.clone();
+    let in_flight = state
+        .in_flight
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .len();
+    Ok(Json(SchedulerStatsResponse { stats, in_flight }))
+}
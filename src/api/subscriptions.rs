@@ -0,0 +1,119 @@
+use axum::extract::{Path, Query, State};
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, validate_subscriptions, SubscriptionConfig};
+use crate::error::AppError;
+use crate::models::SyncLogEntry;
+use crate::state::SharedState;
+use crate::subscriptions::sync_subscription;
+
+use super::auth::require_auth;
+
+/// Default and maximum number of sync log entries returned
+const DEFAULT_LOG_LIMIT: usize = 50;
+const MAX_LOG_LIMIT: usize = 1000;
+
+#[derive(Debug, Serialize)]
+pub struct SubscriptionStatus {
+    #[serde(flatten)]
+    pub config: SubscriptionConfig,
+    /// Servers whose `source` is this subscription
+    pub servers: usize,
+    pub last_sync: Option<SyncLogEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogQuery {
+    pub subscription: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// GET /api/subscriptions — requires auth, since feed URLs usually embed a token
+pub async fn list(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<SubscriptionStatus>>, AppError> {
+    require_auth(&state, &headers).await?;
+    Ok(Json(statuses(&state).await?))
+}
+
+/// PUT /api/subscriptions — replaces the whole list. Servers of a removed
+/// subscription are kept and no longer synced.
+pub async fn update(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(subscriptions): Json<Vec<SubscriptionConfig>>,
+) -> Result<Json<Vec<SubscriptionStatus>>, AppError> {
+    require_auth(&state, &headers).await?;
+    validate_subscriptions(&subscriptions).map_err(|e| AppError::BadRequest(e.to_string()))?;
+
+    *state.subscriptions.write().await = subscriptions;
+    if let Err(e) = config::persist(&state).await {
+        tracing::error!("Failed to persist config: {}", e);
+    }
+    Ok(Json(statuses(&state).await?))
+}
+
+/// POST /api/subscriptions/:name/sync — sync now, even if disabled
+pub async fn sync(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Json<SyncLogEntry>, AppError> {
+    require_auth(&state, &headers).await?;
+    let sub = state
+        .subscriptions
+        .read()
+        .await
+        .iter()
+        .find(|s| s.name == name)
+        .cloned()
+        .ok_or(AppError::SubscriptionNotFound(name))?;
+    Ok(Json(sync_subscription(&state, &sub).await))
+}
+
+/// GET /api/subscriptions/log?subscription=&limit= — newest first
+pub async fn log(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(query): Query<LogQuery>,
+) -> Result<Json<Vec<SyncLogEntry>>, AppError> {
+    require_auth(&state, &headers).await?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LOG_LIMIT)
+        .clamp(1, MAX_LOG_LIMIT);
+    let entries = state
+        .db
+        .load_sync_log(query.subscription.as_deref(), limit)
+        .await?;
+    Ok(Json(entries))
+}
+
+async fn statuses(state: &SharedState) -> Result<Vec<SubscriptionStatus>, AppError> {
+    let subscriptions = state.subscriptions.read().await.clone();
+    let mut statuses = Vec::with_capacity(subscriptions.len());
+    for sub in subscriptions {
+        let servers = state
+            .servers
+            .read()
+            .await
+            .values()
+            .filter(|s| s.source.as_deref() == Some(sub.name.as_str()))
+            .count();
+        let last_sync = state
+            .db
+            .load_sync_log(Some(&sub.name), 1)
+            .await?
+            .into_iter()
+            .next();
+        statuses.push(SubscriptionStatus {
+            config: sub,
+            servers,
+            last_sync,
+        });
+    }
+    Ok(statuses)
+}
@@ -1,22 +1,43 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::time::{Duration, Instant};
 
 use chrono::Utc;
-use shadowsocks::config::{ServerConfig as SsServerConfig, ServerType};
-use shadowsocks::context::Context;
+use futures::future::{join, join_all};
+use shadowsocks::config::{Mode, ServerConfig as SsServerConfig, ServerType};
+use shadowsocks::context::{Context, SharedContext};
 use shadowsocks::crypto::CipherKind;
+use shadowsocks::plugin::{Plugin, PluginConfig, PluginMode};
 use shadowsocks::relay::tcprelay::proxy_stream::ProxyClientStream;
 use shadowsocks::ServerAddr;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 
-use crate::models::{CheckResult, Server, SsCheckResult, TcpCheckResult};
+use crate::models::{
+    CheckResult, Server, SsCheckResult, SsErrorKind, TcpCheckResult, UserCheckResult,
+};
+
+/// Longest wait for a SIP003 plugin to start listening
+const PLUGIN_START_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pause between probes of a starting plugin's local port
+const PLUGIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A test target did not answer in time; reported as `SsErrorKind::Timeout`
+#[derive(Debug, thiserror::Error)]
+#[error("SS protocol check timed out")]
+struct TimedOut;
+
+/// The plugin could not be run; reported as `SsErrorKind::Plugin`
+#[derive(Debug, thiserror::Error)]
+#[error("Plugin {plugin}: {reason}")]
+struct PluginError {
+    plugin: String,
+    reason: String,
+}
 
 pub async fn tcp_check(host: &str, port: u16, timeout: Duration) -> TcpCheckResult {
     let start = Instant::now();
-    let addr = format!("{}:{}", host, port);
 
-    match tokio::time::timeout(timeout, TcpStream::connect(&addr)).await {
+    match tokio::time::timeout(timeout, TcpStream::connect((host, port))).await {
         Ok(Ok(_stream)) => TcpCheckResult {
             reachable: true,
             latency_ms: Some(start.elapsed().as_secs_f64() * 1000.0),
@@ -37,128 +58,269 @@ pub async fn tcp_check(host: &str, port: u16, timeout: Duration) -> TcpCheckResu
 
 /// Perform a real Shadowsocks protocol connectivity check.
 /// Connects to the SS server with the given password/method,
-/// then tries to relay an HTTP request to test_url through it.
+/// then tries to relay an HTTP request through it to each test target in
+/// turn until one answers. Each target gets `timeout` (the first one less
+/// the setup) and the latency is that of the one that answered.
+/// With a SIP003 plugin, the plugin is started for the duration of the
+/// check and the connection goes through it.
 pub async fn ss_protocol_check(
     host: &str,
     port: u16,
     password: &str,
     method_str: &str,
-    test_target: &str,
+    plugin: Option<&PluginConfig>,
+    test_targets: &[String],
     timeout: Duration,
 ) -> SsCheckResult {
-    let start = Instant::now();
+    // Restarted for each fallback target
+    let mut start = Instant::now();
+    let total = timeout * test_targets.len().max(1) as u32;
 
-    let result = tokio::time::timeout(timeout, async {
+    let result = tokio::time::timeout(total, async {
         // Parse cipher method
         let method: CipherKind = method_str
             .parse()
             .map_err(|_| anyhow::anyhow!("Unknown cipher method: {}", method_str))?;
 
         // Build SS server config
-        let server_addr = format!("{}:{}", host, port);
-        let addr: SocketAddr = tokio::net::lookup_host(&server_addr)
+        let addr: SocketAddr = tokio::net::lookup_host((host, port))
             .await?
             .next()
-            .ok_or_else(|| anyhow::anyhow!("DNS resolution failed for {}", server_addr))?;
+            .ok_or_else(|| anyhow::anyhow!("DNS resolution failed for {}:{}", host, port))?;
 
-        let ss_config = SsServerConfig::new(
-            ServerAddr::SocketAddr(addr),
-            password,
-            method,
-        )?;
+        let mut ss_config = SsServerConfig::new(ServerAddr::SocketAddr(addr), password, method)?;
 
-        let context = Context::new_shared(ServerType::Local);
+        // Kept alive until the check is over; dropping it stops the process
+        let _running = match plugin {
+            Some(plugin) => {
+                // Plugins that do TLS need the host name, not the address
+                let remote = match host.parse::<IpAddr>() {
+                    Ok(_) => ServerAddr::SocketAddr(addr),
+                    Err(_) => ServerAddr::DomainName(host.to_string(), port),
+                };
+                let running = start_plugin(plugin, &remote, timeout).await?;
+                ss_config.set_plugin(plugin.clone());
+                ss_config.set_plugin_addr(ServerAddr::SocketAddr(running.local_addr()));
+                Some(running)
+            }
+            None => None,
+        };
 
-        // Connect through the SS server to a test target (e.g. www.gstatic.com:80)
-        // This validates the full SS handshake + encryption works
-        let target_addr = shadowsocks::relay::Address::DomainNameAddress(
-            test_target.to_string(),
-            80,
-        );
-
-        let mut stream = ProxyClientStream::connect(
-            context,
-            &ss_config,
-            target_addr,
-        )
-        .await?;
-
-        // Send a minimal HTTP request through the tunnel
-        let http_req = format!(
-            "GET /generate_204 HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
-            test_target
-        );
-        stream.write_all(http_req.as_bytes()).await?;
-
-        // Read the response (just need to get something back)
-        let mut buf = [0u8; 512];
-        let n = stream.read(&mut buf).await?;
-        if n == 0 {
-            return Err(anyhow::anyhow!("Empty response from test target"));
-        }
+        let context = Context::new_shared(ServerType::Local);
 
-        // Check we got a valid HTTP response
-        let response = String::from_utf8_lossy(&buf[..n]);
-        if !response.starts_with("HTTP/") {
-            return Err(anyhow::anyhow!("Invalid HTTP response"));
+        let mut last_error = anyhow::anyhow!("No test target");
+        for (i, target) in test_targets.iter().enumerate() {
+            if i > 0 {
+                start = Instant::now();
+            }
+            let left = timeout.saturating_sub(start.elapsed());
+            match tokio::time::timeout(left, relay_http(context.clone(), &ss_config, target)).await
+            {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(e)) => last_error = e,
+                Err(_) => last_error = TimedOut.into(),
+            }
         }
-
-        Ok::<_, anyhow::Error>(())
+        Err(last_error)
     })
     .await;
 
     match result {
+        Ok(Err(e)) if e.is::<TimedOut>() => SsCheckResult {
+            success: false,
+            latency_ms: None,
+            error: Some(e.to_string()),
+            error_kind: Some(SsErrorKind::Timeout),
+        },
         Ok(Ok(())) => SsCheckResult {
             success: true,
             latency_ms: Some(start.elapsed().as_secs_f64() * 1000.0),
             error: None,
+            error_kind: None,
         },
         Ok(Err(e)) => SsCheckResult {
             success: false,
             latency_ms: Some(start.elapsed().as_secs_f64() * 1000.0),
             error: Some(e.to_string()),
+            error_kind: Some(if e.is::<PluginError>() {
+                SsErrorKind::Plugin
+            } else {
+                SsErrorKind::Protocol
+            }),
         },
         Err(_) => SsCheckResult {
             success: false,
             latency_ms: None,
             error: Some("SS protocol check timed out".into()),
+            error_kind: Some(SsErrorKind::Timeout),
         },
     }
 }
 
-pub async fn check_server(
-    server: &Server,
-    tcp_timeout: Duration,
-    ss_timeout: Duration,
-    test_target: &str,
-) -> CheckResult {
-    let tcp = tcp_check(&server.host, server.port, tcp_timeout).await;
-
-    // Only do SS protocol check if TCP is reachable
-    let ss = if tcp.reachable {
-        Some(
-            ss_protocol_check(
-                &server.host,
-                server.port,
-                &server.password,
-                &server.method,
-                test_target,
-                ss_timeout,
-            )
-            .await,
+/// Connect through the SS server to `target` port 80 and fetch a page
+/// there, which proves the full SS handshake and encryption work
+async fn relay_http(
+    context: SharedContext,
+    ss_config: &SsServerConfig,
+    target: &str,
+) -> anyhow::Result<()> {
+    let target_addr = shadowsocks::relay::Address::DomainNameAddress(target.to_string(), 80);
+    let mut stream = ProxyClientStream::connect(context, ss_config, target_addr).await?;
+
+    // Send a minimal HTTP request through the tunnel
+    let http_req = format!(
+        "GET /generate_204 HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        target
+    );
+    stream.write_all(http_req.as_bytes()).await?;
+
+    // Read the response (just need to get something back)
+    let mut buf = [0u8; 512];
+    let n = stream.read(&mut buf).await?;
+    if n == 0 {
+        return Err(anyhow::anyhow!("Empty response from test target"));
+    }
+
+    // Check we got a valid HTTP response
+    let response = String::from_utf8_lossy(&buf[..n]);
+    if !response.starts_with("HTTP/") {
+        return Err(anyhow::anyhow!("Invalid HTTP response"));
+    }
+    Ok(())
+}
+
+/// Spawn a client-side plugin towards `remote` and wait until it accepts
+/// connections on its local port
+async fn start_plugin(
+    config: &PluginConfig,
+    remote: &ServerAddr,
+    timeout: Duration,
+) -> Result<Plugin, PluginError> {
+    let error = |reason: String| PluginError {
+        plugin: config.plugin.clone(),
+        reason,
+    };
+    let plugin = Plugin::start(config, remote, PluginMode::Client)
+        .map_err(|e| error(format!("failed to start: {}", e)))?;
+
+    // Leave the check itself at least half of its time
+    let deadline = Instant::now() + PLUGIN_START_TIMEOUT.min(timeout / 2);
+    while TcpStream::connect(plugin.local_addr()).await.is_err() {
+        if Instant::now() >= deadline {
+            return Err(error("not listening after start".into()));
+        }
+        tokio::time::sleep(PLUGIN_POLL_INTERVAL).await;
+    }
+    Ok(plugin)
+}
+
+/// SIP003 client plugin for a server, if it uses one
+fn plugin_config(server: &Server) -> Option<PluginConfig> {
+    let plugin = server.plugin.as_deref().filter(|p| !p.is_empty())?;
+    Some(PluginConfig {
+        plugin: plugin.to_string(),
+        plugin_opts: server.plugin_opts.clone().filter(|o| !o.is_empty()),
+        plugin_args: Vec::new(),
+        plugin_mode: Mode::TcpOnly,
+    })
+}
+
+/// Pause between a failed attempt and its retry
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Effective settings for checking one server, overrides already applied
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    pub tcp_timeout: Duration,
+    pub ss_timeout: Duration,
+    pub test_targets: Vec<String>,
+    pub retries: u32,
+}
+
+impl CheckOptions {
+    /// Longest `check_server` can take with these options: every attempt
+    /// running into the TCP timeout and the SS timeout of every target, with
+    /// the pause before each retry
+    pub fn max_duration(&self) -> Duration {
+        let ss = self.ss_timeout * self.test_targets.len().max(1) as u32;
+        (self.tcp_timeout + ss) * (self.retries + 1) + RETRY_DELAY * self.retries
+    }
+}
+
+/// Check a server, retrying up to `opts.retries` times while it (or one of
+/// its users) fails. Only the last attempt is returned.
+pub async fn check_server(server: &Server, opts: &CheckOptions) -> CheckResult {
+    let mut result = check_once(server, opts).await;
+    for _ in 0..opts.retries {
+        if result.is_fully_up() {
+            break;
+        }
+        tokio::time::sleep(RETRY_DELAY).await;
+        result = check_once(server, opts).await;
+    }
+    result
+}
+
+async fn check_once<'a>(server: &'a Server, opts: &CheckOptions) -> CheckResult {
+    let tcp = tcp_check(&server.host, server.port, opts.tcp_timeout).await;
+
+    // Only do SS protocol checks if TCP is reachable; each user (or other
+    // credential) of the server gets its own check, all run side by side
+    // on the one TCP check
+    let (ss, user_checks) = if tcp.reachable {
+        let plugin = plugin_config(server);
+        let check_key = |password: String, method: &'a str| {
+            let plugin = plugin.clone();
+            async move {
+                ss_protocol_check(
+                    &server.host,
+                    server.port,
+                    &password,
+                    method,
+                    plugin.as_ref(),
+                    &opts.test_targets,
+                    opts.ss_timeout,
+                )
+                .await
+            }
+        };
+        let users = server.users.iter().map(|user| {
+            let check = check_key(server.user_password(user), server.user_method(user));
+            async move {
+                UserCheckResult {
+                    user: user.name.clone(),
+                    ss_check: check.await,
+                }
+            }
+        });
+        join(
+            check_key(server.client_password(&server.password), &server.method),
+            join_all(users),
         )
+        .await
     } else {
-        Some(SsCheckResult {
+        let skipped = SsCheckResult {
             success: false,
             latency_ms: None,
             error: Some("Skipped: TCP unreachable".into()),
-        })
+            error_kind: Some(SsErrorKind::Skipped),
+        };
+        let users = server
+            .users
+            .iter()
+            .map(|user| UserCheckResult {
+                user: user.name.clone(),
+                ss_check: skipped.clone(),
+            })
+            .collect();
+        (skipped, users)
     };
 
     CheckResult {
         server_id: server.id,
         timestamp: Utc::now(),
         tcp_check: tcp,
-        ss_check: ss,
+        ss_check: Some(ss),
+        user_checks,
     }
 }
@@ -0,0 +1,109 @@
+//! Encryption methods the SS check can use, and the key rules of the
+//! Shadowsocks 2022 ones.
+
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::{alphabet, Engine};
+use serde::Serialize;
+use shadowsocks::config::method_support_eih;
+use shadowsocks::crypto::{CipherCategory, CipherKind};
+
+/// Decodes 2022 keys the way shadowsocks does: standard alphabet, padding optional
+const KEY_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Methods offered in the UI, most common first. `none` and `table` are left
+/// out on purpose: they do not encrypt. `parse` still takes them, and every
+/// other method the shadowsocks crate knows.
+const METHODS: &[&str] = &[
+    "aes-128-gcm",
+    "aes-256-gcm",
+    "chacha20-ietf-poly1305",
+    "2022-blake3-aes-128-gcm",
+    "2022-blake3-aes-256-gcm",
+    "2022-blake3-chacha20-poly1305",
+    "aes-128-cfb",
+    "aes-192-cfb",
+    "aes-256-cfb",
+    "aes-128-ctr",
+    "aes-192-ctr",
+    "aes-256-ctr",
+    "camellia-128-cfb",
+    "camellia-192-cfb",
+    "camellia-256-cfb",
+    "chacha20-ietf",
+    "rc4-md5",
+];
+
+/// One entry of `GET /api/methods`
+#[derive(Debug, Clone, Serialize)]
+pub struct MethodInfo {
+    pub name: &'static str,
+    /// `aead`, `aead-2022` or `stream`
+    pub category: &'static str,
+    /// Bytes a 2022 key decodes to; other methods take any password
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_len: Option<usize>,
+    /// Whether servers using it can have identity keys and users
+    pub identity_keys: bool,
+}
+
+/// Methods this build supports, in display order
+pub fn supported() -> Vec<MethodInfo> {
+    METHODS
+        .iter()
+        .filter_map(|&name| {
+            let kind: CipherKind = name.parse().ok()?;
+            let category = match kind.category() {
+                CipherCategory::Aead => "aead",
+                CipherCategory::Aead2022 => "aead-2022",
+                CipherCategory::Stream => "stream",
+                CipherCategory::None => return None,
+            };
+            Some(MethodInfo {
+                name,
+                category,
+                key_len: kind.is_aead_2022().then(|| kind.key_len()),
+                identity_keys: method_support_eih(kind),
+            })
+        })
+        .collect()
+}
+
+/// Parse a method name; any method the shadowsocks crate supports is fine
+pub fn parse(method: &str) -> Result<CipherKind, String> {
+    if method.is_empty() {
+        return Err("is required".into());
+    }
+    method
+        .parse::<CipherKind>()
+        .map_err(|_| format!("unsupported encryption method {:?}", method))
+}
+
+/// Check a password, user key or identity key for `kind`: 2022 methods take
+/// base64 of exactly the method's key length, `none` anything, others any
+/// non-empty string
+pub fn check_key(kind: CipherKind, key: &str) -> Result<(), String> {
+    if kind.category() == CipherCategory::None {
+        return Ok(());
+    }
+    if key.is_empty() {
+        return Err("must not be empty".into());
+    }
+    if !kind.is_aead_2022() {
+        return Ok(());
+    }
+    let bytes = KEY_BASE64
+        .decode(key)
+        .map_err(|_| format!("must be base64 for {}", kind))?;
+    if bytes.len() != kind.key_len() {
+        return Err(format!(
+            "must decode to {} bytes for {}, got {}",
+            kind.key_len(),
+            kind,
+            bytes.len()
+        ));
+    }
+    Ok(())
+}
@@ -1,29 +1,97 @@
 use anyhow::Result;
+use chrono::NaiveTime;
 use serde::{Deserialize, Serialize};
+use shadowsocks::config::method_support_eih;
+use std::collections::HashMap;
 use std::path::Path;
 use uuid::Uuid;
 
+use crate::ciphers;
+use crate::db;
+use crate::models::{CheckOverrides, FieldError, Server, ServerUser, Visibility};
 use crate::state::AppState;
 
+/// Upper bound on `retries`, so a dead server cannot stall its check slot
+pub const MAX_RETRIES: u32 = 5;
+/// Upper bound on `test_targets`; each one tried adds an SS timeout to a check
+pub const MAX_TEST_TARGETS: usize = 5;
+/// Upper bound on `jitter_pct`
+pub const MAX_JITTER_PCT: u32 = 50;
+/// Lower bound on a subscription's `interval_secs`, to go easy on providers
+pub const MIN_SUBSCRIPTION_INTERVAL: u64 = 60;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     #[serde(default = "default_listen")]
     pub listen: String,
     #[serde(default)]
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub public: PublicConfig,
     #[serde(default = "default_check_interval")]
     pub check_interval_secs: u64,
     #[serde(default = "default_tcp_timeout")]
     pub tcp_timeout_secs: u64,
     #[serde(default = "default_ss_timeout")]
     pub ss_timeout_secs: u64,
-    #[serde(default = "default_test_target")]
-    pub test_target: String,
+    /// Hosts the SS check fetches through the server, tried in order until
+    /// one answers
+    #[serde(
+        default = "default_test_targets",
+        alias = "test_target",
+        deserialize_with = "crate::models::one_or_many"
+    )]
+    pub test_targets: Vec<String>,
+    /// Extra attempts after a failed check before the failure is recorded
+    #[serde(default)]
+    pub retries: u32,
+    /// Upper bound on servers checked at the same time
+    #[serde(default = "default_max_concurrent_checks")]
+    pub max_concurrent_checks: usize,
+    /// Random spread applied to each server's interval, in percent
+    #[serde(default = "default_jitter_pct")]
+    pub jitter_pct: u32,
+    #[serde(default)]
+    pub missed_ticks: MissedTicks,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub groups: Vec<GroupConfig>,
+    #[serde(default)]
+    pub subscriptions: Vec<SubscriptionConfig>,
+    #[serde(default)]
+    pub server_store: ServerStore,
     #[serde(default)]
     pub servers: Vec<ServerConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Where servers and runtime settings are kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerStore {
+    /// In this YAML file, rewritten on every change made through the API
+    #[default]
+    Config,
+    /// In SQLite; `servers` here are imported once into an empty database,
+    /// after which the YAML file is never written to
+    Database,
+}
+
+/// What to do with checks that could not start on time because the previous
+/// check of the same server was still running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissedTicks {
+    /// Check once as soon as possible, then a full interval after that
+    #[default]
+    Delay,
+    /// Check once as soon as possible, then stay on the original schedule
+    Skip,
+    /// Run every missed check back to back until caught up
+    Burst,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AuthConfig {
     #[serde(default = "default_username")]
     pub username: String,
@@ -40,6 +108,107 @@ impl Default for AuthConfig {
     }
 }
 
+/// What the public (unauthenticated) views expose
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublicConfig {
+    /// Include raw TCP/SS error strings in public check results
+    #[serde(default)]
+    pub show_errors: bool,
+    /// Include server tags in public server info
+    #[serde(default = "default_show_tags")]
+    pub show_tags: bool,
+}
+
+impl Default for PublicConfig {
+    fn default() -> Self {
+        Self {
+            show_errors: false,
+            show_tags: default_show_tags(),
+        }
+    }
+}
+
+/// How long each tier of check history is kept, in days
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Individual check results
+    #[serde(default = "default_raw_days")]
+    pub raw_days: u32,
+    /// Hourly rollups (also back long-window uptime stats)
+    #[serde(default = "default_hourly_days")]
+    pub hourly_days: u32,
+    /// Daily rollups (back uptime stats older than the hourly rollups)
+    #[serde(default = "default_daily_days")]
+    pub daily_days: u32,
+    /// Cap on raw check results kept per server, newest first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rows_per_server: Option<u32>,
+    /// Time of day (UTC, "HH:MM") at which the daily cleanup runs
+    #[serde(default = "default_cleanup_at")]
+    pub cleanup_at: String,
+}
+
+impl RetentionConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.raw_days == 0 {
+            anyhow::bail!("retention.raw_days must be at least 1");
+        }
+        if self.max_rows_per_server == Some(0) {
+            anyhow::bail!("retention.max_rows_per_server must be at least 1");
+        }
+        self.cleanup_time()?;
+        Ok(())
+    }
+
+    pub fn cleanup_time(&self) -> Result<NaiveTime> {
+        NaiveTime::parse_from_str(&self.cleanup_at, "%H:%M").map_err(|_| {
+            anyhow::anyhow!(
+                "retention.cleanup_at must be HH:MM, got {:?}",
+                self.cleanup_at
+            )
+        })
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            raw_days: default_raw_days(),
+            hourly_days: default_hourly_days(),
+            daily_days: default_daily_days(),
+            max_rows_per_server: None,
+            cleanup_at: default_cleanup_at(),
+        }
+    }
+}
+
+/// Status-page group; servers join it by setting `group` to its name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupConfig {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Sort key on the status page (ascending)
+    #[serde(default)]
+    pub order: i32,
+}
+
+/// Provider feed of servers (SIP008 JSON or base64 `ss://` list) that is
+/// fetched on a schedule and mirrored into the server list
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubscriptionConfig {
+    /// Unique; set as `source` on the servers it manages
+    pub name: String,
+    pub url: String,
+    #[serde(default = "default_subscription_interval")]
+    pub interval_secs: u64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Group given to servers this subscription creates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     #[serde(default = "Uuid::new_v4")]
@@ -50,10 +219,58 @@ pub struct ServerConfig {
     pub password: String,
     #[serde(default = "default_method")]
     pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugin_opts: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub identity_keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<ServerUser>,
     #[serde(default = "default_enabled")]
     pub enabled: bool,
     #[serde(default)]
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_days: Option<u32>,
+    #[serde(flatten)]
+    pub checks: CheckOverrides,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled_by_source: bool,
+}
+
+impl ServerConfig {
+    pub fn to_server(&self) -> Server {
+        Server {
+            id: self.id,
+            name: self.name.clone(),
+            host: self.host.clone(),
+            port: self.port,
+            password: self.password.clone(),
+            method: self.method.clone(),
+            plugin: self.plugin.clone(),
+            plugin_opts: self.plugin_opts.clone(),
+            identity_keys: self.identity_keys.clone(),
+            users: self.users.clone(),
+            enabled: self.enabled,
+            tags: self.tags.clone(),
+            visibility: self.visibility,
+            display_name: self.display_name.clone(),
+            group: self.group.clone(),
+            retention_days: self.retention_days,
+            checks: self.checks.clone(),
+            source: self.source.clone(),
+            disabled_by_source: self.disabled_by_source,
+        }
+    }
 }
 
 fn default_listen() -> String {
@@ -65,6 +282,9 @@ fn default_username() -> String {
 fn default_password() -> String {
     "admin".to_string()
 }
+fn default_show_tags() -> bool {
+    true
+}
 fn default_check_interval() -> u64 {
     60
 }
@@ -74,8 +294,29 @@ fn default_tcp_timeout() -> u64 {
 fn default_ss_timeout() -> u64 {
     10
 }
-fn default_test_target() -> String {
-    "www.gstatic.com".to_string()
+fn default_test_targets() -> Vec<String> {
+    vec!["www.gstatic.com".to_string()]
+}
+fn default_max_concurrent_checks() -> usize {
+    32
+}
+fn default_jitter_pct() -> u32 {
+    10
+}
+fn default_subscription_interval() -> u64 {
+    3600
+}
+fn default_raw_days() -> u32 {
+    7
+}
+fn default_hourly_days() -> u32 {
+    90
+}
+fn default_daily_days() -> u32 {
+    365
+}
+fn default_cleanup_at() -> String {
+    "04:00".to_string()
 }
 fn default_method() -> String {
     "aes-256-gcm".to_string()
@@ -89,10 +330,19 @@ impl Default for AppConfig {
         Self {
             listen: default_listen(),
             auth: AuthConfig::default(),
+            public: PublicConfig::default(),
             check_interval_secs: default_check_interval(),
             tcp_timeout_secs: default_tcp_timeout(),
             ss_timeout_secs: default_ss_timeout(),
-            test_target: default_test_target(),
+            test_targets: default_test_targets(),
+            retries: 0,
+            max_concurrent_checks: default_max_concurrent_checks(),
+            jitter_pct: default_jitter_pct(),
+            missed_ticks: MissedTicks::default(),
+            retention: RetentionConfig::default(),
+            groups: Vec::new(),
+            subscriptions: Vec::new(),
+            server_store: ServerStore::default(),
             servers: Vec::new(),
         }
     }
@@ -103,17 +353,326 @@ pub fn load(path: &str) -> Result<AppConfig> {
         tracing::warn!("Config file {} not found, using defaults", path);
         return Ok(AppConfig::default());
     }
-    let file = std::fs::File::open(path)?;
-    let config: AppConfig = serde_yaml_ng::from_reader(file)?;
+    read(path)
+}
+
+fn read(path: &str) -> Result<AppConfig> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+/// Parse and validate config file contents
+pub fn parse(yaml: &str) -> Result<AppConfig> {
+    let config: AppConfig = serde_yaml_ng::from_str(yaml)?;
+    validate(&config)?;
     Ok(config)
 }
 
+fn validate(config: &AppConfig) -> Result<()> {
+    let checks = CheckOverrides {
+        check_interval_secs: Some(config.check_interval_secs),
+        tcp_timeout_secs: Some(config.tcp_timeout_secs),
+        ss_timeout_secs: Some(config.ss_timeout_secs),
+        test_targets: Some(config.test_targets.clone()),
+        retries: Some(config.retries),
+    };
+    validate_settings(
+        &checks,
+        Some(config.max_concurrent_checks),
+        Some(config.jitter_pct),
+    )?;
+    let mut ids = std::collections::HashSet::new();
+    if let Some(dup) = config.servers.iter().find(|s| !ids.insert(s.id)) {
+        anyhow::bail!("duplicate server id {}", dup.id);
+    }
+    for s in &config.servers {
+        if let Some(e) = validate_server(&s.to_server()).first() {
+            anyhow::bail!("server {}: {}", s.name, e);
+        }
+    }
+    validate_subscriptions(&config.subscriptions)?;
+    config.retention.validate()
+}
+
+pub fn validate_subscriptions(subscriptions: &[SubscriptionConfig]) -> Result<()> {
+    let mut names = std::collections::HashSet::new();
+    for sub in subscriptions {
+        if sub.name.trim().is_empty() {
+            anyhow::bail!("subscription name must not be empty");
+        }
+        if !names.insert(sub.name.as_str()) {
+            anyhow::bail!("duplicate subscription name {}", sub.name);
+        }
+        if !(sub.url.starts_with("http://") || sub.url.starts_with("https://")) {
+            anyhow::bail!("subscription {}: url must be http:// or https://", sub.name);
+        }
+        if sub.interval_secs < MIN_SUBSCRIPTION_INTERVAL {
+            anyhow::bail!(
+                "subscription {}: interval_secs must be at least {}",
+                sub.name,
+                MIN_SUBSCRIPTION_INTERVAL
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Limits on the runtime settings, shared by the config file and
+/// `PUT /api/settings`; fields left unset are not checked
+pub fn validate_settings(
+    checks: &CheckOverrides,
+    max_concurrent_checks: Option<usize>,
+    jitter_pct: Option<u32>,
+) -> Result<()> {
+    if let Some(e) = validate_check_overrides(checks).first() {
+        anyhow::bail!("{}", e);
+    }
+    if max_concurrent_checks == Some(0) {
+        anyhow::bail!("max_concurrent_checks must be at least 1");
+    }
+    if jitter_pct.is_some_and(|j| j > MAX_JITTER_PCT) {
+        anyhow::bail!("jitter_pct must be at most {}", MAX_JITTER_PCT);
+    }
+    Ok(())
+}
+
+/// Same limits as the global settings
+pub fn validate_check_overrides(checks: &CheckOverrides) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if checks.check_interval_secs.is_some_and(|i| i < 5) {
+        errors.push(FieldError::new("check_interval_secs", "must be >= 5"));
+    }
+    if checks.tcp_timeout_secs == Some(0) {
+        errors.push(FieldError::new("tcp_timeout_secs", "must be at least 1"));
+    }
+    if checks.ss_timeout_secs == Some(0) {
+        errors.push(FieldError::new("ss_timeout_secs", "must be at least 1"));
+    }
+    if checks.retries.is_some_and(|r| r > MAX_RETRIES) {
+        errors.push(FieldError::new(
+            "retries",
+            format!("must be at most {}", MAX_RETRIES),
+        ));
+    }
+    if let Some(targets) = &checks.test_targets {
+        if targets.is_empty() {
+            errors.push(FieldError::new(
+                "test_targets",
+                "must list at least one host",
+            ));
+        } else if targets.len() > MAX_TEST_TARGETS {
+            errors.push(FieldError::new(
+                "test_targets",
+                format!("must list at most {} hosts", MAX_TEST_TARGETS),
+            ));
+        } else if targets.iter().any(|t| validate_test_target(t).is_err()) {
+            errors.push(FieldError::new("test_targets", "must be bare host names"));
+        }
+    }
+    errors
+}
+
+/// Every invalid field of a server, whichever way it came in: the config
+/// file, the API, an import or a subscription
+pub fn validate_server(server: &Server) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if server.name.trim().is_empty() {
+        errors.push(FieldError::new("name", "is required"));
+    }
+    errors.extend(validate_endpoint(
+        &server.host,
+        server.port,
+        &server.method,
+        &server.password,
+    ));
+    match &server.plugin {
+        Some(plugin) if plugin.trim().is_empty() => {
+            errors.push(FieldError::new("plugin", "must not be empty"));
+        }
+        None if server.plugin_opts.is_some() => {
+            errors.push(FieldError::new("plugin_opts", "requires a plugin"));
+        }
+        _ => {}
+    }
+    if server.retention_days == Some(0) {
+        errors.push(FieldError::new("retention_days", "must be at least 1"));
+    }
+    errors.extend(validate_check_overrides(&server.checks));
+    errors.extend(validate_users(
+        &server.method,
+        &server.identity_keys,
+        &server.users,
+    ));
+    errors
+}
+
+/// Where and how to reach a server: a host name or IP address, a non-zero
+/// port, a supported method and a password that fits it
+fn validate_endpoint(host: &str, port: u16, method: &str, password: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if host.is_empty() {
+        errors.push(FieldError::new("host", "is required"));
+    } else if !is_valid_host(host) {
+        errors.push(FieldError::new("host", "must be a host name or IP address"));
+    }
+    if port == 0 {
+        errors.push(FieldError::new("port", "must be between 1 and 65535"));
+    }
+    match ciphers::parse(method) {
+        Ok(kind) => {
+            if let Err(e) = ciphers::check_key(kind, password) {
+                errors.push(FieldError::new("password", e));
+            }
+        }
+        Err(e) => errors.push(FieldError::new("method", e)),
+    }
+    errors
+}
+
+fn is_valid_host(host: &str) -> bool {
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return true;
+    }
+    host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// Identity keys only exist for the Shadowsocks 2022 AES methods. Users on
+/// the server's method connect through its identity keys, if it has any;
+/// users with a method of their own are plain extra passwords
+fn validate_users(method: &str, identity_keys: &[String], users: &[ServerUser]) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    // An unknown server method is reported on its own field
+    let server_kind = ciphers::parse(method).ok();
+    if let Some(kind) = server_kind.filter(|_| !identity_keys.is_empty()) {
+        if !method_support_eih(kind) {
+            errors.push(FieldError::new(
+                "identity_keys",
+                format!(
+                    "need a 2022-blake3-aes method, {} has no identity keys",
+                    kind
+                ),
+            ));
+        } else {
+            for (i, key) in identity_keys.iter().enumerate() {
+                if let Err(e) = ciphers::check_key(kind, key) {
+                    errors.push(FieldError::new(format!("identity_keys[{}]", i), e));
+                }
+            }
+        }
+    }
+    // A 2022 server can only tell users on its own method apart by the
+    // identity keys; users with another method are plain passwords
+    if let Some(kind) = server_kind.filter(|k| k.is_aead_2022() && identity_keys.is_empty()) {
+        let eih_user = users
+            .iter()
+            .position(|u| u.method.as_deref().is_none_or(|m| m == method));
+        if let Some(i) = eih_user {
+            let message = if method_support_eih(kind) {
+                "needs at least one identity key on a 2022 method".to_string()
+            } else {
+                format!("needs identity keys, which {} does not have", kind)
+            };
+            errors.push(FieldError::new(format!("users[{}]", i), message));
+        }
+    }
+    let mut names = std::collections::HashSet::new();
+    for (i, user) in users.iter().enumerate() {
+        if user.name.trim().is_empty() {
+            errors.push(FieldError::new(
+                format!("users[{}].name", i),
+                "must not be empty",
+            ));
+        } else if !names.insert(user.name.as_str()) {
+            errors.push(FieldError::new(
+                format!("users[{}].name", i),
+                format!("duplicate user name {}", user.name),
+            ));
+        }
+        let kind = match user.method.as_deref().map(ciphers::parse) {
+            Some(Ok(kind)) => Some(kind),
+            Some(Err(e)) => {
+                errors.push(FieldError::new(format!("users[{}].method", i), e));
+                None
+            }
+            None => server_kind,
+        };
+        if let Some(kind) = kind {
+            if let Err(e) = ciphers::check_key(kind, &user.password) {
+                errors.push(FieldError::new(format!("users[{}].password", i), e));
+            }
+        }
+    }
+    errors
+}
+
+/// The SS check connects to this host on port 80, so it must be a bare host name
+pub fn validate_test_target(target: &str) -> Result<()> {
+    if target.is_empty() || target.contains(|c: char| c.is_whitespace() || c == ':' || c == '/') {
+        anyhow::bail!("test_target must be a host name, got {:?}", target);
+    }
+    Ok(())
+}
+
+/// Add or replace the `changed` servers and delete the `removed` ones, in
+/// `servers` (the locked `state.servers`) and in the store. In database mode
+/// only their rows are written, before `servers` is touched, so a reload from
+/// the database (which holds the same lock) never sees memory ahead of it;
+/// on error nothing has changed. In config mode the whole file is written
+/// afterwards, and a failure to write it is only logged.
+pub async fn save_servers(
+    state: &AppState,
+    servers: &mut HashMap<Uuid, Server>,
+    changed: &[Server],
+    removed: &[Uuid],
+) -> Result<()> {
+    if state.server_store == ServerStore::Database {
+        for server in changed {
+            state.db.upsert_server(server).await?;
+        }
+        for &id in removed {
+            state.db.delete_server(id).await?;
+        }
+    }
+    for server in changed {
+        servers.insert(server.id, server.clone());
+    }
+    for id in removed {
+        servers.remove(id);
+    }
+    if state.server_store == ServerStore::Config {
+        if let Err(e) = write_file(state, servers).await {
+            tracing::error!("Failed to persist config: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Save servers and runtime settings to wherever `server_store` keeps them;
+/// in database mode, only the settings
 pub async fn persist(state: &AppState) -> Result<()> {
+    if state.server_store == ServerStore::Database {
+        return persist_db(state).await;
+    }
+    let servers = state.servers.read().await;
+    write_file(state, &servers).await
+}
+
+/// Write `servers` and the runtime settings to the config file
+async fn write_file(state: &AppState, servers: &HashMap<Uuid, Server>) -> Result<()> {
     let Some(ref path) = state.config_path else {
         return Ok(());
     };
+    // Also keeps a slower save from renaming an older snapshot over a newer one
+    let _lock = state.config_write_lock.lock().await;
 
-    let servers = state.servers.read().await;
     let server_configs: Vec<ServerConfig> = servers
         .values()
         .map(|s| ServerConfig {
@@ -123,24 +682,204 @@ pub async fn persist(state: &AppState) -> Result<()> {
             port: s.port,
             password: s.password.clone(),
             method: s.method.clone(),
+            plugin: s.plugin.clone(),
+            plugin_opts: s.plugin_opts.clone(),
+            identity_keys: s.identity_keys.clone(),
+            users: s.users.clone(),
             enabled: s.enabled,
             tags: s.tags.clone(),
+            visibility: s.visibility,
+            display_name: s.display_name.clone(),
+            group: s.group.clone(),
+            retention_days: s.retention_days,
+            checks: s.checks.clone(),
+            source: s.source.clone(),
+            disabled_by_source: s.disabled_by_source,
         })
         .collect();
 
-    let check_interval_secs = *state.check_interval_secs.read().await;
-
     let config = AppConfig {
         listen: state.listen.clone(),
-        auth: state.auth.clone(),
-        check_interval_secs,
-        tcp_timeout_secs: state.tcp_timeout_secs,
-        ss_timeout_secs: state.ss_timeout_secs,
-        test_target: state.test_target.clone(),
+        auth: state.auth.read().await.clone(),
+        public: state.public.read().await.clone(),
+        check_interval_secs: *state.check_interval_secs.read().await,
+        tcp_timeout_secs: *state.tcp_timeout_secs.read().await,
+        ss_timeout_secs: *state.ss_timeout_secs.read().await,
+        test_targets: state.test_targets.read().await.clone(),
+        retries: *state.retries.read().await,
+        max_concurrent_checks: *state.max_concurrent_checks.read().await,
+        jitter_pct: *state.jitter_pct.read().await,
+        missed_ticks: *state.missed_ticks.read().await,
+        retention: state.retention.read().await.clone(),
+        groups: state.groups.read().await.clone(),
+        subscriptions: state.subscriptions.read().await.clone(),
+        server_store: state.server_store,
         servers: server_configs,
     };
 
-    let file = std::fs::File::create(path)?;
-    serde_yaml_ng::to_writer(file, &config)?;
+    // Write a sibling file and rename it over the config, so the file watcher
+    // never sees a half-written file
+    let yaml = serde_yaml_ng::to_string(&config)?;
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, &yaml)?;
+    std::fs::rename(&tmp, path)?;
+    *state.config_written.write().await = Some(yaml);
+    Ok(())
+}
+
+/// Only the settings: stored servers are written one at a time by
+/// `save_servers`, so rows added by other instances are never lost
+async fn persist_db(state: &AppState) -> Result<()> {
+    let settings = [
+        (
+            db::SETTING_CHECK_INTERVAL,
+            state.check_interval_secs.read().await.to_string(),
+        ),
+        (
+            db::SETTING_TCP_TIMEOUT,
+            state.tcp_timeout_secs.read().await.to_string(),
+        ),
+        (
+            db::SETTING_SS_TIMEOUT,
+            state.ss_timeout_secs.read().await.to_string(),
+        ),
+        (
+            db::SETTING_TEST_TARGETS,
+            serde_json::to_string(&*state.test_targets.read().await)?,
+        ),
+        (db::SETTING_RETRIES, state.retries.read().await.to_string()),
+        (
+            db::SETTING_MAX_CONCURRENT_CHECKS,
+            state.max_concurrent_checks.read().await.to_string(),
+        ),
+        (
+            db::SETTING_JITTER,
+            state.jitter_pct.read().await.to_string(),
+        ),
+        (
+            db::SETTING_MISSED_TICKS,
+            serde_json::to_string(&*state.missed_ticks.read().await)?,
+        ),
+        (
+            db::SETTING_RETENTION,
+            serde_json::to_string(&*state.retention.read().await)?,
+        ),
+        (
+            db::SETTING_SUBSCRIPTIONS,
+            serde_json::to_string(&*state.subscriptions.read().await)?,
+        ),
+    ];
+    for (key, value) in settings {
+        state.db.set_setting(key, &value).await?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    fn key(len: usize) -> String {
+        STANDARD.encode(vec![7u8; len])
+    }
+
+    fn server(method: &str, password: &str) -> Server {
+        serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "name": "Tokyo",
+            "host": "example.com",
+            "port": 8388,
+            "password": password,
+            "method": method,
+            "enabled": true
+        }))
+        .unwrap()
+    }
+
+    fn error_fields(server: &Server) -> Vec<String> {
+        validate_server(server)
+            .into_iter()
+            .map(|e| e.field)
+            .collect()
+    }
+
+    #[test]
+    fn valid_servers_pass() {
+        assert!(validate_server(&server("aes-256-gcm", "any password")).is_empty());
+        assert!(validate_server(&server("2022-blake3-aes-128-gcm", &key(16))).is_empty());
+        assert!(validate_server(&server("2022-blake3-aes-256-gcm", &key(32))).is_empty());
+    }
+
+    #[test]
+    fn unknown_cipher_is_rejected() {
+        assert_eq!(error_fields(&server("aes-512-gcm", "pw")), ["method"]);
+        assert_eq!(error_fields(&server("", "pw")), ["method"]);
+    }
+
+    #[test]
+    fn bad_2022_keys_are_rejected() {
+        // Wrong length for the method
+        assert_eq!(
+            error_fields(&server("2022-blake3-aes-256-gcm", &key(16))),
+            ["password"]
+        );
+        assert_eq!(
+            error_fields(&server("2022-blake3-aes-128-gcm", "not base64!")),
+            ["password"]
+        );
+    }
+
+    #[test]
+    fn bad_endpoint_fields_are_each_reported() {
+        let mut s = server("aes-128-gcm", "pw");
+        s.name = " ".into();
+        s.host = "-bad-.example.com".into();
+        s.port = 0;
+        s.plugin_opts = Some("obfs=http".into());
+        assert_eq!(error_fields(&s), ["name", "host", "port", "plugin_opts"]);
+
+        s.host = "2001:db8::1".into();
+        s.port = 443;
+        s.name = "Tokyo".into();
+        s.plugin = Some("obfs-local".into());
+        assert!(validate_server(&s).is_empty());
+    }
+
+    #[test]
+    fn identity_keys_need_an_aes_2022_method() {
+        let mut s = server("2022-blake3-chacha20-poly1305", &key(32));
+        s.identity_keys = vec![key(32)];
+        assert_eq!(error_fields(&s), ["identity_keys"]);
+
+        let mut s = server("2022-blake3-aes-128-gcm", &key(16));
+        s.identity_keys = vec![key(32)];
+        assert_eq!(error_fields(&s), ["identity_keys[0]"]);
+    }
+
+    #[test]
+    fn users_are_validated() {
+        let user = |name: &str, password: String, method: Option<&str>| ServerUser {
+            name: name.into(),
+            password,
+            method: method.map(String::from),
+        };
+
+        let mut s = server("2022-blake3-aes-128-gcm", &key(16));
+        s.users = vec![user("alice", key(16), None)];
+        assert_eq!(error_fields(&s), ["users[0]"], "no identity key");
+
+        s.identity_keys = vec![key(16)];
+        s.users = vec![
+            user("alice", key(16), None),
+            user("alice", key(32), None),
+            user("bob", "pw".into(), Some("aes-256-gcm")),
+            user("carol", "pw".into(), Some("rot13")),
+        ];
+        assert_eq!(
+            error_fields(&s),
+            ["users[1].name", "users[1].password", "users[3].method"]
+        );
+    }
+}
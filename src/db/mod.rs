@@ -0,0 +1,510 @@
+//! Persistence for check results, rollups, servers and settings.
+//!
+//! Everything goes through the [`Storage`] trait so the rest of the app does
+//! not care whether it is talking to the embedded SQLite file or a shared
+//! PostgreSQL database.
+
+mod postgres;
+mod sqlite;
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::models::{
+    CheckResult, LatencyStats, ResultFilter, Server, StatsWindow, SyncLogEntry, UptimeStats,
+};
+
+pub use self::postgres::PgStorage;
+pub use self::sqlite::SqliteStorage;
+
+// Settings keys for the runtime settings kept in the database in database mode
+pub const SETTING_CHECK_INTERVAL: &str = "check_interval_secs";
+pub const SETTING_TCP_TIMEOUT: &str = "tcp_timeout_secs";
+pub const SETTING_SS_TIMEOUT: &str = "ss_timeout_secs";
+/// Test targets, as JSON; a bare host name from before they were a list
+/// is read as the only one
+pub const SETTING_TEST_TARGETS: &str = "test_target";
+pub const SETTING_RETRIES: &str = "retries";
+pub const SETTING_MAX_CONCURRENT_CHECKS: &str = "max_concurrent_checks";
+pub const SETTING_JITTER: &str = "jitter_pct";
+/// Missed-tick behavior, as JSON
+pub const SETTING_MISSED_TICKS: &str = "missed_ticks";
+/// Retention policy, as JSON
+pub const SETTING_RETENTION: &str = "retention";
+/// Subscription sources, as JSON
+pub const SETTING_SUBSCRIPTIONS: &str = "subscriptions";
+/// Set once the YAML servers have been copied into an empty database
+const SETTING_SERVERS_IMPORTED: &str = "servers_imported";
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Short backend name for logs
+    fn backend(&self) -> &'static str;
+
+    /// Whether this instance is the one that stores check results. Only
+    /// one instance sharing a database may write them, or every check would
+    /// be counted once per monitor.
+    async fn is_results_writer(&self) -> bool {
+        true
+    }
+
+    /// Store a round of results in one transaction
+    async fn insert_results(&self, results: &[CheckResult]) -> Result<()>;
+
+    /// Newest `limit` results for a server, newest first
+    async fn load_results_for_server(
+        &self,
+        server_id: Uuid,
+        limit: usize,
+    ) -> Result<Vec<CheckResult>>;
+
+    /// Number of stored raw results per server
+    async fn count_results_all(&self) -> Result<HashMap<Uuid, u64>>;
+
+    /// Results matching `query`, newest first, each paired with its row id
+    async fn query_results(&self, query: &ResultQuery) -> Result<Vec<(i64, CheckResult)>>;
+
+    /// Next batch of results for export, oldest first, starting after row `after_id`.
+    /// `server_id` of `None` exports every server.
+    async fn export_batch(
+        &self,
+        server_id: Option<Uuid>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        after_id: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, CheckResult)>>;
+
+    /// TCP and SS latency stats over a server's raw results since `since`,
+    /// reduced in the database rather than loaded
+    async fn latency_stats_since(
+        &self,
+        server_id: Uuid,
+        since: DateTime<Utc>,
+    ) -> Result<(LatencyStats, LatencyStats)>;
+
+    /// Uptime and latency aggregates for one server over `window`
+    async fn uptime_stats(&self, server_id: Uuid, window: StatsWindow) -> Result<UptimeStats>;
+
+    /// Uptime and latency aggregates over `window` for every server with results
+    async fn uptime_stats_all(&self, window: StatsWindow) -> Result<HashMap<Uuid, UptimeStats>>;
+
+    /// Roll up every complete bucket since the last run from raw rows.
+    /// Returns the number of buckets processed.
+    async fn update_rollups(&self, rollup: Rollup) -> Result<usize>;
+
+    async fn cleanup_rollups(&self, rollup: Rollup, keep_days: i64) -> Result<usize>;
+
+    /// Delete raw results older than `keep_days`, except for servers listed in
+    /// `overrides`, which use their own retention instead.
+    /// Rows not yet folded into both rollup tables are always kept.
+    async fn cleanup_old(&self, keep_days: i64, overrides: &[(Uuid, i64)]) -> Result<usize>;
+
+    /// Keep only the newest `max_rows` raw results of each server.
+    /// Rows not yet folded into both rollup tables are always kept.
+    async fn trim_results_per_server(&self, max_rows: u32) -> Result<usize>;
+
+    /// Give space freed by cleanup back, if the backend needs to be told
+    async fn vacuum(&self) -> Result<()>;
+
+    async fn get_setting(&self, key: &str) -> Result<Option<String>>;
+
+    async fn set_setting(&self, key: &str, value: &str) -> Result<()>;
+
+    async fn load_servers(&self) -> Result<Vec<Server>>;
+
+    /// Insert `server`, or overwrite the stored server with its id
+    async fn upsert_server(&self, server: &Server) -> Result<()>;
+
+    /// Delete one stored server; a no-op if it is not stored
+    async fn delete_server(&self, id: Uuid) -> Result<()>;
+
+    /// Copy `servers` into the database the first time it is used in database
+    /// mode. Returns how many were imported; later calls are no-ops, so deleting
+    /// every server through the API does not bring the YAML ones back.
+    async fn import_servers_once(&self, servers: &[Server]) -> Result<usize>;
+
+    async fn insert_sync_log(&self, entry: &SyncLogEntry) -> Result<()>;
+
+    /// Newest `limit` sync log entries, newest first, optionally for one
+    /// subscription only
+    async fn load_sync_log(
+        &self,
+        subscription: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SyncLogEntry>>;
+
+    /// Delete sync log entries older than `keep_days`
+    async fn cleanup_sync_log(&self, keep_days: i64) -> Result<usize>;
+
+    /// Write out anything still buffered and release connections before exit
+    async fn close(&self) -> Result<()>;
+}
+
+/// Open PostgreSQL when `database_url` is given, the SQLite file at
+/// `sqlite_path` otherwise, and bring its schema up to date
+pub async fn open(sqlite_path: &str, database_url: Option<&str>) -> Result<Box<dyn Storage>> {
+    match database_url {
+        Some(url) => Ok(Box::new(PgStorage::connect(url).await?)),
+        None => Ok(Box::new(SqliteStorage::open(sqlite_path)?)),
+    }
+}
+
+/// Parameters for a paged history query
+#[derive(Debug, Clone)]
+pub struct ResultQuery {
+    pub server_id: Uuid,
+    /// Inclusive lower bound on timestamp
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on timestamp
+    pub to: Option<DateTime<Utc>>,
+    /// Only rows older than this row id (the previous page's cursor)
+    pub before_id: Option<i64>,
+    pub filter: Option<ResultFilter>,
+    pub limit: usize,
+}
+
+/// Additive partial aggregates that raw rows and rollup buckets both reduce to
+#[derive(Debug, Default, Clone)]
+struct StatsAccumulator {
+    checks: u64,
+    tcp_up: u64,
+    ss_checks: u64,
+    ss_ok: u64,
+    latency_count: u64,
+    latency_sum: f64,
+    latency_min: Option<f64>,
+    latency_max: Option<f64>,
+    ss_latency_count: u64,
+    ss_latency_sum: f64,
+}
+
+impl StatsAccumulator {
+    fn merge(&mut self, other: &Self) {
+        self.checks += other.checks;
+        self.tcp_up += other.tcp_up;
+        self.ss_checks += other.ss_checks;
+        self.ss_ok += other.ss_ok;
+        self.latency_count += other.latency_count;
+        self.latency_sum += other.latency_sum;
+        self.latency_min = min_opt(self.latency_min, other.latency_min);
+        self.latency_max = max_opt(self.latency_max, other.latency_max);
+        self.ss_latency_count += other.ss_latency_count;
+        self.ss_latency_sum += other.ss_latency_sum;
+    }
+
+    fn finish(&self, window: StatsWindow) -> UptimeStats {
+        let pct = |n: u64, d: u64| (d > 0).then(|| n as f64 / d as f64 * 100.0);
+        let mean = |sum: f64, n: u64| (n > 0).then(|| sum / n as f64);
+
+        UptimeStats {
+            window,
+            total_checks: self.checks,
+            tcp_up_checks: self.tcp_up,
+            ss_checks: self.ss_checks,
+            ss_ok_checks: self.ss_ok,
+            uptime_pct: pct(self.tcp_up, self.checks),
+            ss_success_pct: pct(self.ss_ok, self.ss_checks),
+            avg_latency_ms: mean(self.latency_sum, self.latency_count),
+            min_latency_ms: self.latency_min,
+            max_latency_ms: self.latency_max,
+            avg_ss_latency_ms: mean(self.ss_latency_sum, self.ss_latency_count),
+        }
+    }
+}
+
+/// One kind of check's latency samples as reduced by a backend's SQL, in the
+/// terms of `stats::latency_stats`
+#[derive(Debug, Default)]
+struct LatencySummary {
+    attempts: u64,
+    failures: u64,
+    /// Latencies of successful checks
+    samples: u64,
+    mean: Option<f64>,
+    /// Mean squared deviation from `mean`
+    variance: Option<f64>,
+    /// Mean absolute difference between consecutive samples in time order
+    jitter: Option<f64>,
+    p50: Option<f64>,
+    p90: Option<f64>,
+    p99: Option<f64>,
+}
+
+impl LatencySummary {
+    fn finish(self) -> LatencyStats {
+        LatencyStats {
+            attempts: self.attempts,
+            failures: self.failures,
+            failure_pct: (self.attempts > 0)
+                .then(|| self.failures as f64 / self.attempts as f64 * 100.0),
+            samples: self.samples,
+            mean_ms: self.mean,
+            p50_ms: self.p50,
+            p90_ms: self.p90,
+            p99_ms: self.p99,
+            stddev_ms: self.variance.map(|v| v.max(0.0).sqrt()),
+            jitter_ms: self.jitter,
+        }
+    }
+}
+
+/// Per-user results as stored, NULL for servers without users
+fn user_checks_json(result: &CheckResult) -> Result<Option<String>> {
+    if result.user_checks.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::to_string(&result.user_checks)?))
+}
+
+fn min_opt(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn max_opt(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Granularity of a downsampled rollup table
+#[derive(Debug, Clone, Copy)]
+pub enum Rollup {
+    Hourly,
+    Daily,
+}
+
+impl Rollup {
+    fn table(self) -> &'static str {
+        match self {
+            Rollup::Hourly => "check_rollups_hourly",
+            Rollup::Daily => "check_rollups_daily",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Rollup::Hourly => "hourly",
+            Rollup::Daily => "daily",
+        }
+    }
+
+    fn bucket_len(self) -> Duration {
+        match self {
+            Rollup::Hourly => Duration::hours(1),
+            Rollup::Daily => Duration::days(1),
+        }
+    }
+
+    /// Start of the bucket containing `t`
+    fn floor(self, t: DateTime<Utc>) -> DateTime<Utc> {
+        let secs = self.bucket_len().num_seconds();
+        let ts = t.timestamp();
+        DateTime::from_timestamp(ts - ts.rem_euclid(secs), 0).unwrap_or(t)
+    }
+
+    /// Start of the first bucket at or after `t`
+    fn ceil(self, t: DateTime<Utc>) -> DateTime<Utc> {
+        let start = self.floor(t);
+        if start < t {
+            start + self.bucket_len()
+        } else {
+            start
+        }
+    }
+}
+
+/// Which source covers each part of a stats window starting at `since`:
+/// daily rollups before `daily_until`, hourly rollups before `hourly_until`,
+/// raw rows after that.
+struct WindowSplit {
+    since: DateTime<Utc>,
+    daily_until: Option<DateTime<Utc>>,
+    hourly_until: Option<DateTime<Utc>>,
+}
+
+impl WindowSplit {
+    /// `first_hour` is the oldest hourly bucket still stored. Hourly rollups
+    /// expire before daily ones, so anything older comes from the daily table,
+    /// whole days at a time.
+    fn new(
+        since: DateTime<Utc>,
+        hourly_next: Option<DateTime<Utc>>,
+        first_hour: Option<DateTime<Utc>>,
+        daily_next: Option<DateTime<Utc>>,
+    ) -> Self {
+        let hourly_until = hourly_next.filter(|w| *w > since);
+        let daily_until = match (hourly_until, first_hour, daily_next) {
+            (Some(hourly), Some(first), Some(daily)) if first > since => {
+                Some(Rollup::Daily.ceil(first).min(daily).min(hourly)).filter(|t| *t > since)
+            }
+            _ => None,
+        };
+        Self {
+            since,
+            daily_until,
+            hourly_until,
+        }
+    }
+
+    /// Range read from the hourly rollups, if any
+    fn hourly(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let from = self.daily_until.unwrap_or(self.since);
+        self.hourly_until
+            .filter(|until| *until > from)
+            .map(|until| (from, until))
+    }
+
+    /// Start of the range read from raw rows
+    fn raw_since(&self) -> DateTime<Utc> {
+        self.hourly_until.unwrap_or(self.since)
+    }
+}
+
+/// Raw rows before this have been folded into every rollup table and are safe
+/// to delete; `None` until both rollups have run.
+fn rolled_up_until(
+    hourly_next: Option<DateTime<Utc>>,
+    daily_next: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    Some(hourly_next?.min(daily_next?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn rollup_floor_and_ceil() {
+        let t = at("2026-10-01T13:30:15Z");
+        assert_eq!(Rollup::Hourly.floor(t), at("2026-10-01T13:00:00Z"));
+        assert_eq!(Rollup::Hourly.ceil(t), at("2026-10-01T14:00:00Z"));
+        assert_eq!(Rollup::Daily.floor(t), at("2026-10-01T00:00:00Z"));
+        assert_eq!(Rollup::Daily.ceil(t), at("2026-10-02T00:00:00Z"));
+
+        // Bucket starts are their own floor and ceil
+        let hour = at("2026-10-01T13:00:00Z");
+        assert_eq!(Rollup::Hourly.floor(hour), hour);
+        assert_eq!(Rollup::Hourly.ceil(hour), hour);
+
+        // Before the epoch the remainder must not go negative
+        assert_eq!(
+            Rollup::Hourly.floor(at("1969-12-31T23:30:00Z")),
+            at("1969-12-31T23:00:00Z")
+        );
+    }
+
+    #[test]
+    fn window_without_rollups_reads_raw_rows() {
+        let since = at("2026-10-01T00:00:00Z");
+        let split = WindowSplit::new(since, None, None, None);
+        assert_eq!(split.daily_until, None);
+        assert_eq!(split.hourly(), None);
+        assert_eq!(split.raw_since(), since);
+    }
+
+    #[test]
+    fn window_after_hourly_watermark_reads_raw_rows() {
+        let since = at("2026-10-01T12:00:00Z");
+        let split = WindowSplit::new(
+            since,
+            Some(at("2026-10-01T12:00:00Z")),
+            Some(at("2026-09-30T00:00:00Z")),
+            Some(at("2026-10-01T00:00:00Z")),
+        );
+        assert_eq!(split.daily_until, None);
+        assert_eq!(split.hourly(), None);
+        assert_eq!(split.raw_since(), since);
+    }
+
+    #[test]
+    fn window_inside_hourly_retention_skips_daily() {
+        let since = at("2026-10-01T00:30:00Z");
+        let split = WindowSplit::new(
+            since,
+            Some(at("2026-10-01T05:00:00Z")),
+            Some(at("2026-09-20T00:00:00Z")),
+            Some(at("2026-10-01T00:00:00Z")),
+        );
+        assert_eq!(split.daily_until, None);
+        assert_eq!(split.hourly(), Some((since, at("2026-10-01T05:00:00Z"))));
+        assert_eq!(split.raw_since(), at("2026-10-01T05:00:00Z"));
+    }
+
+    #[test]
+    fn window_past_hourly_horizon_uses_whole_days() {
+        // Hourly buckets before 03:00 on the 1st have expired, so the daily
+        // table covers up to the first midnight with full hourly coverage
+        let since = at("2026-09-20T00:00:00Z");
+        let split = WindowSplit::new(
+            since,
+            Some(at("2026-10-03T05:00:00Z")),
+            Some(at("2026-10-01T03:00:00Z")),
+            Some(at("2026-10-03T00:00:00Z")),
+        );
+        assert_eq!(split.daily_until, Some(at("2026-10-02T00:00:00Z")));
+        assert_eq!(
+            split.hourly(),
+            Some((at("2026-10-02T00:00:00Z"), at("2026-10-03T05:00:00Z")))
+        );
+        assert_eq!(split.raw_since(), at("2026-10-03T05:00:00Z"));
+    }
+
+    #[test]
+    fn window_daily_horizon_stops_at_daily_watermark() {
+        // Daily rollups lag behind the oldest hourly bucket: hourly takes
+        // over where daily stops
+        let since = at("2026-09-20T00:00:00Z");
+        let split = WindowSplit::new(
+            since,
+            Some(at("2026-10-03T05:00:00Z")),
+            Some(at("2026-10-01T03:00:00Z")),
+            Some(at("2026-10-01T00:00:00Z")),
+        );
+        assert_eq!(split.daily_until, Some(at("2026-10-01T00:00:00Z")));
+        assert_eq!(
+            split.hourly(),
+            Some((at("2026-10-01T00:00:00Z"), at("2026-10-03T05:00:00Z")))
+        );
+    }
+
+    #[test]
+    fn window_daily_watermark_before_since_is_ignored() {
+        let since = at("2026-09-30T00:00:00Z");
+        let split = WindowSplit::new(
+            since,
+            Some(at("2026-10-03T05:00:00Z")),
+            Some(at("2026-10-01T03:00:00Z")),
+            Some(at("2026-09-28T00:00:00Z")),
+        );
+        assert_eq!(split.daily_until, None);
+        assert_eq!(split.hourly(), Some((since, at("2026-10-03T05:00:00Z"))));
+    }
+
+    #[test]
+    fn rolled_up_until_needs_both_rollups() {
+        let hourly = at("2026-10-01T05:00:00Z");
+        let daily = at("2026-10-01T00:00:00Z");
+        assert_eq!(rolled_up_until(None, None), None);
+        assert_eq!(rolled_up_until(Some(hourly), None), None);
+        assert_eq!(rolled_up_until(None, Some(daily)), None);
+        assert_eq!(rolled_up_until(Some(hourly), Some(daily)), Some(daily));
+        assert_eq!(
+            rolled_up_until(Some(daily), Some(hourly)),
+            Some(daily),
+            "the older watermark wins either way"
+        );
+    }
+}
@@ -0,0 +1,1118 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::{GenericClient, Manager, ManagerConfig, Pool, RecyclingMethod};
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, Row};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{
+    rolled_up_until, user_checks_json, LatencySummary, ResultQuery, Rollup, StatsAccumulator,
+    Storage, WindowSplit, SETTING_SERVERS_IMPORTED,
+};
+use crate::models::{
+    CheckResult, LatencyStats, ResultFilter, Server, SsCheckResult, SsErrorKind, StatsWindow,
+    SyncLogEntry, TcpCheckResult, UptimeStats, Visibility,
+};
+
+/// Schema migrations, applied in order. Migration `n` (1-based) brings the
+/// database to `schema_version = n`; never edit one that has shipped, append
+/// a new one instead. Numbered independently of the SQLite migrations.
+const MIGRATIONS: &[&str] = &[
+    // 1: results, rollups, servers and settings
+    "CREATE TABLE check_results (
+        id             BIGSERIAL   PRIMARY KEY,
+        server_id      UUID        NOT NULL,
+        timestamp      TIMESTAMPTZ NOT NULL,
+        tcp_reachable  BOOLEAN     NOT NULL,
+        tcp_latency_ms DOUBLE PRECISION,
+        tcp_error      TEXT,
+        ss_success     BOOLEAN,
+        ss_latency_ms  DOUBLE PRECISION,
+        ss_error       TEXT
+    );
+    CREATE INDEX idx_results_server_time
+        ON check_results(server_id, timestamp DESC);
+    CREATE INDEX idx_results_time
+        ON check_results(timestamp);
+    CREATE TABLE check_rollups_hourly (
+        server_id        UUID        NOT NULL,
+        bucket           TIMESTAMPTZ NOT NULL,
+        checks           BIGINT      NOT NULL,
+        tcp_up           BIGINT      NOT NULL,
+        ss_checks        BIGINT      NOT NULL,
+        ss_ok            BIGINT      NOT NULL,
+        latency_count    BIGINT      NOT NULL,
+        latency_sum      DOUBLE PRECISION NOT NULL,
+        latency_min      DOUBLE PRECISION,
+        latency_avg      DOUBLE PRECISION,
+        latency_p50      DOUBLE PRECISION,
+        latency_p95      DOUBLE PRECISION,
+        latency_max      DOUBLE PRECISION,
+        ss_latency_count BIGINT      NOT NULL,
+        ss_latency_sum   DOUBLE PRECISION NOT NULL,
+        PRIMARY KEY (server_id, bucket)
+    );
+    CREATE INDEX idx_check_rollups_hourly_bucket
+        ON check_rollups_hourly(bucket);
+    CREATE TABLE check_rollups_daily (
+        server_id        UUID        NOT NULL,
+        bucket           TIMESTAMPTZ NOT NULL,
+        checks           BIGINT      NOT NULL,
+        tcp_up           BIGINT      NOT NULL,
+        ss_checks        BIGINT      NOT NULL,
+        ss_ok            BIGINT      NOT NULL,
+        latency_count    BIGINT      NOT NULL,
+        latency_sum      DOUBLE PRECISION NOT NULL,
+        latency_min      DOUBLE PRECISION,
+        latency_avg      DOUBLE PRECISION,
+        latency_p50      DOUBLE PRECISION,
+        latency_p95      DOUBLE PRECISION,
+        latency_max      DOUBLE PRECISION,
+        ss_latency_count BIGINT      NOT NULL,
+        ss_latency_sum   DOUBLE PRECISION NOT NULL,
+        PRIMARY KEY (server_id, bucket)
+    );
+    CREATE INDEX idx_check_rollups_daily_bucket
+        ON check_rollups_daily(bucket);
+    CREATE TABLE rollup_state (
+        name        TEXT        PRIMARY KEY,
+        next_bucket TIMESTAMPTZ NOT NULL
+    );
+    CREATE TABLE servers (
+        id             UUID    PRIMARY KEY,
+        name           TEXT    NOT NULL,
+        host           TEXT    NOT NULL,
+        port           INTEGER NOT NULL,
+        password       TEXT    NOT NULL,
+        method         TEXT    NOT NULL,
+        enabled        BOOLEAN NOT NULL,
+        tags           TEXT[]  NOT NULL,
+        visibility     TEXT    NOT NULL,
+        display_name   TEXT,
+        group_name     TEXT,
+        retention_days INTEGER
+    );
+    CREATE TABLE settings (
+        key   TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );",
+    // 2: per-server check overrides, as JSON
+    "ALTER TABLE servers ADD COLUMN check_overrides TEXT NOT NULL DEFAULT '{}';",
+    // 3: subscription sources, and whether a sync disabled the server
+    "ALTER TABLE servers ADD COLUMN source TEXT;
+    ALTER TABLE servers ADD COLUMN disabled_by_source BOOLEAN NOT NULL DEFAULT FALSE;
+    CREATE TABLE sync_log (
+        id           BIGSERIAL   PRIMARY KEY,
+        subscription TEXT        NOT NULL,
+        timestamp    TIMESTAMPTZ NOT NULL,
+        added        INTEGER     NOT NULL,
+        updated      INTEGER     NOT NULL,
+        disabled     INTEGER     NOT NULL,
+        duplicates   INTEGER     NOT NULL,
+        invalid      INTEGER     NOT NULL,
+        error        TEXT
+    );
+    CREATE INDEX idx_sync_log_time ON sync_log(timestamp);",
+    // 4: SIP003 plugins and the cause of failed SS checks
+    "ALTER TABLE servers ADD COLUMN plugin TEXT;
+    ALTER TABLE servers ADD COLUMN plugin_opts TEXT;
+    ALTER TABLE check_results ADD COLUMN ss_error_kind TEXT;",
+    // 5: Shadowsocks 2022 identity keys, users and per-user results
+    "ALTER TABLE servers ADD COLUMN identity_keys TEXT[] NOT NULL DEFAULT '{}';
+    ALTER TABLE servers ADD COLUMN users TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE check_results ADD COLUMN user_checks TEXT;",
+    // 6: SS latency was rolled up over failed checks too. Recount it for
+    // every bucket whose raw rows are all still there, which is every bucket
+    // from the one after a server's oldest raw row.
+    "UPDATE check_rollups_hourly AS b SET (ss_latency_count, ss_latency_sum) = (
+        SELECT COUNT(ss_latency_ms), COALESCE(SUM(ss_latency_ms), 0)
+        FROM check_results r
+        WHERE r.server_id = b.server_id
+          AND r.timestamp >= b.bucket AND r.timestamp < b.bucket + INTERVAL '1 hour'
+          AND r.tcp_reachable AND r.ss_success
+    )
+    WHERE b.bucket >= (
+        SELECT to_timestamp(floor(extract(epoch FROM MIN(r.timestamp)) / 3600) * 3600 + 3600)
+        FROM check_results r WHERE r.server_id = b.server_id
+    );
+    UPDATE check_rollups_daily AS b SET (ss_latency_count, ss_latency_sum) = (
+        SELECT COUNT(ss_latency_ms), COALESCE(SUM(ss_latency_ms), 0)
+        FROM check_results r
+        WHERE r.server_id = b.server_id
+          AND r.timestamp >= b.bucket AND r.timestamp < b.bucket + INTERVAL '1 day'
+          AND r.tcp_reachable AND r.ss_success
+    )
+    WHERE b.bucket >= (
+        SELECT to_timestamp(floor(extract(epoch FROM MIN(r.timestamp)) / 86400) * 86400 + 86400)
+        FROM check_results r WHERE r.server_id = b.server_id
+    );",
+];
+
+/// Schema version this build expects
+const SCHEMA_VERSION: i32 = MIGRATIONS.len() as i32;
+
+/// Advisory lock keys, so monitors sharing a database take turns at
+/// migrating and rolling up instead of doing the same work twice
+const MIGRATION_LOCK: i64 = 0x5353_0001;
+const ROLLUP_LOCK: i64 = 0x5353_0002;
+/// Session-level lock held by the one monitor that stores check results
+const WRITER_LOCK: i64 = 0x5353_0003;
+
+/// Connections kept open per instance
+const POOL_SIZE: usize = 8;
+
+/// Shared PostgreSQL backend, so several monitors can share one history.
+/// They all check every server, but only the holder of `WRITER_LOCK` stores
+/// results; the others stand by and take over when it goes away.
+pub struct PgStorage {
+    pool: Pool,
+    pg_config: tokio_postgres::Config,
+    tls: MakeRustlsConnect,
+    writer: Mutex<WriterLock>,
+}
+
+/// A connection of its own for `WRITER_LOCK`, which keeps the lock until
+/// the connection closes
+#[derive(Default)]
+struct WriterLock {
+    client: Option<Client>,
+    held: bool,
+    /// Already logged that another instance holds it
+    standing_by: bool,
+}
+
+impl PgStorage {
+    /// Connect using `sslmode` from the URL: `disable` never uses TLS,
+    /// `prefer` (the default) uses it when the server offers it, `require`
+    /// refuses to connect without it. Server certificates are verified
+    /// against the system and Mozilla root stores.
+    pub async fn connect(url: &str) -> Result<Self> {
+        let pg_config: tokio_postgres::Config = url.parse().context("Invalid database URL")?;
+        let tls = tls_connector()?;
+        let manager = Manager::from_config(
+            pg_config.clone(),
+            tls.clone(),
+            ManagerConfig {
+                recycling_method: RecyclingMethod::Fast,
+            },
+        );
+        let pool = Pool::builder(manager).max_size(POOL_SIZE).build()?;
+        let storage = Self {
+            pool,
+            pg_config,
+            tls,
+            writer: Mutex::new(WriterLock::default()),
+        };
+        storage.migrate().await?;
+        Ok(storage)
+    }
+
+    /// Try to take `WRITER_LOCK`, reconnecting first if the lock
+    /// connection is gone. False while another instance holds it.
+    async fn claim_writer(&self, lock: &mut WriterLock) -> Result<bool> {
+        let client = match &mut lock.client {
+            Some(client) if !client.is_closed() => client,
+            slot => {
+                let (client, connection) = self.pg_config.connect(self.tls.clone()).await?;
+                tokio::spawn(async move {
+                    if let Err(e) = connection.await {
+                        tracing::warn!("Results writer connection closed: {}", e);
+                    }
+                });
+                slot.insert(client)
+            }
+        };
+        Ok(client
+            .query_one("SELECT pg_try_advisory_lock($1)", &[&WRITER_LOCK])
+            .await?
+            .get(0))
+    }
+
+    /// Bring the schema up to `SCHEMA_VERSION` in one transaction.
+    /// Refuses to use a database written by a newer build.
+    async fn migrate(&self) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+            .await?;
+        tx.batch_execute("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)")
+            .await?;
+        let current: i32 = tx
+            .query_opt("SELECT version FROM schema_version", &[])
+            .await?
+            .map(|row| row.get(0))
+            .unwrap_or(0);
+        if current > SCHEMA_VERSION {
+            anyhow::bail!(
+                "Database schema version {} is newer than this build supports ({}); \
+                 upgrade sserver-status or restore a backup taken before the upgrade",
+                current,
+                SCHEMA_VERSION
+            );
+        }
+        if current == SCHEMA_VERSION {
+            return Ok(());
+        }
+
+        for (idx, sql) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+            let version = idx + 1;
+            tx.batch_execute(sql)
+                .await
+                .map_err(|e| anyhow::anyhow!("Migration {} failed: {}", version, e))?;
+            tracing::info!("Applied database migration {}", version);
+        }
+        tx.execute("DELETE FROM schema_version", &[]).await?;
+        tx.execute(
+            "INSERT INTO schema_version (version) VALUES ($1)",
+            &[&SCHEMA_VERSION],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+const RESULT_COLUMNS: &str = "server_id, timestamp, tcp_reachable, tcp_latency_ms, tcp_error,
+                ss_success, ss_latency_ms, ss_error, ss_error_kind, user_checks";
+
+fn result_from_row(row: &Row) -> CheckResult {
+    let ss_success: Option<bool> = row.get(5);
+    let ss_check = ss_success.map(|success| SsCheckResult {
+        success,
+        latency_ms: row.get(6),
+        error: row.get(7),
+        error_kind: row.get::<_, Option<&str>>(8).and_then(SsErrorKind::parse),
+    });
+
+    CheckResult {
+        server_id: row.get(0),
+        timestamp: row.get(1),
+        tcp_check: TcpCheckResult {
+            reachable: row.get(2),
+            latency_ms: row.get(3),
+            error: row.get(4),
+        },
+        ss_check,
+        user_checks: row
+            .get::<_, Option<&str>>(9)
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default(),
+    }
+}
+
+fn server_from_row(row: &Row) -> Server {
+    let visibility: String = row.get(8);
+    Server {
+        id: row.get(0),
+        name: row.get(1),
+        host: row.get(2),
+        port: row.get::<_, i32>(3) as u16,
+        password: row.get(4),
+        method: row.get(5),
+        enabled: row.get(6),
+        tags: row.get(7),
+        visibility: Visibility::parse(&visibility).unwrap_or_default(),
+        display_name: row.get(9),
+        group: row.get(10),
+        retention_days: row.get::<_, Option<i32>>(11).map(|d| d as u32),
+        checks: serde_json::from_str(row.get(12)).unwrap_or_default(),
+        source: row.get(13),
+        plugin: row.get(14),
+        plugin_opts: row.get(15),
+        identity_keys: row.get(16),
+        users: serde_json::from_str(row.get(17)).unwrap_or_default(),
+        disabled_by_source: row.get(18),
+    }
+}
+
+fn sync_log_from_row(row: &Row) -> SyncLogEntry {
+    SyncLogEntry {
+        subscription: row.get(0),
+        timestamp: row.get(1),
+        added: row.get::<_, i32>(2) as u32,
+        updated: row.get::<_, i32>(3) as u32,
+        disabled: row.get::<_, i32>(4) as u32,
+        duplicates: row.get::<_, i32>(5) as u32,
+        invalid: row.get::<_, i32>(6) as u32,
+        error: row.get(7),
+    }
+}
+
+/// Columns summed over raw rows, in the same shape as `ROLLUP_STATS_COLUMNS`.
+/// SS checks skipped because TCP was down do not count as SS checks, and
+/// only successful SS checks count toward SS latency.
+const RAW_STATS_COLUMNS: &str = "server_id,
+                COUNT(*),
+                COUNT(*) FILTER (WHERE tcp_reachable),
+                COUNT(ss_success) FILTER (WHERE tcp_reachable),
+                COUNT(*) FILTER (WHERE tcp_reachable AND ss_success),
+                COUNT(tcp_latency_ms),
+                COALESCE(SUM(tcp_latency_ms), 0),
+                MIN(tcp_latency_ms),
+                MAX(tcp_latency_ms),
+                COUNT(ss_latency_ms) FILTER (WHERE tcp_reachable AND ss_success),
+                COALESCE(SUM(ss_latency_ms) FILTER (WHERE tcp_reachable AND ss_success), 0)";
+
+/// Columns summed over rollup buckets, in the same shape as `RAW_STATS_COLUMNS`
+const ROLLUP_STATS_COLUMNS: &str = "server_id,
+                COALESCE(SUM(checks), 0)::BIGINT,
+                COALESCE(SUM(tcp_up), 0)::BIGINT,
+                COALESCE(SUM(ss_checks), 0)::BIGINT,
+                COALESCE(SUM(ss_ok), 0)::BIGINT,
+                COALESCE(SUM(latency_count), 0)::BIGINT,
+                COALESCE(SUM(latency_sum), 0),
+                MIN(latency_min),
+                MAX(latency_max),
+                COALESCE(SUM(ss_latency_count), 0)::BIGINT,
+                COALESCE(SUM(ss_latency_sum), 0)";
+
+fn accumulator_from_row(row: &Row) -> (Uuid, StatsAccumulator) {
+    (
+        row.get(0),
+        StatsAccumulator {
+            checks: row.get::<_, i64>(1) as u64,
+            tcp_up: row.get::<_, i64>(2) as u64,
+            ss_checks: row.get::<_, i64>(3) as u64,
+            ss_ok: row.get::<_, i64>(4) as u64,
+            latency_count: row.get::<_, i64>(5) as u64,
+            latency_sum: row.get(6),
+            latency_min: row.get(7),
+            latency_max: row.get(8),
+            ss_latency_count: row.get::<_, i64>(9) as u64,
+            ss_latency_sum: row.get(10),
+        },
+    )
+}
+
+/// Start of the first bucket not yet rolled up, if rollups have started
+async fn rollup_watermark(
+    client: &impl GenericClient,
+    rollup: Rollup,
+) -> Result<Option<DateTime<Utc>>> {
+    let row = client
+        .query_opt(
+            "SELECT next_bucket FROM rollup_state WHERE name = $1",
+            &[&rollup.key()],
+        )
+        .await?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+/// Start of the oldest bucket still stored in a rollup table
+async fn earliest_bucket(
+    client: &impl GenericClient,
+    rollup: Rollup,
+) -> Result<Option<DateTime<Utc>>> {
+    let row = client
+        .query_one(&format!("SELECT MIN(bucket) FROM {}", rollup.table()), &[])
+        .await?;
+    Ok(row.get(0))
+}
+
+/// Raw rows before this have been rolled up and may be deleted
+async fn rolled_up_watermark(client: &impl GenericClient) -> Result<Option<DateTime<Utc>>> {
+    Ok(rolled_up_until(
+        rollup_watermark(client, Rollup::Hourly).await?,
+        rollup_watermark(client, Rollup::Daily).await?,
+    ))
+}
+
+/// Insert `s`, or overwrite the stored server with its id
+async fn upsert_server(client: &impl GenericClient, s: &Server) -> Result<()> {
+    client
+        .execute(
+            "INSERT INTO servers
+                (id, name, host, port, password, method, enabled, tags,
+                 visibility, display_name, group_name, retention_days, check_overrides,
+                 source, plugin, plugin_opts, identity_keys, users, disabled_by_source)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16,
+                     $17, $18, $19)
+             ON CONFLICT (id) DO UPDATE SET
+                name = EXCLUDED.name,
+                host = EXCLUDED.host,
+                port = EXCLUDED.port,
+                password = EXCLUDED.password,
+                method = EXCLUDED.method,
+                enabled = EXCLUDED.enabled,
+                tags = EXCLUDED.tags,
+                visibility = EXCLUDED.visibility,
+                display_name = EXCLUDED.display_name,
+                group_name = EXCLUDED.group_name,
+                retention_days = EXCLUDED.retention_days,
+                check_overrides = EXCLUDED.check_overrides,
+                source = EXCLUDED.source,
+                plugin = EXCLUDED.plugin,
+                plugin_opts = EXCLUDED.plugin_opts,
+                identity_keys = EXCLUDED.identity_keys,
+                users = EXCLUDED.users,
+                disabled_by_source = EXCLUDED.disabled_by_source",
+            &[
+                &s.id,
+                &s.name,
+                &s.host,
+                &i32::from(s.port),
+                &s.password,
+                &s.method,
+                &s.enabled,
+                &s.tags,
+                &s.visibility.as_str(),
+                &s.display_name,
+                &s.group,
+                &s.retention_days.map(|d| d as i32),
+                &serde_json::to_string(&s.checks)?,
+                &s.source,
+                &s.plugin,
+                &s.plugin_opts,
+                &s.identity_keys,
+                &serde_json::to_string(&s.users)?,
+                &s.disabled_by_source,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// TCP check samples of a server since $2, for `latency_summary`
+const TCP_SAMPLES: &str = "SELECT id, timestamp, tcp_reachable AS ok, tcp_latency_ms AS latency
+     FROM check_results
+     WHERE server_id = $1 AND timestamp >= $2";
+
+/// SS check samples of a server since $2, leaving out the ones skipped
+/// because TCP was down
+const SS_SAMPLES: &str = "SELECT id, timestamp, ss_success AS ok, ss_latency_ms AS latency
+     FROM check_results
+     WHERE server_id = $1 AND timestamp >= $2
+       AND tcp_reachable AND ss_success IS NOT NULL";
+
+impl PgStorage {
+    /// Reduce the `samples` query (`id, timestamp, ok, latency`) to latency
+    /// stats without loading the rows; percentile_cont interpolates linearly
+    /// like `stats::percentile`
+    async fn latency_summary(
+        &self,
+        samples: &str,
+        server_id: Uuid,
+        since: DateTime<Utc>,
+    ) -> Result<LatencySummary> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_one(
+                &format!(
+                    "WITH s AS ({samples}),
+                     l AS (
+                        SELECT latency,
+                               latency - LAG(latency) OVER (ORDER BY timestamp, id) AS diff,
+                               latency - AVG(latency) OVER () AS dev
+                        FROM s WHERE ok AND latency IS NOT NULL
+                     )
+                     SELECT (SELECT COUNT(*) FROM s), (SELECT COUNT(*) FROM s WHERE NOT ok),
+                            COUNT(*), AVG(latency), AVG(dev * dev), AVG(ABS(diff)),
+                            percentile_cont(ARRAY[0.5, 0.9, 0.99]::float8[])
+                                WITHIN GROUP (ORDER BY latency)
+                     FROM l"
+                ),
+                &[&server_id, &since],
+            )
+            .await?;
+        let percentiles: Option<Vec<f64>> = row.get(6);
+        let p = |i: usize| percentiles.as_ref().and_then(|ps| ps.get(i).copied());
+        Ok(LatencySummary {
+            attempts: row.get::<_, i64>(0) as u64,
+            failures: row.get::<_, i64>(1) as u64,
+            samples: row.get::<_, i64>(2) as u64,
+            mean: row.get(3),
+            variance: row.get(4),
+            jitter: row.get(5),
+            p50: p(0),
+            p90: p(1),
+            p99: p(2),
+        })
+    }
+
+    /// Aggregate stats since `since`, optionally for a single server.
+    /// Complete buckets that have been rolled up are read from the rollup tables
+    /// (daily past the hourly horizon, hourly after that), the rest from raw rows,
+    /// so windows longer than raw or hourly retention still work.
+    async fn aggregate_since(
+        &self,
+        server_id: Option<Uuid>,
+        since: DateTime<Utc>,
+    ) -> Result<HashMap<Uuid, StatsAccumulator>> {
+        let client = self.pool.get().await?;
+        let split = WindowSplit::new(
+            since,
+            rollup_watermark(&client, Rollup::Hourly).await?,
+            earliest_bucket(&client, Rollup::Hourly).await?,
+            rollup_watermark(&client, Rollup::Daily).await?,
+        );
+
+        let mut totals: HashMap<Uuid, StatsAccumulator> = HashMap::new();
+        let ranges = [
+            (Rollup::Daily, split.daily_until.map(|until| (since, until))),
+            (Rollup::Hourly, split.hourly()),
+        ];
+        for (rollup, range) in ranges {
+            let Some((from, until)) = range else {
+                continue;
+            };
+            let rows = client
+                .query(
+                    &format!(
+                        "SELECT {ROLLUP_STATS_COLUMNS} FROM {}
+                         WHERE bucket >= $1 AND bucket < $2
+                           AND ($3::uuid IS NULL OR server_id = $3)
+                         GROUP BY server_id",
+                        rollup.table()
+                    ),
+                    &[&from, &until, &server_id],
+                )
+                .await?;
+            for row in &rows {
+                let (sid, acc) = accumulator_from_row(row);
+                totals.entry(sid).or_default().merge(&acc);
+            }
+        }
+
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {RAW_STATS_COLUMNS} FROM check_results
+                     WHERE timestamp >= $1
+                       AND ($2::uuid IS NULL OR server_id = $2)
+                     GROUP BY server_id"
+                ),
+                &[&split.raw_since(), &server_id],
+            )
+            .await?;
+        for row in &rows {
+            let (sid, acc) = accumulator_from_row(row);
+            totals.entry(sid).or_default().merge(&acc);
+        }
+
+        Ok(totals)
+    }
+}
+
+#[async_trait]
+impl Storage for PgStorage {
+    fn backend(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn is_results_writer(&self) -> bool {
+        let mut lock = self.writer.lock().await;
+        let connected = lock.client.as_ref().is_some_and(|c| !c.is_closed());
+        if lock.held {
+            if connected {
+                return true;
+            }
+            tracing::warn!("Lost the results writer lock; trying to take it back");
+            lock.held = false;
+        }
+        match self.claim_writer(&mut lock).await {
+            Ok(true) => {
+                tracing::info!("This instance now stores check results");
+                lock.held = true;
+                lock.standing_by = false;
+                true
+            }
+            Ok(false) => {
+                if !lock.standing_by {
+                    tracing::info!("Another instance is storing check results; this one stands by");
+                    lock.standing_by = true;
+                }
+                false
+            }
+            Err(e) => {
+                tracing::warn!("Failed to claim the results writer lock: {}", e);
+                false
+            }
+        }
+    }
+
+    async fn insert_results(&self, results: &[CheckResult]) -> Result<()> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let stmt = tx
+            .prepare(
+                "INSERT INTO check_results
+                    (server_id, timestamp, tcp_reachable, tcp_latency_ms, tcp_error,
+                     ss_success, ss_latency_ms, ss_error, ss_error_kind, user_checks)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            )
+            .await?;
+        for result in results {
+            tx.execute(
+                &stmt,
+                &[
+                    &result.server_id,
+                    &result.timestamp,
+                    &result.tcp_check.reachable,
+                    &result.tcp_check.latency_ms,
+                    &result.tcp_check.error,
+                    &result.ss_check.as_ref().map(|s| s.success),
+                    &result.ss_check.as_ref().and_then(|s| s.latency_ms),
+                    &result.ss_check.as_ref().and_then(|s| s.error.clone()),
+                    &result
+                        .ss_check
+                        .as_ref()
+                        .and_then(|s| s.error_kind)
+                        .map(SsErrorKind::as_str),
+                    &user_checks_json(result)?,
+                ],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn load_results_for_server(
+        &self,
+        server_id: Uuid,
+        limit: usize,
+    ) -> Result<Vec<CheckResult>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {RESULT_COLUMNS}
+                     FROM check_results
+                     WHERE server_id = $1
+                     ORDER BY timestamp DESC
+                     LIMIT $2"
+                ),
+                &[&server_id, &(limit as i64)],
+            )
+            .await?;
+        Ok(rows.iter().map(result_from_row).collect())
+    }
+
+    async fn count_results_all(&self) -> Result<HashMap<Uuid, u64>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT server_id, COUNT(*) FROM check_results GROUP BY server_id",
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get::<_, Uuid>(0), row.get::<_, i64>(1) as u64))
+            .collect())
+    }
+
+    async fn query_results(&self, query: &ResultQuery) -> Result<Vec<(i64, CheckResult)>> {
+        let filter_clause = match query.filter {
+            None => "",
+            Some(ResultFilter::Failures) => {
+                "AND (NOT tcp_reachable OR ss_success = FALSE
+                      OR EXISTS (SELECT 1 FROM json_array_elements(user_checks::json) u
+                                 WHERE (u->>'success')::boolean = FALSE))"
+            }
+            Some(ResultFilter::SsFailures) => {
+                "AND tcp_reachable
+                 AND (ss_success = FALSE
+                      OR EXISTS (SELECT 1 FROM json_array_elements(user_checks::json) u
+                                 WHERE (u->>'success')::boolean = FALSE))"
+            }
+        };
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                &format!(
+                    "SELECT {RESULT_COLUMNS}, id
+                     FROM check_results
+                     WHERE server_id = $1
+                       AND ($2::timestamptz IS NULL OR timestamp >= $2)
+                       AND ($3::timestamptz IS NULL OR timestamp < $3)
+                       AND ($4::bigint IS NULL OR id < $4)
+                       {filter_clause}
+                     ORDER BY id DESC
+                     LIMIT $5"
+                ),
+                &[
+                    &query.server_id,
+                    &query.from,
+                    &query.to,
+                    &query.before_id,
+                    &(query.limit as i64),
+                ],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get(10), result_from_row(row)))
+            .collect())
+    }
+
+    async fn export_batch(
+        &self,
+        server_id: Option<Uuid>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        after_id: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, CheckResult)>> {
+        let client = self.pool.get().await?;
+        let stmt = client
+            .prepare_cached(&format!(
+                "SELECT {RESULT_COLUMNS}, id
+                 FROM check_results
+                 WHERE id > $1
+                   AND ($2::uuid IS NULL OR server_id = $2)
+                   AND ($3::timestamptz IS NULL OR timestamp >= $3)
+                   AND ($4::timestamptz IS NULL OR timestamp < $4)
+                 ORDER BY id ASC
+                 LIMIT $5"
+            ))
+            .await?;
+        let rows = client
+            .query(&stmt, &[&after_id, &server_id, &from, &to, &(limit as i64)])
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get(10), result_from_row(row)))
+            .collect())
+    }
+
+    async fn latency_stats_since(
+        &self,
+        server_id: Uuid,
+        since: DateTime<Utc>,
+    ) -> Result<(LatencyStats, LatencyStats)> {
+        Ok((
+            self.latency_summary(TCP_SAMPLES, server_id, since)
+                .await?
+                .finish(),
+            self.latency_summary(SS_SAMPLES, server_id, since)
+                .await?
+                .finish(),
+        ))
+    }
+
+    async fn uptime_stats(&self, server_id: Uuid, window: StatsWindow) -> Result<UptimeStats> {
+        let since = Utc::now() - window.duration();
+        let totals = self.aggregate_since(Some(server_id), since).await?;
+        Ok(totals
+            .get(&server_id)
+            .map(|acc| acc.finish(window))
+            .unwrap_or_else(|| UptimeStats::empty(window)))
+    }
+
+    async fn uptime_stats_all(&self, window: StatsWindow) -> Result<HashMap<Uuid, UptimeStats>> {
+        let since = Utc::now() - window.duration();
+        let totals = self.aggregate_since(None, since).await?;
+        Ok(totals
+            .into_iter()
+            .map(|(sid, acc)| (sid, acc.finish(window)))
+            .collect())
+    }
+
+    async fn update_rollups(&self, rollup: Rollup) -> Result<usize> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&ROLLUP_LOCK])
+            .await?;
+
+        let next = match rollup_watermark(&tx, rollup).await? {
+            Some(next) => next,
+            None => {
+                let earliest: Option<DateTime<Utc>> = tx
+                    .query_one("SELECT MIN(timestamp) FROM check_results", &[])
+                    .await?
+                    .get(0);
+                let Some(earliest) = earliest else {
+                    return Ok(0);
+                };
+                rollup.floor(earliest)
+            }
+        };
+        let end = rollup.floor(Utc::now());
+        if next >= end {
+            return Ok(0);
+        }
+
+        // Bucket start computed the same way as `Rollup::floor`; percentile_cont
+        // interpolates linearly like `stats::percentile`
+        let bucket_secs = rollup.bucket_len().num_seconds() as f64;
+        tx.execute(
+            &format!(
+                "INSERT INTO {}
+                    (server_id, bucket, checks, tcp_up, ss_checks, ss_ok,
+                     latency_count, latency_sum, latency_min, latency_avg,
+                     latency_p50, latency_p95, latency_max,
+                     ss_latency_count, ss_latency_sum)
+                 SELECT server_id,
+                        to_timestamp(floor(extract(epoch FROM timestamp)::float8 / $3) * $3),
+                        COUNT(*),
+                        COUNT(*) FILTER (WHERE tcp_reachable),
+                        COUNT(ss_success) FILTER (WHERE tcp_reachable),
+                        COUNT(*) FILTER (WHERE tcp_reachable AND ss_success),
+                        COUNT(tcp_latency_ms),
+                        COALESCE(SUM(tcp_latency_ms), 0),
+                        MIN(tcp_latency_ms),
+                        AVG(tcp_latency_ms),
+                        percentile_cont(0.5) WITHIN GROUP (ORDER BY tcp_latency_ms),
+                        percentile_cont(0.95) WITHIN GROUP (ORDER BY tcp_latency_ms),
+                        MAX(tcp_latency_ms),
+                        COUNT(ss_latency_ms) FILTER (WHERE tcp_reachable AND ss_success),
+                        COALESCE(SUM(ss_latency_ms) FILTER (WHERE tcp_reachable AND ss_success), 0)
+                 FROM check_results
+                 WHERE timestamp >= $1 AND timestamp < $2
+                 GROUP BY 1, 2
+                 ON CONFLICT (server_id, bucket) DO UPDATE SET
+                    checks = EXCLUDED.checks,
+                    tcp_up = EXCLUDED.tcp_up,
+                    ss_checks = EXCLUDED.ss_checks,
+                    ss_ok = EXCLUDED.ss_ok,
+                    latency_count = EXCLUDED.latency_count,
+                    latency_sum = EXCLUDED.latency_sum,
+                    latency_min = EXCLUDED.latency_min,
+                    latency_avg = EXCLUDED.latency_avg,
+                    latency_p50 = EXCLUDED.latency_p50,
+                    latency_p95 = EXCLUDED.latency_p95,
+                    latency_max = EXCLUDED.latency_max,
+                    ss_latency_count = EXCLUDED.ss_latency_count,
+                    ss_latency_sum = EXCLUDED.ss_latency_sum",
+                rollup.table()
+            ),
+            &[&next, &end, &bucket_secs],
+        )
+        .await?;
+        tx.execute(
+            "INSERT INTO rollup_state (name, next_bucket) VALUES ($1, $2)
+             ON CONFLICT (name) DO UPDATE SET next_bucket = EXCLUDED.next_bucket",
+            &[&rollup.key(), &end],
+        )
+        .await?;
+        tx.commit().await?;
+
+        let buckets = (end - next).num_seconds() / rollup.bucket_len().num_seconds();
+        Ok(buckets as usize)
+    }
+
+    async fn cleanup_rollups(&self, rollup: Rollup, keep_days: i64) -> Result<usize> {
+        let cutoff = Utc::now() - Duration::days(keep_days);
+        let client = self.pool.get().await?;
+        let deleted = client
+            .execute(
+                &format!("DELETE FROM {} WHERE bucket < $1", rollup.table()),
+                &[&cutoff],
+            )
+            .await?;
+        Ok(deleted as usize)
+    }
+
+    async fn cleanup_old(&self, keep_days: i64, overrides: &[(Uuid, i64)]) -> Result<usize> {
+        let client = self.pool.get().await?;
+        let Some(rolled) = rolled_up_watermark(&client).await? else {
+            return Ok(0);
+        };
+        let now = Utc::now();
+        let cutoff = |days: i64| (now - Duration::days(days)).min(rolled);
+        let overridden: Vec<Uuid> = overrides.iter().map(|(id, _)| *id).collect();
+        let mut deleted = client
+            .execute(
+                "DELETE FROM check_results
+                 WHERE timestamp < $1
+                   AND NOT (server_id = ANY($2))",
+                &[&cutoff(keep_days), &overridden],
+            )
+            .await?;
+        for (id, days) in overrides {
+            deleted += client
+                .execute(
+                    "DELETE FROM check_results WHERE server_id = $1 AND timestamp < $2",
+                    &[id, &cutoff(*days)],
+                )
+                .await?;
+        }
+        Ok(deleted as usize)
+    }
+
+    async fn trim_results_per_server(&self, max_rows: u32) -> Result<usize> {
+        let client = self.pool.get().await?;
+        let Some(rolled) = rolled_up_watermark(&client).await? else {
+            return Ok(0);
+        };
+        let deleted = client
+            .execute(
+                "DELETE FROM check_results WHERE id IN (
+                    SELECT id FROM (
+                        SELECT id, timestamp, ROW_NUMBER() OVER (
+                            PARTITION BY server_id ORDER BY id DESC
+                        ) AS rn
+                        FROM check_results
+                    ) ranked WHERE rn > $1 AND timestamp < $2
+                )",
+                &[&i64::from(max_rows), &rolled],
+            )
+            .await?;
+        Ok(deleted as usize)
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        // Left to autovacuum
+        Ok(())
+    }
+
+    async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let client = self.pool.get().await?;
+        let row = client
+            .query_opt("SELECT value FROM settings WHERE key = $1", &[&key])
+            .await?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    async fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO settings (key, value) VALUES ($1, $2)
+                 ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value",
+                &[&key, &value],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn load_servers(&self) -> Result<Vec<Server>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT id, name, host, port, password, method, enabled, tags,
+                        visibility, display_name, group_name, retention_days,
+                        check_overrides, source, plugin, plugin_opts, identity_keys, users,
+                        disabled_by_source
+                 FROM servers",
+                &[],
+            )
+            .await?;
+        Ok(rows.iter().map(server_from_row).collect())
+    }
+
+    async fn upsert_server(&self, server: &Server) -> Result<()> {
+        let client = self.pool.get().await?;
+        upsert_server(&client, server).await
+    }
+
+    async fn delete_server(&self, id: Uuid) -> Result<()> {
+        let client = self.pool.get().await?;
+        client
+            .execute("DELETE FROM servers WHERE id = $1", &[&id])
+            .await?;
+        Ok(())
+    }
+
+    async fn import_servers_once(&self, servers: &[Server]) -> Result<usize> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        // Claiming the marker row decides which instance imports
+        let claimed = tx
+            .execute(
+                "INSERT INTO settings (key, value) VALUES ($1, $2)
+                 ON CONFLICT (key) DO NOTHING",
+                &[&SETTING_SERVERS_IMPORTED, &Utc::now().to_rfc3339()],
+            )
+            .await?;
+        if claimed == 0 {
+            return Ok(0);
+        }
+        let existing: i64 = tx
+            .query_one("SELECT COUNT(*) FROM servers", &[])
+            .await?
+            .get(0);
+        let imported = if existing == 0 {
+            for s in servers {
+                upsert_server(&tx, s).await?;
+            }
+            servers.len()
+        } else {
+            0
+        };
+        tx.commit().await?;
+        Ok(imported)
+    }
+
+    async fn insert_sync_log(&self, entry: &SyncLogEntry) -> Result<()> {
+        let client = self.pool.get().await?;
+        client
+            .execute(
+                "INSERT INTO sync_log
+                    (subscription, timestamp, added, updated, disabled, duplicates, invalid, error)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[
+                    &entry.subscription,
+                    &entry.timestamp,
+                    &(entry.added as i32),
+                    &(entry.updated as i32),
+                    &(entry.disabled as i32),
+                    &(entry.duplicates as i32),
+                    &(entry.invalid as i32),
+                    &entry.error,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn load_sync_log(
+        &self,
+        subscription: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SyncLogEntry>> {
+        let client = self.pool.get().await?;
+        let rows = client
+            .query(
+                "SELECT subscription, timestamp, added, updated, disabled, duplicates,
+                        invalid, error
+                 FROM sync_log
+                 WHERE $1::TEXT IS NULL OR subscription = $1
+                 ORDER BY id DESC LIMIT $2",
+                &[&subscription, &(limit as i64)],
+            )
+            .await?;
+        Ok(rows.iter().map(sync_log_from_row).collect())
+    }
+
+    async fn cleanup_sync_log(&self, keep_days: i64) -> Result<usize> {
+        let cutoff = Utc::now() - Duration::days(keep_days);
+        let client = self.pool.get().await?;
+        let deleted = client
+            .execute("DELETE FROM sync_log WHERE timestamp < $1", &[&cutoff])
+            .await?;
+        Ok(deleted as usize)
+    }
+
+    async fn close(&self) -> Result<()> {
+        // Closing the connection hands the lock to a standby right away
+        self.writer.lock().await.client.take();
+        self.pool.close();
+        Ok(())
+    }
+}
+
+/// TLS for connections that `sslmode` lets use it
+fn tls_connector() -> Result<MakeRustlsConnect> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let native = rustls_native_certs::load_native_certs();
+    for e in &native.errors {
+        tracing::warn!("Failed to load system root certificates: {}", e);
+    }
+    roots.add_parsable_certificates(native.certs);
+
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    Ok(MakeRustlsConnect::new(config))
+}
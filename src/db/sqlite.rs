@@ -0,0 +1,1616 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::{
+    rolled_up_until, user_checks_json, LatencySummary, ResultQuery, Rollup, StatsAccumulator,
+    Storage, WindowSplit, SETTING_SERVERS_IMPORTED,
+};
+use crate::models::{
+    CheckResult, LatencyStats, ResultFilter, Server, SsCheckResult, SsErrorKind, StatsWindow,
+    SyncLogEntry, TcpCheckResult, UptimeStats, Visibility,
+};
+use crate::stats::percentile;
+
+/// Schema migrations, applied in order. Migration `n` (1-based) brings the
+/// database to `user_version = n`; never edit one that has shipped, append
+/// a new one instead.
+///
+/// The first two use `IF NOT EXISTS` because databases created before
+/// versioning was introduced already contain some of these objects.
+const MIGRATIONS: &[&str] = &[
+    // 1: raw check results
+    "CREATE TABLE IF NOT EXISTS check_results (
+        id          INTEGER PRIMARY KEY AUTOINCREMENT,
+        server_id   TEXT    NOT NULL,
+        timestamp   TEXT    NOT NULL,
+        tcp_reachable INTEGER NOT NULL,
+        tcp_latency_ms REAL,
+        tcp_error   TEXT,
+        ss_success  INTEGER,
+        ss_latency_ms REAL,
+        ss_error    TEXT
+    );
+    CREATE INDEX IF NOT EXISTS idx_results_server_time
+        ON check_results(server_id, timestamp DESC);",
+    // 2: time index and hourly/daily rollups
+    "CREATE INDEX IF NOT EXISTS idx_results_time
+        ON check_results(timestamp);
+    CREATE TABLE IF NOT EXISTS check_rollups_hourly (
+        server_id        TEXT    NOT NULL,
+        bucket           TEXT    NOT NULL,
+        checks           INTEGER NOT NULL,
+        tcp_up           INTEGER NOT NULL,
+        ss_checks        INTEGER NOT NULL,
+        ss_ok            INTEGER NOT NULL,
+        latency_count    INTEGER NOT NULL,
+        latency_sum      REAL    NOT NULL,
+        latency_min      REAL,
+        latency_avg      REAL,
+        latency_p50      REAL,
+        latency_p95      REAL,
+        latency_max      REAL,
+        ss_latency_count INTEGER NOT NULL,
+        ss_latency_sum   REAL    NOT NULL,
+        PRIMARY KEY (server_id, bucket)
+    );
+    CREATE INDEX IF NOT EXISTS idx_check_rollups_hourly_bucket
+        ON check_rollups_hourly(bucket);
+    CREATE TABLE IF NOT EXISTS check_rollups_daily (
+        server_id        TEXT    NOT NULL,
+        bucket           TEXT    NOT NULL,
+        checks           INTEGER NOT NULL,
+        tcp_up           INTEGER NOT NULL,
+        ss_checks        INTEGER NOT NULL,
+        ss_ok            INTEGER NOT NULL,
+        latency_count    INTEGER NOT NULL,
+        latency_sum      REAL    NOT NULL,
+        latency_min      REAL,
+        latency_avg      REAL,
+        latency_p50      REAL,
+        latency_p95      REAL,
+        latency_max      REAL,
+        ss_latency_count INTEGER NOT NULL,
+        ss_latency_sum   REAL    NOT NULL,
+        PRIMARY KEY (server_id, bucket)
+    );
+    CREATE INDEX IF NOT EXISTS idx_check_rollups_daily_bucket
+        ON check_rollups_daily(bucket);
+    CREATE TABLE IF NOT EXISTS rollup_state (
+        name        TEXT PRIMARY KEY,
+        next_bucket TEXT NOT NULL
+    );",
+    // 3: servers and settings for `server_store: database`
+    "CREATE TABLE servers (
+        id             TEXT    PRIMARY KEY,
+        name           TEXT    NOT NULL,
+        host           TEXT    NOT NULL,
+        port           INTEGER NOT NULL,
+        password       TEXT    NOT NULL,
+        method         TEXT    NOT NULL,
+        enabled        INTEGER NOT NULL,
+        tags           TEXT    NOT NULL,
+        visibility     TEXT    NOT NULL,
+        display_name   TEXT,
+        group_name     TEXT,
+        retention_days INTEGER
+    );
+    CREATE TABLE settings (
+        key   TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );",
+    // 4: per-server check overrides, as JSON
+    "ALTER TABLE servers ADD COLUMN check_overrides TEXT NOT NULL DEFAULT '{}';",
+    // 5: subscription sources, and whether a sync disabled the server
+    "ALTER TABLE servers ADD COLUMN source TEXT;
+    ALTER TABLE servers ADD COLUMN disabled_by_source INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE sync_log (
+        id           INTEGER PRIMARY KEY AUTOINCREMENT,
+        subscription TEXT    NOT NULL,
+        timestamp    TEXT    NOT NULL,
+        added        INTEGER NOT NULL,
+        updated      INTEGER NOT NULL,
+        disabled     INTEGER NOT NULL,
+        duplicates   INTEGER NOT NULL,
+        invalid      INTEGER NOT NULL,
+        error        TEXT
+    );
+    CREATE INDEX idx_sync_log_time ON sync_log(timestamp);",
+    // 6: SIP003 plugins and the cause of failed SS checks
+    "ALTER TABLE servers ADD COLUMN plugin TEXT;
+    ALTER TABLE servers ADD COLUMN plugin_opts TEXT;
+    ALTER TABLE check_results ADD COLUMN ss_error_kind TEXT;",
+    // 7: Shadowsocks 2022 identity keys, users and per-user results, as JSON
+    "ALTER TABLE servers ADD COLUMN identity_keys TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE servers ADD COLUMN users TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE check_results ADD COLUMN user_checks TEXT;",
+    // 8: SS latency was rolled up over failed checks too. Recount it for
+    // every bucket whose raw rows are all still there, which is every bucket
+    // from the one after a server's oldest raw row.
+    "UPDATE check_rollups_hourly AS b SET (ss_latency_count, ss_latency_sum) = (
+        SELECT COUNT(ss_latency_ms), COALESCE(SUM(ss_latency_ms), 0)
+        FROM check_results r
+        WHERE r.server_id = b.server_id
+          AND r.timestamp >= b.bucket
+          AND r.timestamp < strftime('%Y-%m-%dT%H:%M:%S+00:00', b.bucket, '+1 hour')
+          AND r.tcp_reachable = 1 AND r.ss_success = 1
+    )
+    WHERE b.bucket >= (
+        SELECT strftime('%Y-%m-%dT%H:00:00+00:00', MIN(r.timestamp), '+1 hour')
+        FROM check_results r WHERE r.server_id = b.server_id
+    );
+    UPDATE check_rollups_daily AS b SET (ss_latency_count, ss_latency_sum) = (
+        SELECT COUNT(ss_latency_ms), COALESCE(SUM(ss_latency_ms), 0)
+        FROM check_results r
+        WHERE r.server_id = b.server_id
+          AND r.timestamp >= b.bucket
+          AND r.timestamp < strftime('%Y-%m-%dT%H:%M:%S+00:00', b.bucket, '+1 day')
+          AND r.tcp_reachable = 1 AND r.ss_success = 1
+    )
+    WHERE b.bucket >= (
+        SELECT strftime('%Y-%m-%dT00:00:00+00:00', MIN(r.timestamp), '+1 day')
+        FROM check_results r WHERE r.server_id = b.server_id
+    );",
+];
+
+/// Schema version this build expects
+const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Embedded single-file backend; one connection shared behind a mutex and
+/// only ever used from the blocking thread pool
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        // auto_vacuum only takes effect on an empty file or after a full VACUUM.
+        // A full VACUUM of a large history takes long and needs twice the disk
+        // space, so older databases only get it from `enable_auto_vacuum`.
+        if auto_vacuum(&conn)? != AUTO_VACUUM_INCREMENTAL {
+            let tables: i64 =
+                conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))?;
+            if tables == 0 {
+                conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL;")?;
+            } else {
+                tracing::info!(
+                    "Incremental auto-vacuum is off for {}, so cleanup does not shrink the \
+                     file; run with --migrate-only once to enable it",
+                    path
+                );
+            }
+        }
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Turn on incremental auto-vacuum for an existing database, rebuilding
+    /// the file with a full VACUUM if it is not on yet
+    pub fn enable_auto_vacuum(path: &str) -> Result<()> {
+        let conn = Connection::open(path)?;
+        if auto_vacuum(&conn)? != AUTO_VACUUM_INCREMENTAL {
+            tracing::info!("Enabling incremental auto-vacuum on {}", path);
+            conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")?;
+        }
+        Ok(())
+    }
+
+    /// Run `f` on the blocking thread pool so queries never stall the runtime
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let conn = conn
+                .lock()
+                .map_err(|_| anyhow::anyhow!("SQLite connection mutex poisoned"))?;
+            f(&conn)
+        })
+        .await?
+    }
+}
+
+/// `PRAGMA auto_vacuum` value for incremental mode
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+fn auto_vacuum(conn: &Connection) -> Result<i64> {
+    Ok(conn.query_row("PRAGMA auto_vacuum", [], |row| row.get(0))?)
+}
+
+/// Bring the schema up to `SCHEMA_VERSION`, one transaction per migration.
+/// Refuses to open a database written by a newer build.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let current: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if current > SCHEMA_VERSION {
+        anyhow::bail!(
+            "Database schema version {} is newer than this build supports ({}); \
+             upgrade sserver-status or restore a backup taken before the upgrade",
+            current,
+            SCHEMA_VERSION
+        );
+    }
+
+    for (idx, sql) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = idx as u32 + 1;
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)
+            .map_err(|e| anyhow::anyhow!("Migration {} failed: {}", version, e))?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+        tracing::info!("Applied database migration {}", version);
+    }
+    Ok(())
+}
+
+fn get_setting(conn: &Connection, key: &str) -> Result<Option<String>> {
+    let value = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(value)
+}
+
+fn set_setting(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+fn load_servers(conn: &Connection) -> Result<Vec<Server>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, name, host, port, password, method, enabled, tags,
+                visibility, display_name, group_name, retention_days, check_overrides,
+                source, plugin, plugin_opts, identity_keys, users, disabled_by_source
+         FROM servers",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let id: String = row.get(0)?;
+        let tags: String = row.get(7)?;
+        let visibility: String = row.get(8)?;
+        let checks: String = row.get(12)?;
+        let identity_keys: String = row.get(16)?;
+        let users: String = row.get(17)?;
+        Ok(Server {
+            id: id.parse().unwrap_or_default(),
+            name: row.get(1)?,
+            host: row.get(2)?,
+            port: row.get(3)?,
+            password: row.get(4)?,
+            method: row.get(5)?,
+            enabled: row.get::<_, i32>(6)? != 0,
+            tags: serde_json::from_str(&tags).unwrap_or_default(),
+            visibility: Visibility::parse(&visibility).unwrap_or_default(),
+            display_name: row.get(9)?,
+            group: row.get(10)?,
+            retention_days: row.get(11)?,
+            checks: serde_json::from_str(&checks).unwrap_or_default(),
+            source: row.get(13)?,
+            plugin: row.get(14)?,
+            plugin_opts: row.get(15)?,
+            identity_keys: serde_json::from_str(&identity_keys).unwrap_or_default(),
+            users: serde_json::from_str(&users).unwrap_or_default(),
+            disabled_by_source: row.get::<_, i32>(18)? != 0,
+        })
+    })?;
+
+    let mut servers = Vec::new();
+    for row in rows {
+        servers.push(row?);
+    }
+    Ok(servers)
+}
+
+/// Insert `s`, or overwrite the stored server with its id
+fn upsert_server(conn: &Connection, s: &Server) -> Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT OR REPLACE INTO servers
+            (id, name, host, port, password, method, enabled, tags,
+             visibility, display_name, group_name, retention_days, check_overrides,
+             source, plugin, plugin_opts, identity_keys, users, disabled_by_source)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                 ?17, ?18, ?19)",
+    )?;
+    stmt.execute(params![
+        s.id.to_string(),
+        s.name,
+        s.host,
+        s.port,
+        s.password,
+        s.method,
+        s.enabled as i32,
+        serde_json::to_string(&s.tags)?,
+        s.visibility.as_str(),
+        s.display_name,
+        s.group,
+        s.retention_days,
+        serde_json::to_string(&s.checks)?,
+        s.source,
+        s.plugin,
+        s.plugin_opts,
+        serde_json::to_string(&s.identity_keys)?,
+        serde_json::to_string(&s.users)?,
+        s.disabled_by_source as i32,
+    ])?;
+    Ok(())
+}
+
+fn delete_server(conn: &Connection, id: Uuid) -> Result<()> {
+    conn.execute("DELETE FROM servers WHERE id = ?1", params![id.to_string()])?;
+    Ok(())
+}
+
+/// Copy `servers` into the database the first time it is used in database
+/// mode. Returns how many were imported; later calls are no-ops, so deleting
+/// every server through the API does not bring the YAML ones back.
+fn import_servers_once(conn: &Connection, servers: &[Server]) -> Result<usize> {
+    if get_setting(conn, SETTING_SERVERS_IMPORTED)?.is_some() {
+        return Ok(0);
+    }
+    let existing: i64 = conn.query_row("SELECT COUNT(*) FROM servers", [], |row| row.get(0))?;
+    let imported = if existing == 0 {
+        let tx = conn.unchecked_transaction()?;
+        for s in servers {
+            upsert_server(&tx, s)?;
+        }
+        tx.commit()?;
+        servers.len()
+    } else {
+        0
+    };
+    set_setting(conn, SETTING_SERVERS_IMPORTED, &Utc::now().to_rfc3339())?;
+    Ok(imported)
+}
+
+fn insert_sync_log(conn: &Connection, entry: &SyncLogEntry) -> Result<()> {
+    conn.execute(
+        "INSERT INTO sync_log
+            (subscription, timestamp, added, updated, disabled, duplicates, invalid, error)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            entry.subscription,
+            entry.timestamp.to_rfc3339(),
+            entry.added,
+            entry.updated,
+            entry.disabled,
+            entry.duplicates,
+            entry.invalid,
+            entry.error,
+        ],
+    )?;
+    Ok(())
+}
+
+fn load_sync_log(
+    conn: &Connection,
+    subscription: Option<&str>,
+    limit: usize,
+) -> Result<Vec<SyncLogEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT subscription, timestamp, added, updated, disabled, duplicates, invalid, error
+         FROM sync_log
+         WHERE ?1 IS NULL OR subscription = ?1
+         ORDER BY id DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(params![subscription, limit as i64], |row| {
+        let ts: String = row.get(1)?;
+        Ok(SyncLogEntry {
+            subscription: row.get(0)?,
+            timestamp: DateTime::parse_from_rfc3339(&ts)
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+            added: row.get(2)?,
+            updated: row.get(3)?,
+            disabled: row.get(4)?,
+            duplicates: row.get(5)?,
+            invalid: row.get(6)?,
+            error: row.get(7)?,
+        })
+    })?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        entries.push(row?);
+    }
+    Ok(entries)
+}
+
+fn cleanup_sync_log(conn: &Connection, keep_days: i64) -> Result<usize> {
+    let cutoff = Utc::now() - Duration::days(keep_days);
+    let deleted = conn.execute(
+        "DELETE FROM sync_log WHERE timestamp < ?1",
+        params![cutoff.to_rfc3339()],
+    )?;
+    Ok(deleted)
+}
+
+fn insert_results(conn: &Connection, results: &[CheckResult]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO check_results
+                (server_id, timestamp, tcp_reachable, tcp_latency_ms, tcp_error,
+                 ss_success, ss_latency_ms, ss_error, ss_error_kind, user_checks)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        )?;
+        for result in results {
+            stmt.execute(params![
+                result.server_id.to_string(),
+                result.timestamp.to_rfc3339(),
+                result.tcp_check.reachable as i32,
+                result.tcp_check.latency_ms,
+                result.tcp_check.error,
+                result.ss_check.as_ref().map(|s| s.success as i32),
+                result.ss_check.as_ref().and_then(|s| s.latency_ms),
+                result.ss_check.as_ref().and_then(|s| s.error.clone()),
+                result
+                    .ss_check
+                    .as_ref()
+                    .and_then(|s| s.error_kind)
+                    .map(SsErrorKind::as_str),
+                user_checks_json(result)?,
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+const RESULT_COLUMNS: &str = "server_id, timestamp, tcp_reachable, tcp_latency_ms, tcp_error,
+                ss_success, ss_latency_ms, ss_error, ss_error_kind, user_checks";
+
+fn result_from_row(row: &rusqlite::Row) -> rusqlite::Result<CheckResult> {
+    let server_id_str: String = row.get(0)?;
+    let timestamp_str: String = row.get(1)?;
+    let tcp_reachable: i32 = row.get(2)?;
+    let tcp_latency_ms: Option<f64> = row.get(3)?;
+    let tcp_error: Option<String> = row.get(4)?;
+    let ss_success: Option<i32> = row.get(5)?;
+    let ss_latency_ms: Option<f64> = row.get(6)?;
+    let ss_error: Option<String> = row.get(7)?;
+    let ss_error_kind: Option<String> = row.get(8)?;
+    let user_checks: Option<String> = row.get(9)?;
+
+    let server_id = Uuid::parse_str(&server_id_str).unwrap_or_default();
+    let timestamp: DateTime<Utc> = DateTime::parse_from_rfc3339(&timestamp_str)
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now());
+
+    let ss_check = ss_success.map(|success| SsCheckResult {
+        success: success != 0,
+        latency_ms: ss_latency_ms,
+        error: ss_error,
+        error_kind: ss_error_kind.as_deref().and_then(SsErrorKind::parse),
+    });
+
+    Ok(CheckResult {
+        server_id,
+        timestamp,
+        tcp_check: TcpCheckResult {
+            reachable: tcp_reachable != 0,
+            latency_ms: tcp_latency_ms,
+            error: tcp_error,
+        },
+        ss_check,
+        user_checks: user_checks
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+    })
+}
+
+fn load_results_for_server(
+    conn: &Connection,
+    server_id: Uuid,
+    limit: usize,
+) -> Result<Vec<CheckResult>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {RESULT_COLUMNS}
+         FROM check_results
+         WHERE server_id = ?1
+         ORDER BY timestamp DESC
+         LIMIT ?2"
+    ))?;
+
+    let rows = stmt.query_map(
+        params![server_id.to_string(), limit as i64],
+        result_from_row,
+    )?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// Results matching `query`, newest first, each paired with its row id
+fn query_results(conn: &Connection, query: &ResultQuery) -> Result<Vec<(i64, CheckResult)>> {
+    let filter_clause = match query.filter {
+        None => "",
+        Some(ResultFilter::Failures) => {
+            "AND (tcp_reachable = 0 OR ss_success = 0
+                  OR EXISTS (SELECT 1 FROM json_each(user_checks)
+                             WHERE json_extract(value, '$.success') = 0))"
+        }
+        Some(ResultFilter::SsFailures) => {
+            "AND tcp_reachable = 1
+             AND (ss_success = 0
+                  OR EXISTS (SELECT 1 FROM json_each(user_checks)
+                             WHERE json_extract(value, '$.success') = 0))"
+        }
+    };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {RESULT_COLUMNS}, id
+         FROM check_results
+         WHERE server_id = ?1
+           AND (?2 IS NULL OR timestamp >= ?2)
+           AND (?3 IS NULL OR timestamp < ?3)
+           AND (?4 IS NULL OR id < ?4)
+           {filter_clause}
+         ORDER BY id DESC
+         LIMIT ?5"
+    ))?;
+
+    let rows = stmt.query_map(
+        params![
+            query.server_id.to_string(),
+            query.from.map(|t| t.to_rfc3339()),
+            query.to.map(|t| t.to_rfc3339()),
+            query.before_id,
+            query.limit as i64,
+        ],
+        |row| Ok((row.get(10)?, result_from_row(row)?)),
+    )?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// Next batch of results for export, oldest first, starting after row `after_id`.
+/// `server_id` of `None` exports every server.
+fn export_batch(
+    conn: &Connection,
+    server_id: Option<Uuid>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    after_id: i64,
+    limit: usize,
+) -> Result<Vec<(i64, CheckResult)>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT {RESULT_COLUMNS}, id
+         FROM check_results
+         WHERE id > ?1
+           AND (?2 IS NULL OR server_id = ?2)
+           AND (?3 IS NULL OR timestamp >= ?3)
+           AND (?4 IS NULL OR timestamp < ?4)
+         ORDER BY id ASC
+         LIMIT ?5"
+    ))?;
+
+    let rows = stmt.query_map(
+        params![
+            after_id,
+            server_id.map(|id| id.to_string()),
+            from.map(|t| t.to_rfc3339()),
+            to.map(|t| t.to_rfc3339()),
+            limit as i64,
+        ],
+        |row| Ok((row.get(10)?, result_from_row(row)?)),
+    )?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// TCP check samples of a server since ?2, for `latency_summary`
+const TCP_SAMPLES: &str = "SELECT id, timestamp, tcp_reachable AS ok, tcp_latency_ms AS latency
+     FROM check_results
+     WHERE server_id = ?1 AND timestamp >= ?2";
+
+/// SS check samples of a server since ?2, leaving out the ones skipped
+/// because TCP was down
+const SS_SAMPLES: &str = "SELECT id, timestamp, ss_success AS ok, ss_latency_ms AS latency
+     FROM check_results
+     WHERE server_id = ?1 AND timestamp >= ?2
+       AND tcp_reachable = 1 AND ss_success IS NOT NULL";
+
+/// Reduce the `samples` query (`id, timestamp, ok, latency`) to latency stats
+/// without loading the rows. SQLite has no percentile function, so each
+/// percentile is read by its rank from the sorted latencies.
+fn latency_summary(
+    conn: &Connection,
+    samples: &str,
+    server_id: Uuid,
+    since: DateTime<Utc>,
+) -> Result<LatencySummary> {
+    let params = params![server_id.to_string(), since.to_rfc3339()];
+    let mut summary = conn.query_row(
+        &format!(
+            "WITH s AS ({samples}),
+             l AS (
+                SELECT latency,
+                       latency - LAG(latency) OVER (ORDER BY timestamp, id) AS diff,
+                       latency - AVG(latency) OVER () AS dev
+                FROM s WHERE ok = 1 AND latency IS NOT NULL
+             )
+             SELECT (SELECT COUNT(*) FROM s), (SELECT COUNT(*) FROM s WHERE ok = 0),
+                    COUNT(*), AVG(latency), AVG(dev * dev), AVG(ABS(diff))
+             FROM l"
+        ),
+        params,
+        |row| {
+            Ok(LatencySummary {
+                attempts: row.get::<_, i64>(0)? as u64,
+                failures: row.get::<_, i64>(1)? as u64,
+                samples: row.get::<_, i64>(2)? as u64,
+                mean: row.get(3)?,
+                variance: row.get(4)?,
+                jitter: row.get(5)?,
+                ..Default::default()
+            })
+        },
+    )?;
+
+    // Linear interpolation between closest ranks, as in `stats::percentile`
+    let n = summary.samples;
+    let mut stmt = conn.prepare(&format!(
+        "WITH s AS ({samples})
+         SELECT latency FROM s WHERE ok = 1 AND latency IS NOT NULL
+         ORDER BY latency LIMIT 2 OFFSET ?3"
+    ))?;
+    let mut percentile = |p: f64| -> Result<Option<f64>> {
+        if n == 0 {
+            return Ok(None);
+        }
+        let rank = p / 100.0 * (n - 1) as f64;
+        let lo = rank.floor();
+        let pair: Vec<f64> = stmt
+            .query_map(
+                params![server_id.to_string(), since.to_rfc3339(), lo as i64],
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(match pair[..] {
+            [a, b] => Some(a + (b - a) * (rank - lo)),
+            [a] => Some(a),
+            _ => None,
+        })
+    };
+    summary.p50 = percentile(50.0)?;
+    summary.p90 = percentile(90.0)?;
+    summary.p99 = percentile(99.0)?;
+    Ok(summary)
+}
+
+fn latency_stats_since(
+    conn: &Connection,
+    server_id: Uuid,
+    since: DateTime<Utc>,
+) -> Result<(LatencyStats, LatencyStats)> {
+    Ok((
+        latency_summary(conn, TCP_SAMPLES, server_id, since)?.finish(),
+        latency_summary(conn, SS_SAMPLES, server_id, since)?.finish(),
+    ))
+}
+
+fn count_results_all(conn: &Connection) -> Result<HashMap<Uuid, u64>> {
+    let mut stmt =
+        conn.prepare("SELECT server_id, COUNT(*) FROM check_results GROUP BY server_id")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+    })?;
+
+    let mut counts = HashMap::new();
+    for row in rows {
+        let (id, count) = row?;
+        if let Ok(id) = Uuid::parse_str(&id) {
+            counts.insert(id, count as u64);
+        }
+    }
+    Ok(counts)
+}
+
+/// Columns summed over raw rows, in the same shape as `ROLLUP_STATS_COLUMNS`.
+/// SS checks skipped because TCP was down do not count as SS checks, and
+/// only successful SS checks count toward SS latency.
+const RAW_STATS_COLUMNS: &str = "server_id,
+                COUNT(*),
+                COALESCE(SUM(tcp_reachable), 0),
+                COUNT(CASE WHEN tcp_reachable = 1 THEN ss_success END),
+                COALESCE(SUM(CASE WHEN tcp_reachable = 1 THEN ss_success END), 0),
+                COUNT(tcp_latency_ms),
+                COALESCE(SUM(tcp_latency_ms), 0),
+                MIN(tcp_latency_ms),
+                MAX(tcp_latency_ms),
+                COUNT(CASE WHEN tcp_reachable = 1 AND ss_success = 1 THEN ss_latency_ms END),
+                COALESCE(
+                    SUM(CASE WHEN tcp_reachable = 1 AND ss_success = 1 THEN ss_latency_ms END),
+                    0
+                )";
+
+/// Columns summed over rollup buckets, in the same shape as `RAW_STATS_COLUMNS`
+const ROLLUP_STATS_COLUMNS: &str = "server_id,
+                COALESCE(SUM(checks), 0),
+                COALESCE(SUM(tcp_up), 0),
+                COALESCE(SUM(ss_checks), 0),
+                COALESCE(SUM(ss_ok), 0),
+                COALESCE(SUM(latency_count), 0),
+                COALESCE(SUM(latency_sum), 0),
+                MIN(latency_min),
+                MAX(latency_max),
+                COALESCE(SUM(ss_latency_count), 0),
+                COALESCE(SUM(ss_latency_sum), 0)";
+
+fn accumulator_from_row(row: &rusqlite::Row) -> rusqlite::Result<(String, StatsAccumulator)> {
+    Ok((
+        row.get(0)?,
+        StatsAccumulator {
+            checks: row.get::<_, i64>(1)? as u64,
+            tcp_up: row.get::<_, i64>(2)? as u64,
+            ss_checks: row.get::<_, i64>(3)? as u64,
+            ss_ok: row.get::<_, i64>(4)? as u64,
+            latency_count: row.get::<_, i64>(5)? as u64,
+            latency_sum: row.get(6)?,
+            latency_min: row.get(7)?,
+            latency_max: row.get(8)?,
+            ss_latency_count: row.get::<_, i64>(9)? as u64,
+            ss_latency_sum: row.get(10)?,
+        },
+    ))
+}
+
+/// Aggregate stats since `since`, optionally for a single server.
+/// Complete buckets that have been rolled up are read from the rollup tables
+/// (daily past the hourly horizon, hourly after that), the rest from raw rows,
+/// so windows longer than raw or hourly retention still work.
+fn aggregate_since(
+    conn: &Connection,
+    server_id: Option<Uuid>,
+    since: DateTime<Utc>,
+) -> Result<HashMap<String, StatsAccumulator>> {
+    let split = WindowSplit::new(
+        since,
+        rollup_watermark(conn, Rollup::Hourly)?,
+        earliest_bucket(conn, Rollup::Hourly)?,
+        rollup_watermark(conn, Rollup::Daily)?,
+    );
+    let server_filter = if server_id.is_some() {
+        "AND server_id = ?3"
+    } else {
+        ""
+    };
+    let server_param = server_id.map(|id| id.to_string());
+
+    let mut totals: HashMap<String, StatsAccumulator> = HashMap::new();
+    let mut collect =
+        |sql: &str, from: DateTime<Utc>, until: Option<DateTime<Utc>>| -> Result<()> {
+            let mut stmt = conn.prepare(sql)?;
+            let until = until.map(|t| t.to_rfc3339());
+            let rows = match &server_param {
+                Some(id) => {
+                    stmt.query_map(params![from.to_rfc3339(), until, id], accumulator_from_row)?
+                }
+                None => stmt.query_map(params![from.to_rfc3339(), until], accumulator_from_row)?,
+            };
+            for row in rows {
+                let (sid, acc) = row?;
+                totals.entry(sid).or_default().merge(&acc);
+            }
+            Ok(())
+        };
+
+    let ranges = [
+        (Rollup::Daily, split.daily_until.map(|until| (since, until))),
+        (Rollup::Hourly, split.hourly()),
+    ];
+    for (rollup, range) in ranges {
+        let Some((from, until)) = range else {
+            continue;
+        };
+        collect(
+            &format!(
+                "SELECT {ROLLUP_STATS_COLUMNS} FROM {}
+                 WHERE bucket >= ?1 AND bucket < ?2 {server_filter}
+                 GROUP BY server_id",
+                rollup.table()
+            ),
+            from,
+            Some(until),
+        )?;
+    }
+
+    collect(
+        &format!(
+            "SELECT {RAW_STATS_COLUMNS} FROM check_results
+             WHERE timestamp >= ?1 AND (?2 IS NULL OR timestamp < ?2) {server_filter}
+             GROUP BY server_id"
+        ),
+        split.raw_since(),
+        None,
+    )?;
+
+    Ok(totals)
+}
+
+/// Uptime and latency aggregates for one server over `window`
+fn uptime_stats(conn: &Connection, server_id: Uuid, window: StatsWindow) -> Result<UptimeStats> {
+    let since = Utc::now() - window.duration();
+    let totals = aggregate_since(conn, Some(server_id), since)?;
+    Ok(totals
+        .get(&server_id.to_string())
+        .map(|acc| acc.finish(window))
+        .unwrap_or_else(|| UptimeStats::empty(window)))
+}
+
+/// Uptime and latency aggregates over `window` for every server with results
+fn uptime_stats_all(conn: &Connection, window: StatsWindow) -> Result<HashMap<Uuid, UptimeStats>> {
+    let since = Utc::now() - window.duration();
+    let totals = aggregate_since(conn, None, since)?;
+    Ok(totals
+        .into_iter()
+        .filter_map(|(sid, acc)| Some((Uuid::parse_str(&sid).ok()?, acc.finish(window))))
+        .collect())
+}
+
+/// Start of the first bucket not yet rolled up, if rollups have started
+fn rollup_watermark(conn: &Connection, rollup: Rollup) -> Result<Option<DateTime<Utc>>> {
+    let next: Option<String> = conn
+        .query_row(
+            "SELECT next_bucket FROM rollup_state WHERE name = ?1",
+            params![rollup.key()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(next
+        .and_then(|ts| DateTime::parse_from_rfc3339(&ts).ok())
+        .map(|dt| dt.with_timezone(&Utc)))
+}
+
+/// Start of the oldest bucket still stored in a rollup table
+fn earliest_bucket(conn: &Connection, rollup: Rollup) -> Result<Option<DateTime<Utc>>> {
+    let first: Option<String> = conn.query_row(
+        &format!("SELECT MIN(bucket) FROM {}", rollup.table()),
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(first
+        .and_then(|ts| DateTime::parse_from_rfc3339(&ts).ok())
+        .map(|dt| dt.with_timezone(&Utc)))
+}
+
+#[derive(Default)]
+struct BucketAgg {
+    checks: i64,
+    tcp_up: i64,
+    ss_checks: i64,
+    ss_ok: i64,
+    latencies: Vec<f64>,
+    ss_latency_count: i64,
+    ss_latency_sum: f64,
+}
+
+/// Roll up every complete bucket since the last run from raw rows.
+/// Returns the number of buckets processed.
+fn update_rollups(conn: &Connection, rollup: Rollup) -> Result<usize> {
+    let next = match rollup_watermark(conn, rollup)? {
+        Some(next) => next,
+        None => {
+            let earliest: Option<String> =
+                conn.query_row("SELECT MIN(timestamp) FROM check_results", [], |row| {
+                    row.get(0)
+                })?;
+            let Some(earliest) = earliest.and_then(|ts| DateTime::parse_from_rfc3339(&ts).ok())
+            else {
+                return Ok(0);
+            };
+            rollup.floor(earliest.with_timezone(&Utc))
+        }
+    };
+    let end = rollup.floor(Utc::now());
+    if next >= end {
+        return Ok(0);
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    let mut processed = 0;
+    let mut bucket = next;
+    while bucket < end {
+        let bucket_end = bucket + rollup.bucket_len();
+        let mut per_server: HashMap<String, BucketAgg> = HashMap::new();
+        {
+            let mut stmt = tx.prepare_cached(
+                "SELECT server_id, tcp_reachable, tcp_latency_ms, ss_success, ss_latency_ms
+                 FROM check_results
+                 WHERE timestamp >= ?1 AND timestamp < ?2",
+            )?;
+            let mut rows = stmt.query(params![bucket.to_rfc3339(), bucket_end.to_rfc3339()])?;
+            while let Some(row) = rows.next()? {
+                let agg = per_server.entry(row.get(0)?).or_default();
+                let tcp_up = row.get::<_, i64>(1)?;
+                agg.checks += 1;
+                agg.tcp_up += tcp_up;
+                if let Some(latency) = row.get::<_, Option<f64>>(2)? {
+                    agg.latencies.push(latency);
+                }
+                // Skipped SS checks (TCP down) are not SS checks
+                let ok = row.get::<_, Option<i64>>(3)?.filter(|_| tcp_up == 1);
+                if let Some(ok) = ok {
+                    agg.ss_checks += 1;
+                    agg.ss_ok += ok;
+                }
+                // Only successful SS checks count toward SS latency
+                if let Some(latency) = row.get::<_, Option<f64>>(4)?.filter(|_| ok == Some(1)) {
+                    agg.ss_latency_count += 1;
+                    agg.ss_latency_sum += latency;
+                }
+            }
+        }
+
+        let sql = format!(
+            "INSERT OR REPLACE INTO {}
+                (server_id, bucket, checks, tcp_up, ss_checks, ss_ok,
+                 latency_count, latency_sum, latency_min, latency_avg,
+                 latency_p50, latency_p95, latency_max,
+                 ss_latency_count, ss_latency_sum)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            rollup.table()
+        );
+        let mut insert = tx.prepare_cached(&sql)?;
+        for (server_id, mut agg) in per_server {
+            agg.latencies.sort_by(f64::total_cmp);
+            let count = agg.latencies.len();
+            let sum: f64 = agg.latencies.iter().sum();
+            insert.execute(params![
+                server_id,
+                bucket.to_rfc3339(),
+                agg.checks,
+                agg.tcp_up,
+                agg.ss_checks,
+                agg.ss_ok,
+                count as i64,
+                sum,
+                agg.latencies.first(),
+                (count > 0).then(|| sum / count as f64),
+                percentile(&agg.latencies, 50.0),
+                percentile(&agg.latencies, 95.0),
+                agg.latencies.last(),
+                agg.ss_latency_count,
+                agg.ss_latency_sum,
+            ])?;
+        }
+
+        processed += 1;
+        bucket = bucket_end;
+    }
+
+    tx.execute(
+        "INSERT OR REPLACE INTO rollup_state (name, next_bucket) VALUES (?1, ?2)",
+        params![rollup.key(), end.to_rfc3339()],
+    )?;
+    tx.commit()?;
+    Ok(processed)
+}
+
+fn cleanup_rollups(conn: &Connection, rollup: Rollup, keep_days: i64) -> Result<usize> {
+    let cutoff = Utc::now() - Duration::days(keep_days);
+    let deleted = conn.execute(
+        &format!("DELETE FROM {} WHERE bucket < ?1", rollup.table()),
+        params![cutoff.to_rfc3339()],
+    )?;
+    Ok(deleted)
+}
+
+/// Raw rows before this have been rolled up and may be deleted
+fn rolled_up_watermark(conn: &Connection) -> Result<Option<DateTime<Utc>>> {
+    Ok(rolled_up_until(
+        rollup_watermark(conn, Rollup::Hourly)?,
+        rollup_watermark(conn, Rollup::Daily)?,
+    ))
+}
+
+/// Delete raw results older than `keep_days`, except for servers listed in
+/// `overrides`, which use their own retention instead.
+/// Rows not yet rolled up are kept until they are.
+fn cleanup_old(conn: &Connection, keep_days: i64, overrides: &[(Uuid, i64)]) -> Result<usize> {
+    let Some(rolled) = rolled_up_watermark(conn)? else {
+        return Ok(0);
+    };
+    let now = Utc::now();
+    let cutoff = |days: i64| (now - Duration::days(days)).min(rolled).to_rfc3339();
+    let overridden: Vec<String> = overrides.iter().map(|(id, _)| id.to_string()).collect();
+    let mut deleted = conn.execute(
+        "DELETE FROM check_results
+         WHERE timestamp < ?1
+           AND server_id NOT IN (SELECT value FROM json_each(?2))",
+        params![cutoff(keep_days), serde_json::to_string(&overridden)?],
+    )?;
+    for (id, days) in overrides {
+        deleted += conn.execute(
+            "DELETE FROM check_results WHERE server_id = ?1 AND timestamp < ?2",
+            params![id.to_string(), cutoff(*days)],
+        )?;
+    }
+    Ok(deleted)
+}
+
+/// Keep only the newest `max_rows` raw results of each server.
+/// Rows not yet rolled up are kept until they are.
+fn trim_results_per_server(conn: &Connection, max_rows: u32) -> Result<usize> {
+    let Some(rolled) = rolled_up_watermark(conn)? else {
+        return Ok(0);
+    };
+    let deleted = conn.execute(
+        "DELETE FROM check_results WHERE id IN (
+            SELECT id FROM (
+                SELECT id, timestamp, ROW_NUMBER() OVER (
+                    PARTITION BY server_id ORDER BY id DESC
+                ) AS rn
+                FROM check_results
+            ) WHERE rn > ?1 AND timestamp < ?2
+        )",
+        params![max_rows, rolled.to_rfc3339()],
+    )?;
+    Ok(deleted)
+}
+
+/// Return pages freed by deletes to the filesystem
+fn incremental_vacuum(conn: &Connection) -> Result<()> {
+    conn.execute_batch("PRAGMA incremental_vacuum;")?;
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn insert_results(&self, results: &[CheckResult]) -> Result<()> {
+        let results = results.to_vec();
+        self.run(move |conn| insert_results(conn, &results)).await
+    }
+
+    async fn load_results_for_server(
+        &self,
+        server_id: Uuid,
+        limit: usize,
+    ) -> Result<Vec<CheckResult>> {
+        self.run(move |conn| load_results_for_server(conn, server_id, limit))
+            .await
+    }
+
+    async fn count_results_all(&self) -> Result<HashMap<Uuid, u64>> {
+        self.run(count_results_all).await
+    }
+
+    async fn query_results(&self, query: &ResultQuery) -> Result<Vec<(i64, CheckResult)>> {
+        let query = query.clone();
+        self.run(move |conn| query_results(conn, &query)).await
+    }
+
+    async fn export_batch(
+        &self,
+        server_id: Option<Uuid>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        after_id: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, CheckResult)>> {
+        self.run(move |conn| export_batch(conn, server_id, from, to, after_id, limit))
+            .await
+    }
+
+    async fn latency_stats_since(
+        &self,
+        server_id: Uuid,
+        since: DateTime<Utc>,
+    ) -> Result<(LatencyStats, LatencyStats)> {
+        self.run(move |conn| latency_stats_since(conn, server_id, since))
+            .await
+    }
+
+    async fn uptime_stats(&self, server_id: Uuid, window: StatsWindow) -> Result<UptimeStats> {
+        self.run(move |conn| uptime_stats(conn, server_id, window))
+            .await
+    }
+
+    async fn uptime_stats_all(&self, window: StatsWindow) -> Result<HashMap<Uuid, UptimeStats>> {
+        self.run(move |conn| uptime_stats_all(conn, window)).await
+    }
+
+    async fn update_rollups(&self, rollup: Rollup) -> Result<usize> {
+        self.run(move |conn| update_rollups(conn, rollup)).await
+    }
+
+    async fn cleanup_rollups(&self, rollup: Rollup, keep_days: i64) -> Result<usize> {
+        self.run(move |conn| cleanup_rollups(conn, rollup, keep_days))
+            .await
+    }
+
+    async fn cleanup_old(&self, keep_days: i64, overrides: &[(Uuid, i64)]) -> Result<usize> {
+        let overrides = overrides.to_vec();
+        self.run(move |conn| cleanup_old(conn, keep_days, &overrides))
+            .await
+    }
+
+    async fn trim_results_per_server(&self, max_rows: u32) -> Result<usize> {
+        self.run(move |conn| trim_results_per_server(conn, max_rows))
+            .await
+    }
+
+    async fn vacuum(&self) -> Result<()> {
+        self.run(incremental_vacuum).await
+    }
+
+    async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let key = key.to_string();
+        self.run(move |conn| get_setting(conn, &key)).await
+    }
+
+    async fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        let (key, value) = (key.to_string(), value.to_string());
+        self.run(move |conn| set_setting(conn, &key, &value)).await
+    }
+
+    async fn load_servers(&self) -> Result<Vec<Server>> {
+        self.run(load_servers).await
+    }
+
+    async fn upsert_server(&self, server: &Server) -> Result<()> {
+        let server = server.clone();
+        self.run(move |conn| upsert_server(conn, &server)).await
+    }
+
+    async fn delete_server(&self, id: Uuid) -> Result<()> {
+        self.run(move |conn| delete_server(conn, id)).await
+    }
+
+    async fn import_servers_once(&self, servers: &[Server]) -> Result<usize> {
+        let servers = servers.to_vec();
+        self.run(move |conn| import_servers_once(conn, &servers))
+            .await
+    }
+
+    async fn insert_sync_log(&self, entry: &SyncLogEntry) -> Result<()> {
+        let entry = entry.clone();
+        self.run(move |conn| insert_sync_log(conn, &entry)).await
+    }
+
+    async fn load_sync_log(
+        &self,
+        subscription: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SyncLogEntry>> {
+        let subscription = subscription.map(str::to_string);
+        self.run(move |conn| load_sync_log(conn, subscription.as_deref(), limit))
+            .await
+    }
+
+    async fn cleanup_sync_log(&self, keep_days: i64) -> Result<usize> {
+        self.run(move |conn| cleanup_sync_log(conn, keep_days))
+            .await
+    }
+
+    async fn close(&self) -> Result<()> {
+        // Waits for any statement still running on the blocking pool
+        self.run(|conn| Ok(conn.cache_flush()?)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserCheckResult;
+
+    fn memory() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn
+    }
+
+    /// A result with TCP up when `tcp_ms` is given; `ss` is the SS check's
+    /// outcome and latency, a failed check without TCP being a skipped one
+    fn check(
+        server_id: Uuid,
+        timestamp: DateTime<Utc>,
+        tcp_ms: Option<f64>,
+        ss: Option<(bool, Option<f64>)>,
+    ) -> CheckResult {
+        let skipped = tcp_ms.is_none();
+        CheckResult {
+            server_id,
+            timestamp,
+            tcp_check: TcpCheckResult {
+                reachable: tcp_ms.is_some(),
+                latency_ms: tcp_ms,
+                error: skipped.then(|| "connection refused".to_string()),
+            },
+            ss_check: ss.map(|(success, latency_ms)| SsCheckResult {
+                success,
+                latency_ms,
+                error: (!success).then(|| "failed".to_string()),
+                error_kind: match (success, skipped) {
+                    (true, _) => None,
+                    (false, true) => Some(SsErrorKind::Skipped),
+                    (false, false) => Some(SsErrorKind::Protocol),
+                },
+            }),
+            user_checks: Vec::new(),
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct BucketRow {
+        checks: i64,
+        tcp_up: i64,
+        ss_checks: i64,
+        ss_ok: i64,
+        latency_count: i64,
+        latency_sum: f64,
+        latency_min: Option<f64>,
+        latency_avg: Option<f64>,
+        latency_p50: Option<f64>,
+        latency_max: Option<f64>,
+        ss_latency_count: i64,
+        ss_latency_sum: f64,
+    }
+
+    fn bucket_row(
+        conn: &Connection,
+        rollup: Rollup,
+        server_id: Uuid,
+        bucket: DateTime<Utc>,
+    ) -> Option<BucketRow> {
+        conn.query_row(
+            &format!(
+                "SELECT checks, tcp_up, ss_checks, ss_ok, latency_count, latency_sum,
+                        latency_min, latency_avg, latency_p50, latency_max,
+                        ss_latency_count, ss_latency_sum
+                 FROM {} WHERE server_id = ?1 AND bucket = ?2",
+                rollup.table()
+            ),
+            params![server_id.to_string(), bucket.to_rfc3339()],
+            |row| {
+                Ok(BucketRow {
+                    checks: row.get(0)?,
+                    tcp_up: row.get(1)?,
+                    ss_checks: row.get(2)?,
+                    ss_ok: row.get(3)?,
+                    latency_count: row.get(4)?,
+                    latency_sum: row.get(5)?,
+                    latency_min: row.get(6)?,
+                    latency_avg: row.get(7)?,
+                    latency_p50: row.get(8)?,
+                    latency_max: row.get(9)?,
+                    ss_latency_count: row.get(10)?,
+                    ss_latency_sum: row.get(11)?,
+                })
+            },
+        )
+        .optional()
+        .unwrap()
+    }
+
+    #[test]
+    fn hourly_rollup_sums_complete_buckets() {
+        let conn = memory();
+        let server = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let current = Rollup::Hourly.floor(Utc::now());
+        let hour = current - Duration::hours(3);
+        let minute = |n: i64| hour + Duration::minutes(n);
+        insert_results(
+            &conn,
+            &[
+                check(server, minute(1), Some(10.0), Some((true, Some(100.0)))),
+                check(server, minute(2), Some(20.0), Some((true, Some(300.0)))),
+                // Failed SS checks count as checks but not toward SS latency
+                check(server, minute(3), Some(30.0), Some((false, Some(999.0)))),
+                // Skipped for TCP being down: not an SS check at all
+                check(server, minute(4), None, Some((false, None))),
+                check(other, minute(61), Some(5.0), None),
+                // Still in the incomplete current hour
+                check(server, current, Some(1.0), Some((true, Some(1.0)))),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(update_rollups(&conn, Rollup::Hourly).unwrap(), 3);
+        assert_eq!(
+            bucket_row(&conn, Rollup::Hourly, server, hour),
+            Some(BucketRow {
+                checks: 4,
+                tcp_up: 3,
+                ss_checks: 3,
+                ss_ok: 2,
+                latency_count: 3,
+                latency_sum: 60.0,
+                latency_min: Some(10.0),
+                latency_avg: Some(20.0),
+                latency_p50: Some(20.0),
+                latency_max: Some(30.0),
+                ss_latency_count: 2,
+                ss_latency_sum: 400.0,
+            })
+        );
+        let next_hour = hour + Duration::hours(1);
+        assert_eq!(bucket_row(&conn, Rollup::Hourly, server, next_hour), None);
+        assert_eq!(
+            bucket_row(&conn, Rollup::Hourly, other, next_hour).map(|row| row.checks),
+            Some(1)
+        );
+        assert_eq!(bucket_row(&conn, Rollup::Hourly, server, current), None);
+        assert_eq!(
+            rollup_watermark(&conn, Rollup::Hourly).unwrap(),
+            Some(current)
+        );
+
+        // Runs pick up where the last one stopped
+        assert_eq!(update_rollups(&conn, Rollup::Hourly).unwrap(), 0);
+    }
+
+    #[test]
+    fn daily_rollup_waits_for_the_day_to_end() {
+        let conn = memory();
+        let server = Uuid::new_v4();
+        let today = Rollup::Daily.floor(Utc::now());
+        let yesterday = today - Duration::days(1);
+        insert_results(
+            &conn,
+            &[
+                check(
+                    server,
+                    yesterday + Duration::hours(6),
+                    Some(10.0),
+                    Some((true, Some(50.0))),
+                ),
+                check(server, yesterday + Duration::hours(18), None, None),
+                check(server, today, Some(10.0), Some((true, Some(50.0)))),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(update_rollups(&conn, Rollup::Daily).unwrap(), 1);
+        let row = bucket_row(&conn, Rollup::Daily, server, yesterday).unwrap();
+        assert_eq!(
+            (row.checks, row.tcp_up, row.ss_checks, row.ss_ok),
+            (2, 1, 1, 1)
+        );
+        assert_eq!(bucket_row(&conn, Rollup::Daily, server, today), None);
+        assert_eq!(rollup_watermark(&conn, Rollup::Daily).unwrap(), Some(today));
+    }
+
+    #[test]
+    fn rollups_without_results_do_nothing() {
+        let conn = memory();
+        assert_eq!(update_rollups(&conn, Rollup::Hourly).unwrap(), 0);
+        assert_eq!(rollup_watermark(&conn, Rollup::Hourly).unwrap(), None);
+    }
+
+    fn assert_close(a: Option<f64>, b: Option<f64>) {
+        match (a, b) {
+            (Some(a), Some(b)) => assert!((a - b).abs() < 1e-9, "{a} != {b}"),
+            _ => assert_eq!(a, b),
+        }
+    }
+
+    fn assert_same_stats(a: &UptimeStats, b: &UptimeStats) {
+        assert_eq!(
+            (a.total_checks, a.tcp_up_checks, a.ss_checks, a.ss_ok_checks),
+            (b.total_checks, b.tcp_up_checks, b.ss_checks, b.ss_ok_checks)
+        );
+        assert_close(a.uptime_pct, b.uptime_pct);
+        assert_close(a.ss_success_pct, b.ss_success_pct);
+        assert_close(a.avg_latency_ms, b.avg_latency_ms);
+        assert_close(a.min_latency_ms, b.min_latency_ms);
+        assert_close(a.max_latency_ms, b.max_latency_ms);
+        assert_close(a.avg_ss_latency_ms, b.avg_ss_latency_ms);
+    }
+
+    #[test]
+    fn uptime_stats_over_raw_rows() {
+        let conn = memory();
+        let server = Uuid::new_v4();
+        let now = Utc::now();
+        let ago = |hours: i64| now - Duration::hours(hours);
+        insert_results(
+            &conn,
+            &[
+                check(server, ago(1), Some(10.0), Some((true, Some(100.0)))),
+                check(server, ago(2), Some(30.0), Some((false, Some(900.0)))),
+                check(server, ago(3), None, Some((false, None))),
+                check(server, ago(4), Some(20.0), Some((true, Some(200.0)))),
+                // Outside the 24h window
+                check(server, ago(30), None, Some((false, None))),
+            ],
+        )
+        .unwrap();
+
+        let day = uptime_stats(&conn, server, StatsWindow::Day).unwrap();
+        assert_eq!(day.total_checks, 4);
+        assert_eq!(day.tcp_up_checks, 3);
+        assert_eq!(day.ss_checks, 3);
+        assert_eq!(day.ss_ok_checks, 2);
+        assert_close(day.uptime_pct, Some(75.0));
+        assert_close(day.ss_success_pct, Some(200.0 / 3.0));
+        assert_close(day.avg_latency_ms, Some(20.0));
+        assert_close(day.min_latency_ms, Some(10.0));
+        assert_close(day.max_latency_ms, Some(30.0));
+        assert_close(day.avg_ss_latency_ms, Some(150.0));
+
+        let week = uptime_stats(&conn, server, StatsWindow::Week).unwrap();
+        assert_eq!((week.total_checks, week.tcp_up_checks), (5, 3));
+
+        let none = uptime_stats(&conn, Uuid::new_v4(), StatsWindow::Day).unwrap();
+        assert_eq!(none.total_checks, 0);
+        assert_eq!(none.uptime_pct, None);
+    }
+
+    #[test]
+    fn uptime_stats_read_from_rollups_match_raw_rows() {
+        let conn = memory();
+        let servers = [Uuid::new_v4(), Uuid::new_v4()];
+        let now = Utc::now();
+        let mut results = Vec::new();
+        for k in 0..40i64 {
+            let server = servers[k as usize % 2];
+            let timestamp = now - Duration::minutes(k * 97);
+            let latency = 5.0 + k as f64;
+            results.push(match k % 4 {
+                0 => check(server, timestamp, None, Some((false, None))),
+                1 => check(server, timestamp, Some(latency), Some((false, Some(1e4)))),
+                _ => check(
+                    server,
+                    timestamp,
+                    Some(latency),
+                    Some((true, Some(latency * 3.0))),
+                ),
+            });
+        }
+        insert_results(&conn, &results).unwrap();
+
+        let raw = uptime_stats_all(&conn, StatsWindow::Week).unwrap();
+        assert_eq!(raw.len(), 2);
+        assert_eq!(raw.values().map(|s| s.total_checks).sum::<u64>(), 40);
+
+        update_rollups(&conn, Rollup::Hourly).unwrap();
+        update_rollups(&conn, Rollup::Daily).unwrap();
+        // Raw rows that are rolled up are gone, so the totals have to come
+        // from the rollup tables
+        assert!(cleanup_old(&conn, 0, &[]).unwrap() > 0);
+        let rolled = uptime_stats_all(&conn, StatsWindow::Week).unwrap();
+        for server in &servers {
+            assert_same_stats(&rolled[server], &raw[server]);
+        }
+
+        // Past the hourly horizon the daily table takes over
+        assert!(cleanup_rollups(&conn, Rollup::Hourly, 1).unwrap() > 0);
+        let daily = uptime_stats_all(&conn, StatsWindow::Week).unwrap();
+        for server in &servers {
+            assert_same_stats(&daily[server], &raw[server]);
+        }
+        assert_same_stats(
+            &uptime_stats(&conn, servers[0], StatsWindow::Week).unwrap(),
+            &raw[&servers[0]],
+        );
+    }
+
+    /// Every page of `query`, following cursors the way the history API does
+    fn all_pages(conn: &Connection, mut query: ResultQuery) -> Vec<Vec<i64>> {
+        let mut pages = Vec::new();
+        loop {
+            let rows = query_results(conn, &query).unwrap();
+            let full = rows.len() == query.limit;
+            query.before_id = rows.last().map(|(id, _)| *id);
+            pages.push(rows.into_iter().map(|(id, _)| id).collect());
+            if !full {
+                return pages;
+            }
+        }
+    }
+
+    #[test]
+    fn query_results_pages_newest_first() {
+        let conn = memory();
+        let server = Uuid::new_v4();
+        let start = Rollup::Hourly.floor(Utc::now()) - Duration::hours(10);
+        let results: Vec<_> = (0..7)
+            .map(|i| {
+                check(
+                    server,
+                    start + Duration::minutes(i * 10),
+                    Some(10.0),
+                    Some((true, Some(20.0))),
+                )
+            })
+            .collect();
+        insert_results(&conn, &results).unwrap();
+        insert_results(&conn, &[check(Uuid::new_v4(), start, Some(1.0), None)]).unwrap();
+
+        let query = ResultQuery {
+            server_id: server,
+            from: None,
+            to: None,
+            before_id: None,
+            filter: None,
+            limit: 3,
+        };
+        let pages = all_pages(&conn, query.clone());
+        assert_eq!(pages, vec![vec![7, 6, 5], vec![4, 3, 2], vec![1]]);
+
+        // An exact multiple of the page size ends with an empty page
+        let pages = all_pages(
+            &conn,
+            ResultQuery {
+                limit: 7,
+                ..query.clone()
+            },
+        );
+        assert_eq!(pages, vec![vec![7, 6, 5, 4, 3, 2, 1], vec![]]);
+
+        // `from` is inclusive and `to` exclusive
+        let pages = all_pages(
+            &conn,
+            ResultQuery {
+                from: Some(start + Duration::minutes(10)),
+                to: Some(start + Duration::minutes(50)),
+                ..query
+            },
+        );
+        assert_eq!(pages, vec![vec![5, 4, 3], vec![2]]);
+    }
+
+    #[test]
+    fn query_results_filters_failures() {
+        let conn = memory();
+        let server = Uuid::new_v4();
+        let start = Rollup::Hourly.floor(Utc::now()) - Duration::hours(10);
+        let at = |i: i64| start + Duration::minutes(i);
+        let mut user_failed = check(server, at(3), Some(10.0), Some((true, Some(20.0))));
+        user_failed.user_checks = vec![
+            UserCheckResult {
+                user: "alice".to_string(),
+                ss_check: check(server, at(3), Some(10.0), Some((true, Some(20.0))))
+                    .ss_check
+                    .unwrap(),
+            },
+            UserCheckResult {
+                user: "bob".to_string(),
+                ss_check: check(server, at(3), Some(10.0), Some((false, None)))
+                    .ss_check
+                    .unwrap(),
+            },
+        ];
+        insert_results(
+            &conn,
+            &[
+                check(server, at(0), Some(10.0), Some((true, Some(20.0)))),
+                check(server, at(1), None, Some((false, None))),
+                check(server, at(2), Some(10.0), Some((false, None))),
+                user_failed,
+                check(server, at(4), Some(10.0), Some((true, Some(20.0)))),
+            ],
+        )
+        .unwrap();
+
+        let ids = |filter| {
+            let query = ResultQuery {
+                server_id: server,
+                from: None,
+                to: None,
+                before_id: None,
+                filter,
+                limit: 2,
+            };
+            all_pages(&conn, query).concat()
+        };
+        assert_eq!(ids(None), vec![5, 4, 3, 2, 1]);
+        assert_eq!(ids(Some(ResultFilter::Failures)), vec![4, 3, 2]);
+        assert_eq!(ids(Some(ResultFilter::SsFailures)), vec![4, 3]);
+    }
+}
@@ -3,14 +3,23 @@ use axum::response::{IntoResponse, Response};
 use axum::Json;
 use uuid::Uuid;
 
+use crate::models::FieldError;
+
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
     #[error("Server not found: {0}")]
     NotFound(Uuid),
+    #[error("Subscription not found: {0}")]
+    SubscriptionNotFound(String),
     #[error("Invalid input: {0}")]
     BadRequest(String),
+    /// One or more request fields are invalid; the message names the first
+    #[error("Invalid input: {}", .0.first().map(ToString::to_string).unwrap_or_default())]
+    Validation(Vec<FieldError>),
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("{0}")]
+    Conflict(String),
     #[error("Internal error: {0}")]
     Internal(#[from] anyhow::Error),
 }
@@ -18,9 +27,16 @@ pub enum AppError {
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let (status, msg) = match &self {
-            AppError::NotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::NotFound(_) | AppError::SubscriptionNotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string())
+            }
             AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            AppError::Validation(fields) => {
+                let body = serde_json::json!({ "error": self.to_string(), "fields": fields });
+                return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+            }
             AppError::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()),
+            AppError::Conflict(_) => (StatusCode::CONFLICT, self.to_string()),
             AppError::Internal(e) => {
                 tracing::error!("Internal error: {}", e);
                 (
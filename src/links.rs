@@ -0,0 +1,746 @@
+//! Shadowsocks share formats: `ss://` URIs (SIP002), SIP008 JSON documents
+//! and base64 subscription blobs, as handed out by providers, plus the Clash
+//! and sing-box proxy lists servers are exported to.
+
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::Result;
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+use shadowsocks::config::ServerConfig as SsServerConfig;
+use shadowsocks::crypto::CipherKind;
+use shadowsocks::ServerAddr;
+use uuid::Uuid;
+
+use crate::models::{Server, ServerUser};
+
+/// Characters escaped in the `#remarks` fragment and in 2022 userinfo
+const COMPONENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b':')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'@')
+    .add(b'[')
+    .add(b']')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// The `plugin` query value additionally escapes `;`, `=` and `&`
+const PLUGIN_COMPONENT: &AsciiSet = &COMPONENT.add(b';').add(b'=').add(b'&');
+
+/// One server read from a share link or document
+#[derive(Debug, Clone)]
+pub struct SharedServer {
+    /// Server id from a SIP008 document
+    pub id: Option<Uuid>,
+    /// `#remarks` of a URI, `remarks` of a SIP008 entry
+    pub name: Option<String>,
+    pub host: String,
+    pub port: u16,
+    /// For 2022 ciphers, the user key without the identity keys
+    pub password: String,
+    pub method: String,
+    /// Shadowsocks 2022 identity keys sent ahead of `password`
+    pub identity_keys: Vec<String>,
+    /// SIP003 plugin name
+    pub plugin: Option<String>,
+    pub plugin_opts: Option<String>,
+}
+
+/// SIP008 online configuration document
+#[derive(Debug, Serialize, Deserialize)]
+struct Sip008Document {
+    #[serde(default = "sip008_version")]
+    version: u32,
+    servers: Vec<Sip008Server>,
+}
+
+fn sip008_version() -> u32 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Sip008Server {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    remarks: Option<String>,
+    server: String,
+    server_port: u16,
+    password: String,
+    method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plugin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plugin_opts: Option<String>,
+}
+
+/// Clash proxy list, ready to merge into a Clash config
+#[derive(Debug, Serialize)]
+struct ClashDocument<'a> {
+    proxies: Vec<ClashProxy<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ClashProxy<'a> {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    server: &'a str,
+    port: u16,
+    cipher: &'a str,
+    password: String,
+    udp: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plugin: Option<&'static str>,
+    #[serde(rename = "plugin-opts", skip_serializing_if = "Option::is_none")]
+    plugin_opts: Option<ClashPluginOpts>,
+}
+
+/// Clash takes plugin options as a map rather than a SIP003 string
+#[derive(Debug, Default, Serialize)]
+struct ClashPluginOpts {
+    mode: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tls: Option<bool>,
+}
+
+/// sing-box outbounds, ready to merge into a sing-box config
+#[derive(Debug, Serialize)]
+struct SingBoxDocument<'a> {
+    outbounds: Vec<SingBoxOutbound<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct SingBoxOutbound<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    tag: String,
+    server: &'a str,
+    server_port: u16,
+    method: &'a str,
+    password: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plugin: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plugin_opts: Option<&'a str>,
+}
+
+/// Parse a SIP008 document, `ss://` URIs one per line, or either of those
+/// base64-encoded. Each entry is parsed on its own so one bad line does not
+/// sink the rest; an error is returned only when the content as a whole is
+/// unrecognisable.
+pub fn parse(content: &str) -> Result<Vec<Result<SharedServer, String>>> {
+    let text = content.trim();
+    if text.starts_with('{') {
+        return parse_sip008(text);
+    }
+    if text.lines().any(|line| line.trim().starts_with("ss://")) {
+        return Ok(parse_uri_lines(text));
+    }
+
+    let decoded = decode_base64(text)
+        .ok_or_else(|| anyhow::anyhow!("expected ss:// URIs, a SIP008 document or base64"))?;
+    let decoded = decoded.trim();
+    if decoded.starts_with('{') {
+        parse_sip008(decoded)
+    } else if decoded.lines().any(|line| line.trim().starts_with("ss://")) {
+        Ok(parse_uri_lines(decoded))
+    } else {
+        anyhow::bail!("base64 content holds no ss:// URIs or SIP008 document")
+    }
+}
+
+/// Parse a single `ss://` URI
+fn parse_uri(uri: &str) -> Result<SharedServer, String> {
+    let config = SsServerConfig::from_url(uri).map_err(|e| e.to_string())?;
+    let (host, port) = match config.addr() {
+        ServerAddr::SocketAddr(addr) => (addr.ip().to_string(), addr.port()),
+        ServerAddr::DomainName(host, port) => (host.clone(), *port),
+    };
+    let identity_keys = config
+        .identity_keys()
+        .iter()
+        .map(|key| STANDARD.encode(key))
+        .collect();
+    // The legacy ss://BASE64#remarks form loses its fragment in the parser
+    let name = match config.remarks() {
+        Some(remarks) => Some(remarks.to_string()),
+        None => uri
+            .split_once('#')
+            .map(|(_, frag)| percent_decode_str(frag).decode_utf8_lossy().into_owned())
+            .filter(|name| !name.is_empty()),
+    };
+    Ok(SharedServer {
+        id: None,
+        name,
+        host,
+        port,
+        password: config.password().to_string(),
+        method: config.method().to_string(),
+        identity_keys,
+        plugin: config.plugin().map(|p| p.plugin.clone()),
+        plugin_opts: config.plugin().and_then(|p| p.plugin_opts.clone()),
+    })
+}
+
+fn parse_uri_lines(text: &str) -> Vec<Result<SharedServer, String>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            if line.starts_with("ss://") {
+                parse_uri(line)
+            } else {
+                let scheme = line.split("://").next().unwrap_or(line);
+                Err(format!("not an ss:// URI ({}://)", scheme))
+            }
+        })
+        .collect()
+}
+
+fn parse_sip008(text: &str) -> Result<Vec<Result<SharedServer, String>>> {
+    let doc: Sip008Document = serde_json::from_str(text)
+        .map_err(|e| anyhow::anyhow!("invalid SIP008 document: {}", e))?;
+    Ok(doc
+        .servers
+        .into_iter()
+        .map(|s| {
+            validate_credentials(&s.server, s.server_port, &s.password, &s.method)?;
+            let (identity_keys, password) = split_identity_keys(&s.method, &s.password);
+            Ok(SharedServer {
+                id: s.id,
+                name: s.remarks,
+                host: s.server,
+                port: s.server_port,
+                password,
+                method: s.method,
+                identity_keys,
+                plugin: s.plugin.filter(|p| !p.is_empty()),
+                plugin_opts: s.plugin_opts.filter(|o| !o.is_empty()),
+            })
+        })
+        .collect())
+}
+
+/// Split a 2022 `iPSK:...:uPSK` password into identity keys and user key
+fn split_identity_keys(method: &str, password: &str) -> (Vec<String>, String) {
+    if !method.starts_with("2022-") {
+        return (Vec::new(), password.to_string());
+    }
+    let mut keys: Vec<String> = password.split(':').map(str::to_string).collect();
+    let user_key = keys.pop().unwrap_or_default();
+    (keys, user_key)
+}
+
+/// Check that the method is supported and the password fits it, the way the
+/// SS check will use them
+fn validate_credentials(host: &str, port: u16, password: &str, method: &str) -> Result<(), String> {
+    if host.is_empty() {
+        return Err("missing server address".into());
+    }
+    let method: CipherKind = method
+        .parse()
+        .map_err(|_| format!("unknown encryption method {}", method))?;
+    let addr = match host.parse::<IpAddr>() {
+        Ok(ip) => ServerAddr::SocketAddr(SocketAddr::new(ip, port)),
+        Err(_) => ServerAddr::DomainName(host.to_string(), port),
+    };
+    SsServerConfig::new(addr, password, method)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// One way for a client to connect to a server: with the server's own key,
+/// or as one of its `users`. Exports list every one of them, since a
+/// multi-user 2022 server is only reachable with a user's `iPSK:uPSK`.
+struct Credential<'a> {
+    server: &'a Server,
+    /// `None` for the server's own key
+    user: Option<&'a ServerUser>,
+}
+
+impl<'a> Credential<'a> {
+    /// The server's name, followed by the user's for a user
+    fn name(&self) -> String {
+        match self.user {
+            Some(user) => format!("{} / {}", self.server.name, user.name),
+            None => self.server.name.clone(),
+        }
+    }
+
+    fn method(&self) -> &'a str {
+        match self.user {
+            Some(user) => self.server.user_method(user),
+            None => &self.server.method,
+        }
+    }
+
+    /// The password as a client enters it, identity keys included
+    fn password(&self) -> String {
+        match self.user {
+            Some(user) => self.server.user_password(user),
+            None => self.server.client_password(&self.server.password),
+        }
+    }
+}
+
+/// Every credential of `servers`, each server's own key before its users
+fn credentials(servers: &[Server]) -> impl Iterator<Item = Credential<'_>> {
+    servers.iter().flat_map(|server| {
+        std::iter::once(Credential { server, user: None }).chain(server.users.iter().map(
+            move |user| Credential {
+                server,
+                user: Some(user),
+            },
+        ))
+    })
+}
+
+/// SIP002 URI for a credential, named by its `#remarks`. 2022 ciphers put
+/// the percent-encoded key in the userinfo as SIP002 requires; older ciphers
+/// use the base64 form every client understands.
+fn to_uri(credential: &Credential) -> String {
+    let server = credential.server;
+    let method = credential.method();
+    let userinfo = if method.starts_with("2022-") {
+        format!(
+            "{}:{}",
+            utf8_percent_encode(method, COMPONENT),
+            utf8_percent_encode(&credential.password(), COMPONENT)
+        )
+    } else {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", method, credential.password()))
+    };
+    let host = if server.host.contains(':') {
+        format!("[{}]", server.host)
+    } else {
+        server.host.clone()
+    };
+    let plugin = match (&server.plugin, &server.plugin_opts) {
+        (Some(plugin), Some(opts)) => format!("{};{}", plugin, opts),
+        (Some(plugin), None) => plugin.clone(),
+        (None, _) => String::new(),
+    };
+    let query = if plugin.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "/?plugin={}",
+            utf8_percent_encode(&plugin, PLUGIN_COMPONENT)
+        )
+    };
+    format!(
+        "ss://{}@{}:{}{}#{}",
+        userinfo,
+        host,
+        server.port,
+        query,
+        utf8_percent_encode(&credential.name(), COMPONENT)
+    )
+}
+
+/// `ss://` URIs one per line
+pub fn to_uri_list(servers: &[Server]) -> String {
+    credentials(servers).map(|c| to_uri(&c) + "\n").collect()
+}
+
+/// SIP008 document, keeping server ids so re-imports line up. Users get no
+/// id of their own, as SIP008 ids are per entry.
+pub fn to_sip008(servers: &[Server]) -> Result<String> {
+    let doc = Sip008Document {
+        version: sip008_version(),
+        servers: credentials(servers)
+            .map(|c| Sip008Server {
+                id: c.user.is_none().then_some(c.server.id),
+                remarks: Some(c.name()),
+                server: c.server.host.clone(),
+                server_port: c.server.port,
+                password: c.password(),
+                method: c.method().to_string(),
+                plugin: c.server.plugin.clone(),
+                plugin_opts: c.server.plugin_opts.clone(),
+            })
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&doc)?)
+}
+
+/// Clash `proxies:` list. Clash only knows the obfs and v2ray-plugin
+/// plugins; servers using any other plugin are left out.
+pub fn to_clash(servers: &[Server]) -> Result<String> {
+    let credentials: Vec<(Credential, Option<(&'static str, ClashPluginOpts)>)> =
+        credentials(servers)
+            .filter_map(|c| clash_plugin(c.server).map(|plugin| (c, plugin)))
+            .collect();
+    let names = unique_names(credentials.iter().map(|(c, _)| c.name()));
+    let doc = ClashDocument {
+        proxies: credentials
+            .into_iter()
+            .zip(names)
+            .map(|((c, plugin), name)| {
+                let (plugin, plugin_opts) = plugin.unzip();
+                ClashProxy {
+                    name,
+                    kind: "ss",
+                    server: &c.server.host,
+                    port: c.server.port,
+                    cipher: c.method(),
+                    password: c.password(),
+                    udp: true,
+                    plugin,
+                    plugin_opts,
+                }
+            })
+            .collect(),
+    };
+    Ok(serde_yaml_ng::to_string(&doc)?)
+}
+
+/// Clash plugin name and options for a server: `Some(None)` without a
+/// plugin, `None` if Clash cannot run its plugin
+fn clash_plugin(server: &Server) -> Option<Option<(&'static str, ClashPluginOpts)>> {
+    let Some(plugin) = server.plugin.as_deref() else {
+        return Some(None);
+    };
+    let opts = parse_plugin_opts(server.plugin_opts.as_deref().unwrap_or_default());
+    let get = |key: &str| opts.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+    match plugin {
+        "obfs-local" | "simple-obfs" => Some(Some((
+            "obfs",
+            ClashPluginOpts {
+                mode: get("obfs").unwrap_or_else(|| "http".into()),
+                host: get("obfs-host"),
+                ..Default::default()
+            },
+        ))),
+        "v2ray-plugin" => Some(Some((
+            "v2ray-plugin",
+            ClashPluginOpts {
+                mode: get("mode").unwrap_or_else(|| "websocket".into()),
+                host: get("host"),
+                path: get("path"),
+                tls: get("tls").map(|_| true),
+            },
+        ))),
+        _ => None,
+    }
+}
+
+/// Split SIP003 options (`key=value;flag;...`, `\` escaping `;`, `=` and
+/// `\`) into pairs; flags get an empty value
+fn parse_plugin_opts(opts: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut current = (String::new(), String::new());
+    let mut in_value = false;
+    let mut chars = opts.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    if in_value {
+                        &mut current.1
+                    } else {
+                        &mut current.0
+                    }
+                    .push(next);
+                }
+            }
+            '=' if !in_value => in_value = true,
+            ';' => {
+                if !current.0.is_empty() {
+                    pairs.push(std::mem::take(&mut current));
+                }
+                in_value = false;
+            }
+            c => if in_value {
+                &mut current.1
+            } else {
+                &mut current.0
+            }
+            .push(c),
+        }
+    }
+    if !current.0.is_empty() {
+        pairs.push(current);
+    }
+    pairs
+}
+
+/// sing-box `outbounds` list
+pub fn to_sing_box(servers: &[Server]) -> Result<String> {
+    let credentials: Vec<Credential> = credentials(servers).collect();
+    let tags = unique_names(credentials.iter().map(Credential::name));
+    let doc = SingBoxDocument {
+        outbounds: credentials
+            .iter()
+            .zip(tags)
+            .map(|(c, tag)| SingBoxOutbound {
+                kind: "shadowsocks",
+                tag,
+                server: &c.server.host,
+                server_port: c.server.port,
+                method: c.method(),
+                password: c.password(),
+                plugin: c.server.plugin.as_deref(),
+                plugin_opts: c.server.plugin_opts.as_deref(),
+            })
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&doc)?)
+}
+
+/// Clash and sing-box refer to proxies by name, so repeated names get a
+/// ` (2)`, ` (3)`… suffix
+fn unique_names(names: impl Iterator<Item = String>) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    names
+        .map(|base| {
+            let mut name = base.clone();
+            let mut n = 1;
+            while !seen.insert(name.clone()) {
+                n += 1;
+                name = format!("{} ({})", base, n);
+            }
+            name
+        })
+        .collect()
+}
+
+/// Subscriptions come padded or not, standard or URL-safe, and often
+/// wrapped across lines
+fn decode_base64(text: &str) -> Option<String> {
+    let compact: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let compact = compact.trim_end_matches('=');
+    let bytes = STANDARD_NO_PAD
+        .decode(compact)
+        .or_else(|_| URL_SAFE_NO_PAD.decode(compact))
+        .ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> String {
+        STANDARD.encode([byte; 16])
+    }
+
+    fn parse_ok(content: &str) -> Vec<SharedServer> {
+        parse(content)
+            .unwrap()
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn sip002_with_base64_userinfo_and_plugin() {
+        let userinfo = URL_SAFE_NO_PAD.encode("aes-256-gcm:secret");
+        let uri = format!(
+            "ss://{userinfo}@example.com:8388/?plugin=obfs-local%3Bobfs%3Dhttp%3Bobfs-host%3Dwww.bing.com#Tokyo%20A"
+        );
+        let [server] = parse_ok(&uri).try_into().unwrap();
+        assert_eq!(server.name.as_deref(), Some("Tokyo A"));
+        assert_eq!((server.host.as_str(), server.port), ("example.com", 8388));
+        assert_eq!(server.method, "aes-256-gcm");
+        assert_eq!(server.password, "secret");
+        assert!(server.identity_keys.is_empty());
+        assert_eq!(server.plugin.as_deref(), Some("obfs-local"));
+        assert_eq!(
+            server.plugin_opts.as_deref(),
+            Some("obfs=http;obfs-host=www.bing.com")
+        );
+    }
+
+    #[test]
+    fn sip002_2022_splits_identity_keys() {
+        let password = format!("{}:{}", key(1), key(2));
+        let uri = format!(
+            "ss://2022-blake3-aes-128-gcm:{}@[2001:db8::1]:443#multi",
+            utf8_percent_encode(&password, COMPONENT)
+        );
+        let [server] = parse_ok(&uri).try_into().unwrap();
+        assert_eq!((server.host.as_str(), server.port), ("2001:db8::1", 443));
+        assert_eq!(server.method, "2022-blake3-aes-128-gcm");
+        assert_eq!(server.identity_keys, vec![key(1)]);
+        assert_eq!(server.password, key(2));
+        assert_eq!(server.plugin, None);
+    }
+
+    #[test]
+    fn legacy_uri_keeps_its_remarks() {
+        let uri = format!(
+            "ss://{}#Old%20node",
+            STANDARD.encode("chacha20-ietf-poly1305:p@ss@192.0.2.7:8443")
+        );
+        let [server] = parse_ok(&uri).try_into().unwrap();
+        assert_eq!(server.name.as_deref(), Some("Old node"));
+        assert_eq!((server.host.as_str(), server.port), ("192.0.2.7", 8443));
+        assert_eq!(server.method, "chacha20-ietf-poly1305");
+        assert_eq!(server.password, "p@ss");
+    }
+
+    #[test]
+    fn bad_lines_fail_on_their_own() {
+        let good = format!(
+            "ss://{}@example.com:8388",
+            URL_SAFE_NO_PAD.encode("aes-128-gcm:pw")
+        );
+        let content = format!(
+            "{good}\n\n  vmess://abc\nss://{}@example.com:8388\n",
+            URL_SAFE_NO_PAD.encode("rot13:pw")
+        );
+        let entries = parse(&content).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries[0].is_ok());
+        assert_eq!(
+            entries[1].as_ref().unwrap_err(),
+            "not an ss:// URI (vmess://)"
+        );
+        assert!(entries[2].is_err());
+    }
+
+    #[test]
+    fn sip008_document() {
+        let id = Uuid::new_v4();
+        let doc = serde_json::json!({
+            "version": 1,
+            "servers": [
+                {
+                    "id": id,
+                    "remarks": "A",
+                    "server": "a.example.com",
+                    "server_port": 8388,
+                    "password": format!("{}:{}", key(1), key(2)),
+                    "method": "2022-blake3-aes-128-gcm",
+                    "plugin": "",
+                    "plugin_opts": ""
+                },
+                {
+                    "server": "b.example.com",
+                    "server_port": 8389,
+                    "password": "pw",
+                    "method": "no-such-cipher"
+                },
+                {
+                    "server": "c.example.com",
+                    "server_port": 8390,
+                    "password": "short",
+                    "method": "2022-blake3-aes-256-gcm"
+                }
+            ]
+        });
+        let entries = parse(&doc.to_string()).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let a = entries[0].as_ref().unwrap();
+        assert_eq!(a.id, Some(id));
+        assert_eq!(a.name.as_deref(), Some("A"));
+        assert_eq!((a.host.as_str(), a.port), ("a.example.com", 8388));
+        assert_eq!(a.identity_keys, vec![key(1)]);
+        assert_eq!(a.password, key(2));
+        assert_eq!((a.plugin.as_ref(), a.plugin_opts.as_ref()), (None, None));
+
+        assert_eq!(
+            entries[1].as_ref().unwrap_err(),
+            "unknown encryption method no-such-cipher"
+        );
+        assert!(entries[2].is_err(), "2022 key of the wrong length");
+
+        assert!(parse(r#"{"servers": 1}"#).is_err());
+    }
+
+    #[test]
+    fn base64_subscription() {
+        let uris = [
+            format!(
+                "ss://{}@a.example.com:1",
+                URL_SAFE_NO_PAD.encode("aes-128-gcm:x")
+            ),
+            format!(
+                "ss://{}@b.example.com:2",
+                URL_SAFE_NO_PAD.encode("aes-256-gcm:y")
+            ),
+        ]
+        .join("\n");
+        // Padded and wrapped across lines, as providers serve them
+        let encoded = STANDARD.encode(&uris);
+        let (first, rest) = encoded.split_at(20);
+        let servers = parse_ok(&format!("{first}\n{rest}\n"));
+        let hosts: Vec<_> = servers.iter().map(|s| s.host.as_str()).collect();
+        assert_eq!(hosts, ["a.example.com", "b.example.com"]);
+
+        let doc = r#"{"servers":[{"server":"c.example.com","server_port":3,"password":"z","method":"aes-128-gcm"}]}"#;
+        let [server] = parse_ok(&URL_SAFE_NO_PAD.encode(doc)).try_into().unwrap();
+        assert_eq!(server.host, "c.example.com");
+
+        assert!(parse("not a subscription").is_err());
+        assert!(parse(&STANDARD.encode("hello")).is_err());
+    }
+
+    #[test]
+    fn exported_uris_parse_back_with_users() {
+        let server: Server = serde_json::from_value(serde_json::json!({
+            "id": Uuid::new_v4(),
+            "name": "Multi",
+            "host": "example.com",
+            "port": 8388,
+            "password": key(2),
+            "method": "2022-blake3-aes-128-gcm",
+            "identity_keys": [key(1)],
+            "users": [{"name": "alice", "password": key(3)}],
+            "plugin": "obfs-local",
+            "plugin_opts": "obfs=tls",
+            "enabled": true
+        }))
+        .unwrap();
+        let parsed = parse_ok(&to_uri_list(&[server]));
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].name.as_deref(), Some("Multi"));
+        assert_eq!(parsed[0].password, key(2));
+        assert_eq!(parsed[1].name.as_deref(), Some("Multi / alice"));
+        assert_eq!(parsed[1].password, key(3));
+        for entry in &parsed {
+            assert_eq!(entry.identity_keys, vec![key(1)]);
+            assert_eq!(entry.plugin.as_deref(), Some("obfs-local"));
+            assert_eq!(entry.plugin_opts.as_deref(), Some("obfs=tls"));
+        }
+    }
+
+    #[test]
+    fn plugin_opts_unescape() {
+        assert_eq!(
+            parse_plugin_opts(r"obfs=http;obfs-host=a\;b;tls;path=\\x"),
+            vec![
+                ("obfs".to_string(), "http".to_string()),
+                ("obfs-host".to_string(), "a;b".to_string()),
+                ("tls".to_string(), String::new()),
+                ("path".to_string(), r"\x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_names_get_suffixes() {
+        let names = ["a", "b", "a", "a"].map(String::from);
+        assert_eq!(
+            unique_names(names.into_iter()),
+            ["a", "b", "a (2)", "a (3)"]
+        );
+    }
+}
@@ -1,8 +1,10 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::config::PublicConfig;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Server {
     pub id: Uuid,
     pub name: String,
@@ -12,9 +14,168 @@ pub struct Server {
     pub password: String,
     /// SS encryption method, e.g. "aes-256-gcm", "chacha20-ietf-poly1305", "2022-blake3-aes-256-gcm"
     pub method: String,
+    /// SIP003 plugin executable, e.g. "obfs-local", "v2ray-plugin"
+    #[serde(default)]
+    pub plugin: Option<String>,
+    /// SIP003 plugin options, e.g. "obfs=http;obfs-host=www.bing.com"
+    #[serde(default)]
+    pub plugin_opts: Option<String>,
+    /// Shadowsocks 2022 identity PSKs (base64), outermost first, sent as
+    /// extensible identity headers ahead of `password` and each user key
+    #[serde(default)]
+    pub identity_keys: Vec<String>,
+    /// Further users of a multi-user 2022 server, each checked with its own key
+    #[serde(default)]
+    pub users: Vec<ServerUser>,
     pub enabled: bool,
     #[serde(default)]
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// Name shown on the public status page instead of `name`
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Group label shown on the public status page
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Days to keep raw check results, overriding `retention.raw_days`
+    #[serde(default)]
+    pub retention_days: Option<u32>,
+    #[serde(flatten)]
+    pub checks: CheckOverrides,
+    /// Name of the subscription that manages this server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Disabled by its subscription after dropping out of the feed, and
+    /// re-enabled if it comes back; servers disabled by hand stay disabled
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled_by_source: bool,
+}
+
+impl Server {
+    /// Password a client uses to connect with `key`: the identity keys,
+    /// if any, followed by `key`, separated by colons
+    pub fn client_password(&self, key: &str) -> String {
+        let mut chain = self.identity_keys.clone();
+        chain.push(key.to_string());
+        chain.join(":")
+    }
+
+    /// Method `user` connects with
+    pub fn user_method<'a>(&'a self, user: &'a ServerUser) -> &'a str {
+        user.method.as_deref().unwrap_or(&self.method)
+    }
+
+    /// Password `user` connects with; the identity keys only go in front
+    /// for users on the server's own method
+    pub fn user_password(&self, user: &ServerUser) -> String {
+        if self.user_method(user) == self.method {
+            self.client_password(&user.password)
+        } else {
+            user.password.clone()
+        }
+    }
+}
+
+/// Another credential checked on the same host and port: a user of a
+/// multi-user Shadowsocks 2022 server, or one of several passwords the
+/// server accepts (ssmanager-style)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerUser {
+    pub name: String,
+    /// The user's PSK (uPSK, base64) or password
+    pub password: String,
+    /// Method of this credential, when it differs from the server's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+}
+
+/// Per-server replacements for the global check settings; unset fields
+/// fall back to the global value
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CheckOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_interval_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ss_timeout_secs: Option<u64>,
+    #[serde(
+        default,
+        alias = "test_target",
+        deserialize_with = "opt_one_or_many",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub test_targets: Option<Vec<String>>,
+    /// Extra attempts after a failed check before the failure is recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+}
+
+/// Test targets as written: one host name, as before they took a list, or
+/// several
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for Vec<String> {
+    fn from(value: OneOrMany) -> Self {
+        match value {
+            OneOrMany::One(one) => vec![one],
+            OneOrMany::Many(many) => many,
+        }
+    }
+}
+
+pub fn one_or_many<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+    OneOrMany::deserialize(d).map(Into::into)
+}
+
+pub fn opt_one_or_many<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<String>>, D::Error> {
+    Ok(Option::<OneOrMany>::deserialize(d)?.map(Into::into))
+}
+
+/// Who can see a server without logging in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Listed on the status page and in the public event stream
+    #[default]
+    Public,
+    /// Not listed, but its history is reachable by id
+    Unlisted,
+    /// Never exposed to anonymous users
+    Hidden,
+}
+
+impl Visibility {
+    pub fn is_listed(self) -> bool {
+        self == Visibility::Public
+    }
+
+    pub fn is_reachable(self) -> bool {
+        self != Visibility::Hidden
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Hidden => "hidden",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "public" => Some(Visibility::Public),
+            "unlisted" => Some(Visibility::Unlisted),
+            "hidden" => Some(Visibility::Hidden),
+            _ => None,
+        }
+    }
 }
 
 /// Public view of a server — hides host, port, password, method
@@ -22,17 +183,23 @@ pub struct Server {
 pub struct PublicServer {
     pub id: Uuid,
     pub name: String,
+    pub group: Option<String>,
     pub enabled: bool,
     pub tags: Vec<String>,
 }
 
-impl From<&Server> for PublicServer {
-    fn from(s: &Server) -> Self {
+impl PublicServer {
+    pub fn new(s: &Server, opts: &PublicConfig) -> Self {
         Self {
             id: s.id,
-            name: s.name.clone(),
+            name: s.display_name.clone().unwrap_or_else(|| s.name.clone()),
+            group: s.group.clone(),
             enabled: s.enabled,
-            tags: s.tags.clone(),
+            tags: if opts.show_tags {
+                s.tags.clone()
+            } else {
+                Vec::new()
+            },
         }
     }
 }
@@ -43,6 +210,36 @@ pub struct CheckResult {
     pub timestamp: DateTime<Utc>,
     pub tcp_check: TcpCheckResult,
     pub ss_check: Option<SsCheckResult>,
+    /// SS checks of the server's `users`, in the same order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_checks: Vec<UserCheckResult>,
+}
+
+impl CheckResult {
+    /// TCP reachable and every SS check that ran passed, the server's own
+    /// and each user's
+    pub fn is_fully_up(&self) -> bool {
+        self.tcp_check.reachable
+            && self.ss_check.as_ref().is_none_or(|ss| ss.success)
+            && self.user_checks.iter().all(|u| u.ss_check.success)
+    }
+
+    /// Copy of this result as shown to anonymous users; user names are
+    /// never shown, users are numbered instead
+    pub fn public(&self, opts: &PublicConfig) -> CheckResult {
+        let mut result = self.clone();
+        for (i, user) in result.user_checks.iter_mut().enumerate() {
+            user.user = (i + 1).to_string();
+        }
+        if !opts.show_errors {
+            result.tcp_check.error = None;
+            let users = result.user_checks.iter_mut().map(|u| &mut u.ss_check);
+            for ss in result.ss_check.iter_mut().chain(users) {
+                ss.error = None;
+            }
+        }
+        result
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,6 +255,165 @@ pub struct SsCheckResult {
     pub success: bool,
     pub latency_ms: Option<f64>,
     pub error: Option<String>,
+    /// What failed, set whenever `success` is false
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<SsErrorKind>,
+}
+
+/// SS check made with one user's key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserCheckResult {
+    pub user: String,
+    #[serde(flatten)]
+    pub ss_check: SsCheckResult,
+}
+
+/// Broad cause of a failed SS check, so plugin trouble can be told apart
+/// from the server itself misbehaving
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SsErrorKind {
+    /// The SIP003 plugin could not be started or never began listening
+    Plugin,
+    Timeout,
+    /// Not attempted because the TCP check failed
+    Skipped,
+    /// Handshake, relay or response failure
+    Protocol,
+}
+
+impl SsErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SsErrorKind::Plugin => "plugin",
+            SsErrorKind::Timeout => "timeout",
+            SsErrorKind::Skipped => "skipped",
+            SsErrorKind::Protocol => "protocol",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "plugin" => Some(SsErrorKind::Plugin),
+            "timeout" => Some(SsErrorKind::Timeout),
+            "skipped" => Some(SsErrorKind::Skipped),
+            "protocol" => Some(SsErrorKind::Protocol),
+            _ => None,
+        }
+    }
+}
+
+/// Restrict a history query to certain outcomes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultFilter {
+    /// TCP unreachable, or the SS check of the server or of a user failed
+    Failures,
+    /// An SS check, the server's or a user's, ran and failed (TCP was
+    /// reachable, so it was not skipped)
+    SsFailures,
+}
+
+/// Time window for long-term statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StatsWindow {
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+    #[serde(rename = "30d")]
+    Month,
+    #[serde(rename = "90d")]
+    Quarter,
+    /// Only on request; mostly read from daily rollups
+    #[serde(rename = "365d")]
+    Year,
+}
+
+impl StatsWindow {
+    /// Windows included in every `ServerStatus`
+    pub const ALL: [StatsWindow; 4] = [
+        StatsWindow::Day,
+        StatsWindow::Week,
+        StatsWindow::Month,
+        StatsWindow::Quarter,
+    ];
+
+    pub fn duration(self) -> Duration {
+        match self {
+            StatsWindow::Day => Duration::hours(24),
+            StatsWindow::Week => Duration::days(7),
+            StatsWindow::Month => Duration::days(30),
+            StatsWindow::Quarter => Duration::days(90),
+            StatsWindow::Year => Duration::days(365),
+        }
+    }
+}
+
+/// Uptime and latency aggregates over a time window, computed from stored results.
+/// SS figures cover the server's own key; users' checks count toward status,
+/// the failure filters and the CSV export's `user_failures` instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UptimeStats {
+    pub window: StatsWindow,
+    pub total_checks: u64,
+    pub tcp_up_checks: u64,
+    /// Checks where the SS protocol check ran (not skipped for TCP being down)
+    pub ss_checks: u64,
+    pub ss_ok_checks: u64,
+    pub uptime_pct: Option<f64>,
+    pub ss_success_pct: Option<f64>,
+    pub avg_latency_ms: Option<f64>,
+    pub min_latency_ms: Option<f64>,
+    pub max_latency_ms: Option<f64>,
+    pub avg_ss_latency_ms: Option<f64>,
+}
+
+impl UptimeStats {
+    pub fn empty(window: StatsWindow) -> Self {
+        Self {
+            window,
+            total_checks: 0,
+            tcp_up_checks: 0,
+            ss_checks: 0,
+            ss_ok_checks: 0,
+            uptime_pct: None,
+            ss_success_pct: None,
+            avg_latency_ms: None,
+            min_latency_ms: None,
+            max_latency_ms: None,
+            avg_ss_latency_ms: None,
+        }
+    }
+}
+
+/// Latency distribution and failure rate for one kind of check over a set of results
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyStats {
+    /// Checks attempted
+    pub attempts: u64,
+    /// Checks that failed (unreachable / SS failure)
+    pub failures: u64,
+    pub failure_pct: Option<f64>,
+    /// Latency samples from successful checks
+    pub samples: u64,
+    pub mean_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub stddev_ms: Option<f64>,
+    /// Mean absolute difference between consecutive samples
+    pub jitter_ms: Option<f64>,
+}
+
+/// Response of the per-server stats API
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerStats {
+    #[serde(flatten)]
+    pub uptime: UptimeStats,
+    /// Latency details over raw results in the window (limited by raw retention)
+    pub tcp_latency: LatencyStats,
+    pub ss_latency: LatencyStats,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,6 +424,11 @@ pub struct ServerStatus {
     pub uptime_pct: f64,
     pub avg_latency_ms: Option<f64>,
     pub total_checks: u64,
+    /// Latency details over `history`
+    pub tcp_latency: LatencyStats,
+    pub ss_latency: LatencyStats,
+    /// Long-term stats for each of `StatsWindow::ALL`
+    pub windows: Vec<UptimeStats>,
 }
 
 /// Public view of server status — uses PublicServer
@@ -79,56 +440,147 @@ pub struct PublicServerStatus {
     pub uptime_pct: f64,
     pub avg_latency_ms: Option<f64>,
     pub total_checks: u64,
+    pub tcp_latency: LatencyStats,
+    pub ss_latency: LatencyStats,
+    pub windows: Vec<UptimeStats>,
 }
 
-impl From<&ServerStatus> for PublicServerStatus {
-    fn from(s: &ServerStatus) -> Self {
+impl PublicServerStatus {
+    pub fn new(s: &ServerStatus, opts: &PublicConfig) -> Self {
         Self {
-            server: PublicServer::from(&s.server),
-            latest_result: s.latest_result.clone(),
-            history: s.history.clone(),
+            server: PublicServer::new(&s.server, opts),
+            latest_result: s.latest_result.as_ref().map(|r| r.public(opts)),
+            history: s.history.iter().map(|r| r.public(opts)).collect(),
             uptime_pct: s.uptime_pct,
             avg_latency_ms: s.avg_latency_ms,
             total_checks: s.total_checks,
+            tcp_latency: s.tcp_latency.clone(),
+            ss_latency: s.ss_latency.clone(),
+            windows: s.windows.clone(),
         }
     }
 }
 
+/// Aggregate health of a status-page group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupHealth {
+    /// Every checked server is fully up
+    Up,
+    /// Some servers are up, some are not
+    Partial,
+    /// No checked server is up
+    Down,
+    /// No enabled server has been checked yet
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupStatus {
+    pub name: String,
+    pub description: Option<String>,
+    pub order: i32,
+    pub status: GroupHealth,
+    /// Mean uptime of the group's enabled servers that have history
+    pub uptime_pct: Option<f64>,
+    pub servers_up: usize,
+    pub servers_total: usize,
+    pub server_ids: Vec<Uuid>,
+}
+
 /// Internal SSE event (broadcast between backend components, contains full data)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum SseEvent {
-    CheckComplete { result: CheckResult },
-    ServerUpdated { server: Server },
-    ServerRemoved { server_id: Uuid },
-    Snapshot { statuses: Vec<ServerStatus> },
+    CheckComplete {
+        result: CheckResult,
+    },
+    /// `was_listed`: whether the server was publicly listed before this change
+    ServerUpdated {
+        server: Box<Server>,
+        #[serde(default)]
+        was_listed: bool,
+    },
+    ServerRemoved {
+        server_id: Uuid,
+        #[serde(default)]
+        was_listed: bool,
+    },
+    /// Group statuses, computed over publicly listed servers only
+    GroupsUpdated {
+        groups: Vec<GroupStatus>,
+    },
+    Snapshot {
+        statuses: Vec<ServerStatus>,
+        groups: Vec<GroupStatus>,
+    },
 }
 
 /// Public SSE event sent to all clients — no sensitive server info
 #[derive(Debug, Clone, Serialize)]
 #[serde(tag = "type")]
 pub enum PublicSseEvent {
-    CheckComplete { result: CheckResult },
-    ServerUpdated { server: PublicServer },
-    ServerRemoved { server_id: Uuid },
-    Snapshot { statuses: Vec<PublicServerStatus> },
+    CheckComplete {
+        result: CheckResult,
+    },
+    ServerUpdated {
+        server: PublicServer,
+    },
+    ServerRemoved {
+        server_id: Uuid,
+    },
+    GroupsUpdated {
+        groups: Vec<GroupStatus>,
+    },
+    Snapshot {
+        statuses: Vec<PublicServerStatus>,
+        groups: Vec<GroupStatus>,
+    },
 }
 
-impl From<&SseEvent> for PublicSseEvent {
-    fn from(e: &SseEvent) -> Self {
+impl PublicSseEvent {
+    /// Convert an internal event for public consumption.
+    /// `visibility` is the current visibility of the server the event refers to;
+    /// events about servers that are not publicly listed are dropped, and an update
+    /// that makes a listed server unlisted is reported as a removal. Servers that
+    /// were never listed are not mentioned at all, so their ids stay private.
+    pub fn new(e: &SseEvent, visibility: Visibility, opts: &PublicConfig) -> Option<Self> {
         match e {
-            SseEvent::CheckComplete { result } => PublicSseEvent::CheckComplete {
-                result: result.clone(),
-            },
-            SseEvent::ServerUpdated { server } => PublicSseEvent::ServerUpdated {
-                server: PublicServer::from(server),
-            },
-            SseEvent::ServerRemoved { server_id } => PublicSseEvent::ServerRemoved {
+            SseEvent::CheckComplete { result } => {
+                visibility
+                    .is_listed()
+                    .then(|| PublicSseEvent::CheckComplete {
+                        result: result.public(opts),
+                    })
+            }
+            SseEvent::ServerUpdated { server, was_listed } => {
+                if server.visibility.is_listed() {
+                    Some(PublicSseEvent::ServerUpdated {
+                        server: PublicServer::new(server, opts),
+                    })
+                } else {
+                    was_listed.then(|| PublicSseEvent::ServerRemoved {
+                        server_id: server.id,
+                    })
+                }
+            }
+            SseEvent::ServerRemoved {
+                server_id,
+                was_listed,
+            } => was_listed.then(|| PublicSseEvent::ServerRemoved {
                 server_id: *server_id,
-            },
-            SseEvent::Snapshot { statuses } => PublicSseEvent::Snapshot {
-                statuses: statuses.iter().map(PublicServerStatus::from).collect(),
-            },
+            }),
+            SseEvent::GroupsUpdated { groups } => Some(PublicSseEvent::GroupsUpdated {
+                groups: groups.clone(),
+            }),
+            SseEvent::Snapshot { statuses, groups } => Some(PublicSseEvent::Snapshot {
+                statuses: statuses
+                    .iter()
+                    .filter(|s| s.server.visibility.is_listed())
+                    .map(|s| PublicServerStatus::new(s, opts))
+                    .collect(),
+                groups: groups.clone(),
+            }),
         }
     }
 }
@@ -141,10 +593,28 @@ pub struct CreateServerRequest {
     pub password: String,
     #[serde(default = "default_method")]
     pub method: String,
+    #[serde(default)]
+    pub plugin: Option<String>,
+    #[serde(default)]
+    pub plugin_opts: Option<String>,
+    #[serde(default)]
+    pub identity_keys: Vec<String>,
+    #[serde(default)]
+    pub users: Vec<ServerUser>,
     #[serde(default = "default_enabled")]
     pub enabled: bool,
     #[serde(default)]
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub retention_days: Option<u32>,
+    #[serde(flatten)]
+    pub checks: CheckOverrides,
 }
 
 fn default_enabled() -> bool {
@@ -164,8 +634,57 @@ impl CreateServerRequest {
             port: self.port,
             password: self.password,
             method: self.method,
+            plugin: self.plugin,
+            plugin_opts: self.plugin_opts,
+            identity_keys: self.identity_keys,
+            users: self.users,
             enabled: self.enabled,
             tags: self.tags,
+            visibility: self.visibility,
+            display_name: self.display_name,
+            group: self.group,
+            retention_days: self.retention_days,
+            checks: self.checks,
+            source: None,
+            disabled_by_source: false,
         }
     }
 }
+
+/// A rejected request field, e.g. `method` or `users[1].password`
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.field, self.message)
+    }
+}
+
+/// Outcome of one subscription sync
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncLogEntry {
+    pub subscription: String,
+    pub timestamp: DateTime<Utc>,
+    pub added: u32,
+    pub updated: u32,
+    pub disabled: u32,
+    /// Entries skipped because another server already has their host and port
+    pub duplicates: u32,
+    pub invalid: u32,
+    /// Why the sync failed; nothing was changed if set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
@@ -0,0 +1,393 @@
+//! Apply edits to the config file without a restart.
+//!
+//! The file is re-read whenever it changes on disk and on SIGHUP. A config
+//! that fails to parse or validate is rejected as a whole and the running
+//! state is left untouched; sessions always survive a reload.
+//!
+//! In database mode the server list is also re-read from the database every
+//! `SERVER_REFRESH_INTERVAL`, so instances sharing it see each other's edits.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{mpsc, RwLockWriteGuard};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use uuid::Uuid;
+
+use crate::config::{self, AppConfig, ServerStore};
+use crate::models::{Server, SseEvent};
+use crate::state::{broadcast_groups, set_retention, shutdown_requested, SharedState};
+
+/// Editors and deploy tools touch the file several times per save
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// How often database mode picks up servers changed by other instances
+const SERVER_REFRESH_INTERVAL: Duration = Duration::from_secs(15);
+
+pub fn start_reloader(state: SharedState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let Some(path) = state.config_path.clone() else {
+            return;
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let _watcher = match watch(&path, tx) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                tracing::warn!(
+                    "Cannot watch {} for changes, reload with SIGHUP: {}",
+                    path,
+                    e
+                );
+                None
+            }
+        };
+        let mut hangup = match Hangup::new() {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGHUP: {}", e);
+                Hangup::never()
+            }
+        };
+
+        loop {
+            tokio::select! {
+                Some(()) = rx.recv() => {
+                    tokio::time::sleep(DEBOUNCE).await;
+                    while rx.try_recv().is_ok() {}
+                }
+                () = hangup.recv() => {
+                    tracing::info!("SIGHUP received, reloading {}", path);
+                }
+                () = shutdown_requested(&state) => return,
+            }
+            reload(&state, &path).await;
+        }
+    })
+}
+
+/// In database mode, reload the server list from the database periodically
+pub fn start_server_refresh(state: SharedState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if state.server_store != ServerStore::Database {
+            return;
+        }
+        let mut tick = tokio::time::interval(SERVER_REFRESH_INTERVAL);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick is immediate, and the list was only just loaded
+        tick.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = tick.tick() => refresh_servers(&state).await,
+                () = shutdown_requested(&state) => return,
+            }
+        }
+    })
+}
+
+async fn refresh_servers(state: &SharedState) {
+    // Loaded under the lock, as servers are saved, so an edit made here
+    // between the load and the apply cannot be undone by it
+    let current = state.servers.write().await;
+    let servers = match state.db.load_servers().await {
+        Ok(servers) => servers,
+        Err(e) => {
+            tracing::warn!("Failed to reload servers from the database: {}", e);
+            return;
+        }
+    };
+    let (added, updated, removed) = apply_servers(state, current, servers).await;
+    if added + updated + removed == 0 {
+        return;
+    }
+    tracing::info!(
+        "Servers reloaded from the database: {} added, {} updated, {} removed",
+        added,
+        updated,
+        removed
+    );
+    broadcast_groups(state).await;
+}
+
+/// Watch the directory rather than the file, so replacing the file by
+/// rename (as editors and Ansible do) keeps being noticed
+fn watch(path: &str, tx: mpsc::UnboundedSender<()>) -> notify::Result<RecommendedWatcher> {
+    let path = PathBuf::from(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = path.file_name().map(|n| n.to_os_string());
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else {
+            return;
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        if event
+            .paths
+            .iter()
+            .any(|p| p.file_name().map(|n| n.to_os_string()) == file_name)
+        {
+            let _ = tx.send(());
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// Re-read the config file and apply it, or log why it was rejected
+pub async fn reload(state: &SharedState, path: &str) {
+    let yaml = match std::fs::read_to_string(path) {
+        Ok(yaml) => yaml,
+        Err(e) => {
+            tracing::warn!("Cannot read {}, not reloading: {}", path, e);
+            return;
+        }
+    };
+    // Our own write; the state may already be ahead of it
+    if state.config_written.read().await.as_deref() == Some(yaml.as_str()) {
+        return;
+    }
+    match config::parse(&yaml) {
+        Ok(config) => apply(state, config).await,
+        Err(e) => tracing::error!("Rejected config reload, keeping the running config: {}", e),
+    }
+}
+
+async fn apply(state: &SharedState, config: AppConfig) {
+    if config.listen != state.listen {
+        tracing::warn!("Changing listen requires a restart");
+    }
+    if config.server_store != state.server_store {
+        tracing::warn!("Changing server_store requires a restart");
+    }
+
+    let mut changed: Vec<&str> = Vec::new();
+
+    set_if_changed(&state.public, config.public, "public", &mut changed).await;
+    set_if_changed(&state.auth, config.auth, "auth", &mut changed).await;
+    set_if_changed(&state.groups, config.groups, "groups", &mut changed).await;
+
+    // In database mode servers and check settings live in the database and
+    // are only changed through the API
+    if state.server_store == ServerStore::Database {
+        if changed.is_empty() {
+            tracing::debug!("Config reloaded, nothing changed");
+            return;
+        }
+        tracing::info!(
+            "Config reloaded: settings changed: [{}]",
+            changed.join(", ")
+        );
+        broadcast_groups(state).await;
+        return;
+    }
+
+    set_if_changed(
+        &state.check_interval_secs,
+        config.check_interval_secs,
+        "check_interval_secs",
+        &mut changed,
+    )
+    .await;
+    set_if_changed(
+        &state.tcp_timeout_secs,
+        config.tcp_timeout_secs,
+        "tcp_timeout_secs",
+        &mut changed,
+    )
+    .await;
+    set_if_changed(
+        &state.ss_timeout_secs,
+        config.ss_timeout_secs,
+        "ss_timeout_secs",
+        &mut changed,
+    )
+    .await;
+    set_if_changed(
+        &state.test_targets,
+        config.test_targets,
+        "test_targets",
+        &mut changed,
+    )
+    .await;
+    set_if_changed(&state.retries, config.retries, "retries", &mut changed).await;
+    set_if_changed(
+        &state.max_concurrent_checks,
+        config.max_concurrent_checks,
+        "max_concurrent_checks",
+        &mut changed,
+    )
+    .await;
+    set_if_changed(
+        &state.jitter_pct,
+        config.jitter_pct,
+        "jitter_pct",
+        &mut changed,
+    )
+    .await;
+    set_if_changed(
+        &state.missed_ticks,
+        config.missed_ticks,
+        "missed_ticks",
+        &mut changed,
+    )
+    .await;
+    set_if_changed(
+        &state.subscriptions,
+        config.subscriptions,
+        "subscriptions",
+        &mut changed,
+    )
+    .await;
+
+    if *state.retention.read().await != config.retention {
+        set_retention(state, config.retention).await;
+        changed.push("retention");
+    }
+
+    let servers = config.servers.iter().map(|sc| sc.to_server()).collect();
+    let (added, updated, removed) =
+        apply_servers(state, state.servers.write().await, servers).await;
+
+    if changed.is_empty() && added + updated + removed == 0 {
+        tracing::debug!("Config reloaded, nothing changed");
+        return;
+    }
+    tracing::info!(
+        "Config reloaded: {} server(s) added, {} updated, {} removed; settings changed: [{}]",
+        added,
+        updated,
+        removed,
+        changed.join(", ")
+    );
+
+    broadcast_groups(state).await;
+
+    // Write back ids generated for servers added without one
+    if added > 0 {
+        if let Err(e) = config::persist(state).await {
+            tracing::error!("Failed to persist config: {}", e);
+        }
+    }
+}
+
+/// Replace `current` with `value` if they differ; returns whether it changed
+async fn set_if_changed<T: PartialEq>(
+    current: &tokio::sync::RwLock<T>,
+    value: T,
+    name: &'static str,
+    changed: &mut Vec<&'static str>,
+) -> bool {
+    let mut current = current.write().await;
+    if *current == value {
+        return false;
+    }
+    *current = value;
+    changed.push(name);
+    true
+}
+
+/// Make the live server list (`current`, the locked `state.servers`) match
+/// `servers`, returning the number of servers added, updated and removed
+async fn apply_servers(
+    state: &SharedState,
+    mut current: RwLockWriteGuard<'_, HashMap<Uuid, Server>>,
+    servers: Vec<Server>,
+) -> (usize, usize, usize) {
+    let mut next: HashMap<Uuid, Server> = servers.into_iter().map(|s| (s.id, s)).collect();
+    let mut events = Vec::new();
+    let (mut added, mut updated) = (0, 0);
+
+    let removed: Vec<Server> = {
+        let removed: Vec<Uuid> = current
+            .keys()
+            .filter(|id| !next.contains_key(id))
+            .copied()
+            .collect();
+        let removed: Vec<Server> = removed.iter().filter_map(|id| current.remove(id)).collect();
+
+        for (id, server) in next.drain() {
+            let was_listed = match current.get(&id) {
+                Some(existing) if *existing == server => continue,
+                Some(existing) => {
+                    updated += 1;
+                    existing.visibility.is_listed()
+                }
+                None => {
+                    added += 1;
+                    false
+                }
+            };
+            events.push(SseEvent::ServerUpdated {
+                server: Box::new(server.clone()),
+                was_listed,
+            });
+            current.insert(id, server);
+        }
+        removed
+    };
+    drop(current);
+
+    if !removed.is_empty() {
+        let mut results = state.results.write().await;
+        for server in &removed {
+            results.remove(&server.id);
+        }
+    }
+
+    for event in events {
+        let _ = state.sse_tx.send(event);
+    }
+    for server in &removed {
+        let _ = state.sse_tx.send(SseEvent::ServerRemoved {
+            server_id: server.id,
+            was_listed: server.visibility.is_listed(),
+        });
+    }
+
+    (added, updated, removed.len())
+}
+
+/// SIGHUP listener; never fires where signals are unavailable
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    #[cfg(unix)]
+    fn new() -> std::io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self {
+            signal: Some(signal(SignalKind::hangup())?),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> std::io::Result<Self> {
+        Ok(Self::never())
+    }
+
+    fn never() -> Self {
+        Self {
+            #[cfg(unix)]
+            signal: None,
+        }
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = self.signal.as_mut() {
+            if signal.recv().await.is_some() {
+                return;
+            }
+        }
+        std::future::pending::<()>().await
+    }
+}
@@ -1,101 +1,386 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::Duration;
 
-use tokio::task::JoinHandle;
+use chrono::Utc;
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+use tokio::time::{Instant, MissedTickBehavior};
+use uuid::Uuid;
 
 use crate::checker::check_server;
-use crate::models::{Server, SseEvent};
-use crate::state::{SharedState, MAX_HISTORY};
+use crate::config::MissedTicks;
+use crate::db::Rollup;
+use crate::models::{CheckResult, Server, SseEvent};
+use crate::state::{
+    begin_check, broadcast_groups, check_interval, check_options, is_in_flight, record_results,
+    refresh_stats_cache, shutdown_requested, ApiCheck, InFlightGuard, SchedulerStats, SharedState,
+};
 
-const KEEP_DAYS: i64 = 7;
+/// How often the scheduler starts due checks and stores finished results
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often rollups and cached stats are brought up to date
+const STATS_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
 
+/// When a server's last scheduled check was due
+struct Slot {
+    last: Instant,
+    /// Fraction of the interval added to (or taken off) the next wait
+    jitter: f64,
+    /// Already counted as overrun for the current round
+    overrun: bool,
+}
+
+/// Check each enabled server once its own interval has passed since its
+/// last scheduled check, at most `max_concurrent_checks` at a time and never
+/// while another check of the same server is running. Results go out over
+/// SSE as each check finishes and are stored in one batch per poll. Checks
+/// queued from the API run on the same task set but store their own result.
+/// On shutdown no new checks start; running ones get as long as the slowest
+/// possible check (see `drain_timeout`) and everything finished is stored
+/// before the task ends.
 pub fn start_scheduler(state: SharedState) -> JoinHandle<()> {
-    let mut interval_rx = state.interval_tx.subscribe();
+    let mut api_checks = state
+        .api_check_rx
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .expect("scheduler already started");
 
     tokio::spawn(async move {
-        let mut secs = *interval_rx.borrow_and_update();
-        let mut interval = tokio::time::interval(Duration::from_secs(secs));
-        let mut check_count: u64 = 0;
+        let mut slots: HashMap<Uuid, Slot> = HashMap::new();
+        // Scheduled checks yield their result; API checks store theirs and yield `None`
+        let mut checks: JoinSet<Option<CheckResult>> = JoinSet::new();
+        let mut finished: Vec<CheckResult> = Vec::new();
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         loop {
             tokio::select! {
-                _ = interval.tick() => {
-                    run_all_checks(&state).await;
+                _ = poll.tick() => {
+                    if !finished.is_empty() {
+                        record_results(&state, &finished).await;
+                        finished.clear();
+                        broadcast_groups(&state).await;
+                    }
 
-                    // Cleanup old records every ~100 checks
-                    check_count += 1;
-                    if check_count % 100 == 0 {
-                        cleanup_db(&state).await;
+                    let limit = *state.max_concurrent_checks.read().await;
+                    let free = limit.saturating_sub(checks.len());
+                    for (server, guard) in due_servers(&state, &mut slots, free).await {
+                        let state = Arc::clone(&state);
+                        checks.spawn(async move {
+                            let _guard = guard;
+                            let opts = check_options(&state, &server).await;
+                            Some(check_server(&server, &opts).await)
+                        });
                     }
                 }
-                Ok(()) = interval_rx.changed() => {
-                    secs = *interval_rx.borrow_and_update();
-                    tracing::info!("Check interval changed to {}s", secs);
-                    interval = tokio::time::interval(Duration::from_secs(secs));
-                    interval.tick().await;
+                Some(check) = api_checks.recv() => {
+                    checks.spawn(run_api_check(Arc::clone(&state), check));
                 }
+                Some(joined) = checks.join_next(), if !checks.is_empty() => {
+                    check_finished(&state, joined, &mut finished);
+                }
+                () = shutdown_requested(&state) => break,
             }
         }
+        // Checks queued but not started are cancelled
+        drop(api_checks);
+
+        if !checks.is_empty() {
+            let timeout = drain_timeout(&state).await;
+            tracing::info!(
+                "Waiting up to {:?} for {} running check(s)",
+                timeout,
+                checks.len()
+            );
+            let drain = async {
+                while let Some(joined) = checks.join_next().await {
+                    check_finished(&state, joined, &mut finished);
+                }
+            };
+            if tokio::time::timeout(timeout, drain).await.is_err() {
+                tracing::warn!(
+                    "Abandoning {} check(s) still running after {:?}",
+                    checks.len(),
+                    timeout
+                );
+                checks.shutdown().await;
+            }
+        }
+        if !finished.is_empty() {
+            record_results(&state, &finished).await;
+        }
     })
 }
 
-pub async fn run_all_checks(state: &SharedState) {
+/// Run a check queued from the API, then store and announce its result
+/// before handing it back
+async fn run_api_check(state: SharedState, check: ApiCheck) -> Option<CheckResult> {
+    let ApiCheck {
+        server,
+        guard,
+        reply,
+    } = check;
+    let opts = check_options(&state, &server).await;
+    let result = check_server(&server, &opts).await;
+    drop(guard);
+
+    record_results(&state, std::slice::from_ref(&result)).await;
+    let _ = state.sse_tx.send(SseEvent::CheckComplete {
+        result: result.clone(),
+    });
+    broadcast_groups(&state).await;
+    let _ = reply.send(result);
+    None
+}
+
+/// How long running checks get to finish on shutdown: the longest any
+/// server's check can take with its timeouts and retries
+async fn drain_timeout(state: &SharedState) -> Duration {
+    let servers: Vec<Server> = state.servers.read().await.values().cloned().collect();
+    let mut longest = Duration::ZERO;
+    for server in &servers {
+        longest = longest.max(check_options(state, server).await.max_duration());
+    }
+    longest
+}
+
+/// Announce a finished scheduled check and queue its result for storage
+fn check_finished(
+    state: &SharedState,
+    joined: Result<Option<CheckResult>, JoinError>,
+    finished: &mut Vec<CheckResult>,
+) {
+    match joined {
+        Ok(None) => {}
+        Ok(Some(result)) => {
+            let _ = state.sse_tx.send(SseEvent::CheckComplete {
+                result: result.clone(),
+            });
+            finished.push(result);
+        }
+        Err(e) => tracing::error!("Check task failed: {}", e),
+    }
+}
+
+/// Up to `limit` enabled servers whose jittered interval has passed, most
+/// overdue first, each claimed for checking. A server seen for the first
+/// time is placed at a random point of its first interval, so a restart does
+/// not check everything at once. A server still being checked stays due
+/// until that check ends; what happens to the rounds it missed depends on
+/// `missed_ticks`.
+async fn due_servers(
+    state: &SharedState,
+    slots: &mut HashMap<Uuid, Slot>,
+    limit: usize,
+) -> Vec<(Server, InFlightGuard)> {
     let servers: Vec<Server> = {
         let map = state.servers.read().await;
         map.values().filter(|s| s.enabled).cloned().collect()
     };
+    let ids: HashSet<Uuid> = servers.iter().map(|s| s.id).collect();
+    slots.retain(|id, _| ids.contains(id));
 
-    if servers.is_empty() {
-        return;
+    let mut stats = SchedulerStats::default();
+    let now = Instant::now();
+    let mut due: Vec<(Duration, Duration, Server)> = Vec::new();
+    for server in servers {
+        let interval = check_interval(state, &server).await;
+        let slot = slots.entry(server.id).or_insert_with(|| Slot {
+            last: now
+                .checked_sub(interval.mul_f64(rand::random::<f64>()))
+                .unwrap_or(now),
+            jitter: 0.0,
+            overrun: false,
+        });
+        let wait = interval.mul_f64(1.0 + slot.jitter);
+        // Half a poll of slack so a 60s wait fires on the 60th poll, not the 61st
+        let elapsed = now.duration_since(slot.last) + POLL_INTERVAL / 2;
+        if elapsed < wait {
+            continue;
+        }
+        if is_in_flight(state, server.id) {
+            if !slot.overrun {
+                slot.overrun = true;
+                stats.overruns += 1;
+            }
+            continue;
+        }
+        due.push((elapsed, wait, server));
     }
 
-    let tcp_timeout = Duration::from_secs(state.tcp_timeout_secs);
-    let ss_timeout = Duration::from_secs(state.ss_timeout_secs);
-    let test_target = state.test_target.clone();
+    due.sort_by_key(|(elapsed, wait, _)| std::cmp::Reverse(*elapsed - *wait));
+    due.truncate(limit);
 
-    let futures: Vec<_> = servers
-        .iter()
-        .map(|server| {
-            let server = server.clone();
-            let test_target = test_target.clone();
-            async move { check_server(&server, tcp_timeout, ss_timeout, &test_target).await }
-        })
-        .collect();
+    let missed_ticks = *state.missed_ticks.read().await;
+    let spread = f64::from(*state.jitter_pct.read().await) / 100.0;
+    let mut claimed = Vec::new();
+    for (elapsed, wait, server) in due {
+        // Lost a race with a manual check; try again next poll
+        let Some(guard) = begin_check(state, server.id) else {
+            continue;
+        };
+        let Some(slot) = slots.get_mut(&server.id) else {
+            continue;
+        };
+        // Whole rounds that came due before this one
+        let missed = ((elapsed.as_secs_f64() / wait.as_secs_f64()) as u32).saturating_sub(1);
+        slot.last = match missed_ticks {
+            MissedTicks::Delay => now,
+            MissedTicks::Skip => slot.last + wait * (missed + 1),
+            MissedTicks::Burst => slot.last + wait,
+        };
+        if missed_ticks != MissedTicks::Burst {
+            stats.skipped_rounds += u64::from(missed);
+        }
+        slot.jitter = (rand::random::<f64>() * 2.0 - 1.0) * spread;
+        slot.overrun = false;
+        stats.checks_started += 1;
+        claimed.push((server, guard));
+    }
 
-    let results = futures::future::join_all(futures).await;
+    if stats.checks_started + stats.overruns > 0 {
+        let mut total = state.scheduler_stats.write().await;
+        total.checks_started += stats.checks_started;
+        total.overruns += stats.overruns;
+        total.skipped_rounds += stats.skipped_rounds;
+    }
+    claimed
+}
 
-    // Save to SQLite
-    {
-        let db = state.db.lock().await;
-        for result in &results {
-            if let Err(e) = crate::db::insert_result(&db, result) {
-                tracing::error!("Failed to save result to db: {}", e);
+/// Every `STATS_REFRESH_INTERVAL`, roll up completed buckets and recompute
+/// the cached stats. Kept off the scheduler so slow aggregate queries never
+/// hold up starting checks or storing their results.
+pub fn start_stats_refresh(state: SharedState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(STATS_REFRESH_INTERVAL);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick is immediate, and startup has just refreshed the stats
+        tick.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = tick.tick() => {
+                    rollup_db(&state).await;
+                    refresh_stats_cache(&state).await;
+                }
+                () = shutdown_requested(&state) => return,
             }
         }
+    })
+}
+
+/// Run the retention cleanup once a day at `retention.cleanup_at` (UTC)
+pub fn start_cleanup(state: SharedState) -> JoinHandle<()> {
+    let mut cleanup_at_rx = state.cleanup_at_tx.subscribe();
+
+    tokio::spawn(async move {
+        loop {
+            let at = *cleanup_at_rx.borrow_and_update();
+            let now = Utc::now();
+            let mut next = now.date_naive().and_time(at).and_utc();
+            if next <= now {
+                next += chrono::Duration::days(1);
+            }
+            tracing::debug!("Next retention cleanup at {}", next);
+            // Recomputed every round so the schedule tracks the wall clock
+            tokio::select! {
+                _ = tokio::time::sleep((next - now).to_std().unwrap_or_default()) => {
+                    cleanup_db(&state).await;
+                }
+                Ok(()) = cleanup_at_rx.changed() => {}
+                () = shutdown_requested(&state) => return,
+            }
+        }
+    })
+}
+
+/// Roll up any buckets completed since the last tick; cheap when there are none
+async fn rollup_db(state: &SharedState) {
+    for rollup in [Rollup::Hourly, Rollup::Daily] {
+        match state.db.update_rollups(rollup).await {
+            Ok(0) => {}
+            Ok(n) => tracing::debug!("Rolled up {} {:?} bucket(s)", n, rollup),
+            Err(e) => tracing::error!("Failed to update {:?} rollups: {}", rollup, e),
+        }
     }
+}
+
+/// Apply the retention policy to raw results and rollups, then reclaim space
+pub async fn cleanup_db(state: &SharedState) {
+    let retention = state.retention.read().await.clone();
+    let overrides: Vec<(Uuid, i64)> = {
+        let servers = state.servers.read().await;
+        servers
+            .values()
+            .filter_map(|s| s.retention_days.map(|days| (s.id, days.into())))
+            .collect()
+    };
 
-    // Update in-memory cache
+    match state
+        .db
+        .cleanup_old(retention.raw_days.into(), &overrides)
+        .await
     {
-        let mut results_map = state.results.write().await;
-        for result in &results {
-            let history = results_map.entry(result.server_id).or_default();
-            history.insert(0, result.clone());
-            history.truncate(MAX_HISTORY);
+        Ok(deleted) => {
+            if deleted > 0 {
+                tracing::info!(
+                    "Cleaned up {} old check results (>{} days)",
+                    deleted,
+                    retention.raw_days
+                );
+            }
         }
+        Err(e) => tracing::error!("Failed to cleanup old results: {}", e),
     }
 
-    for result in results {
-        let _ = state.sse_tx.send(SseEvent::CheckComplete { result });
+    if let Some(max_rows) = retention.max_rows_per_server {
+        match state.db.trim_results_per_server(max_rows).await {
+            Ok(deleted) => {
+                if deleted > 0 {
+                    tracing::info!(
+                        "Trimmed {} check results (>{} rows per server)",
+                        deleted,
+                        max_rows
+                    );
+                }
+            }
+            Err(e) => tracing::error!("Failed to trim check results: {}", e),
+        }
+    }
+
+    for (rollup, days) in [
+        (Rollup::Hourly, retention.hourly_days),
+        (Rollup::Daily, retention.daily_days),
+    ] {
+        match state.db.cleanup_rollups(rollup, days.into()).await {
+            Ok(deleted) => {
+                if deleted > 0 {
+                    tracing::info!(
+                        "Cleaned up {} old {:?} rollups (>{} days)",
+                        deleted,
+                        rollup,
+                        days
+                    );
+                }
+            }
+            Err(e) => tracing::error!("Failed to cleanup {:?} rollups: {}", rollup, e),
+        }
     }
-}
 
-async fn cleanup_db(state: &SharedState) {
-    let db = state.db.lock().await;
-    match crate::db::cleanup_old(&db, KEEP_DAYS) {
+    // The sync log follows the raw results
+    match state.db.cleanup_sync_log(retention.raw_days.into()).await {
         Ok(deleted) => {
             if deleted > 0 {
-                tracing::info!("Cleaned up {} old check results (>{} days)", deleted, KEEP_DAYS);
+                tracing::info!("Cleaned up {} old sync log entries", deleted);
             }
         }
-        Err(e) => tracing::error!("Failed to cleanup old results: {}", e),
+        Err(e) => tracing::error!("Failed to cleanup sync log: {}", e),
+    }
+
+    if let Err(e) = state.db.vacuum().await {
+        tracing::error!("Failed to vacuum database: {}", e);
     }
+
+    refresh_stats_cache(state).await;
 }
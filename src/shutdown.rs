@@ -0,0 +1,53 @@
+//! Orderly exit on SIGINT or SIGTERM.
+//!
+//! The signal flips `shutdown_tx`, which stops the HTTP server from taking
+//! new connections, ends SSE streams and tells the background tasks to wind
+//! down. Once they have, [`finish`] writes out the config and closes the
+//! database.
+
+use crate::config;
+use crate::state::SharedState;
+
+/// Wait for SIGINT or SIGTERM, then start shutting down
+pub async fn wait_for_signal(state: SharedState) {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Cannot listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    let name = tokio::select! {
+        () = ctrl_c => "SIGINT",
+        () = terminate => "SIGTERM",
+    };
+    tracing::info!("{} received, shutting down", name);
+    state.shutdown_tx.send_replace(true);
+}
+
+/// Last steps once the server and background tasks have stopped
+pub async fn finish(state: &SharedState) {
+    if let Err(e) = config::persist(state).await {
+        tracing::error!("Failed to persist config: {}", e);
+    }
+    if let Err(e) = state.db.close().await {
+        tracing::error!("Failed to close database: {}", e);
+    }
+    tracing::info!("Shutdown complete");
+}
@@ -1,59 +1,120 @@
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use tokio::sync::{broadcast, watch, Mutex, RwLock};
+use chrono::NaiveTime;
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
 use uuid::Uuid;
 
-use crate::config::{AppConfig, AuthConfig};
-use crate::models::{CheckResult, Server, ServerStatus, SseEvent};
+use crate::checker::CheckOptions;
+use crate::config::{
+    AppConfig, AuthConfig, GroupConfig, MissedTicks, PublicConfig, RetentionConfig, ServerStore,
+    SubscriptionConfig,
+};
+use crate::db::{self, Storage};
+use crate::models::{
+    CheckResult, GroupHealth, GroupStatus, Server, ServerStatus, SseEvent, StatsWindow, UptimeStats,
+};
+use crate::stats::{ss_latency_stats, tcp_latency_stats};
 
 pub const MAX_HISTORY: usize = 100;
 
+/// Database aggregates shown on every status response. Refreshed after each
+/// check round and cleanup instead of being queried per request.
+#[derive(Debug, Default)]
+pub struct StatsCache {
+    /// Stored raw results per server
+    pub total_checks: HashMap<Uuid, u64>,
+    /// Per-window stats, in `StatsWindow::ALL` order
+    pub windows: Vec<HashMap<Uuid, UptimeStats>>,
+}
+
+/// Counters kept by the scheduler since startup
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SchedulerStats {
+    /// Scheduled checks started
+    pub checks_started: u64,
+    /// Times a server came due while its previous check was still running
+    pub overruns: u64,
+    /// Scheduled checks dropped to catch up after an overrun
+    pub skipped_rounds: u64,
+}
+
 pub struct AppState {
     pub servers: RwLock<HashMap<Uuid, Server>>,
     pub results: RwLock<HashMap<Uuid, Vec<CheckResult>>>,
-    pub db: Mutex<rusqlite::Connection>,
+    pub db: Box<dyn Storage>,
     pub sse_tx: broadcast::Sender<SseEvent>,
     pub config_path: Option<String>,
+    /// What `config::persist` last wrote, so the reloader can skip our own writes
+    pub config_written: RwLock<Option<String>>,
+    /// Held while the config file is written, so saves never share its temp file
+    pub config_write_lock: tokio::sync::Mutex<()>,
     pub listen: String,
-    pub auth: AuthConfig,
+    pub auth: RwLock<AuthConfig>,
+    pub public: RwLock<PublicConfig>,
     pub sessions: RwLock<HashSet<String>>,
     pub check_interval_secs: RwLock<u64>,
-    pub interval_tx: watch::Sender<u64>,
-    pub tcp_timeout_secs: u64,
-    pub ss_timeout_secs: u64,
-    pub test_target: String,
+    pub tcp_timeout_secs: RwLock<u64>,
+    pub ss_timeout_secs: RwLock<u64>,
+    pub test_targets: RwLock<Vec<String>>,
+    pub retries: RwLock<u32>,
+    pub max_concurrent_checks: RwLock<usize>,
+    pub jitter_pct: RwLock<u32>,
+    pub missed_ticks: RwLock<MissedTicks>,
+    pub retention: RwLock<RetentionConfig>,
+    /// Wakes the cleanup task when `retention.cleanup_at` changes
+    pub cleanup_at_tx: watch::Sender<NaiveTime>,
+    /// Set to true once SIGINT or SIGTERM arrives
+    pub shutdown_tx: watch::Sender<bool>,
+    pub groups: RwLock<Vec<GroupConfig>>,
+    pub subscriptions: RwLock<Vec<SubscriptionConfig>>,
+    /// Held for the whole of a subscription sync, so syncs never interleave
+    pub sync_lock: tokio::sync::Mutex<()>,
+    pub server_store: ServerStore,
+    pub stats_cache: RwLock<StatsCache>,
+    /// Servers with a check running right now, from any source
+    pub in_flight: Mutex<HashSet<Uuid>>,
+    /// Checks started from the API, run by the scheduler alongside its own
+    pub api_check_tx: mpsc::UnboundedSender<ApiCheck>,
+    /// Taken by the scheduler when it starts
+    pub api_check_rx: Mutex<Option<mpsc::UnboundedReceiver<ApiCheck>>>,
+    pub scheduler_stats: RwLock<SchedulerStats>,
 }
 
 pub type SharedState = Arc<AppState>;
 
-pub fn build_from_config(
-    config: AppConfig,
+pub async fn build_from_config(
+    mut config: AppConfig,
     config_path: String,
-    db_conn: rusqlite::Connection,
-) -> SharedState {
-    let (sse_tx, _) = broadcast::channel(256);
-    let (interval_tx, _) = watch::channel(config.check_interval_secs);
-
-    let mut servers = HashMap::new();
-    for sc in &config.servers {
-        let server = Server {
-            id: sc.id,
-            name: sc.name.clone(),
-            host: sc.host.clone(),
-            port: sc.port,
-            password: sc.password.clone(),
-            method: sc.method.clone(),
-            enabled: sc.enabled,
-            tags: sc.tags.clone(),
-        };
-        servers.insert(server.id, server);
+    db: Box<dyn Storage>,
+) -> anyhow::Result<SharedState> {
+    let mut server_list: Vec<Server> = config.servers.iter().map(|sc| sc.to_server()).collect();
+
+    if config.server_store == ServerStore::Database {
+        let imported = db.import_servers_once(&server_list).await?;
+        if imported > 0 {
+            tracing::info!(
+                "Imported {} server(s) from config into the database",
+                imported
+            );
+        }
+        server_list = db.load_servers().await?;
+        load_db_settings(&*db, &mut config).await?;
     }
+    let check_interval_secs = config.check_interval_secs;
+
+    let (sse_tx, _) = broadcast::channel(256);
+    let (cleanup_at_tx, _) = watch::channel(config.retention.cleanup_time()?);
+    let (api_check_tx, api_check_rx) = mpsc::unbounded_channel();
+
+    let servers: HashMap<Uuid, Server> = server_list.into_iter().map(|s| (s.id, s)).collect();
 
-    // Load history from SQLite for each server
+    // Load history from the database for each server
     let mut results: HashMap<Uuid, Vec<CheckResult>> = HashMap::new();
     for id in servers.keys() {
-        match crate::db::load_results_for_server(&db_conn, *id, MAX_HISTORY) {
+        match db.load_results_for_server(*id, MAX_HISTORY).await {
             Ok(history) => {
                 if !history.is_empty() {
                     results.insert(*id, history);
@@ -63,32 +124,249 @@ pub fn build_from_config(
         }
     }
 
-    Arc::new(AppState {
+    Ok(Arc::new(AppState {
         servers: RwLock::new(servers),
         results: RwLock::new(results),
-        db: Mutex::new(db_conn),
+        db,
         sse_tx,
         config_path: Some(config_path),
+        config_written: RwLock::new(None),
+        config_write_lock: tokio::sync::Mutex::new(()),
         listen: config.listen,
-        auth: config.auth,
+        auth: RwLock::new(config.auth),
+        public: RwLock::new(config.public),
         sessions: RwLock::new(HashSet::new()),
-        check_interval_secs: RwLock::new(config.check_interval_secs),
-        interval_tx,
-        tcp_timeout_secs: config.tcp_timeout_secs,
-        ss_timeout_secs: config.ss_timeout_secs,
-        test_target: config.test_target,
+        check_interval_secs: RwLock::new(check_interval_secs),
+        tcp_timeout_secs: RwLock::new(config.tcp_timeout_secs),
+        ss_timeout_secs: RwLock::new(config.ss_timeout_secs),
+        test_targets: RwLock::new(config.test_targets),
+        retries: RwLock::new(config.retries),
+        max_concurrent_checks: RwLock::new(config.max_concurrent_checks),
+        jitter_pct: RwLock::new(config.jitter_pct),
+        missed_ticks: RwLock::new(config.missed_ticks),
+        retention: RwLock::new(config.retention),
+        cleanup_at_tx,
+        shutdown_tx: watch::channel(false).0,
+        groups: RwLock::new(config.groups),
+        subscriptions: RwLock::new(config.subscriptions),
+        sync_lock: tokio::sync::Mutex::new(()),
+        server_store: config.server_store,
+        stats_cache: RwLock::new(StatsCache::default()),
+        in_flight: Mutex::new(HashSet::new()),
+        api_check_tx,
+        api_check_rx: Mutex::new(Some(api_check_rx)),
+        scheduler_stats: RwLock::new(SchedulerStats::default()),
+    }))
+}
+
+/// Override `config` with the runtime settings saved in database mode
+async fn load_db_settings(db: &dyn Storage, config: &mut AppConfig) -> anyhow::Result<()> {
+    if let Some(v) = db.get_setting(db::SETTING_CHECK_INTERVAL).await? {
+        config.check_interval_secs = v.parse()?;
+    }
+    if let Some(v) = db.get_setting(db::SETTING_TCP_TIMEOUT).await? {
+        config.tcp_timeout_secs = v.parse()?;
+    }
+    if let Some(v) = db.get_setting(db::SETTING_SS_TIMEOUT).await? {
+        config.ss_timeout_secs = v.parse()?;
+    }
+    if let Some(v) = db.get_setting(db::SETTING_TEST_TARGETS).await? {
+        config.test_targets = serde_json::from_str(&v).unwrap_or_else(|_| vec![v]);
+    }
+    if let Some(v) = db.get_setting(db::SETTING_RETRIES).await? {
+        config.retries = v.parse()?;
+    }
+    if let Some(v) = db.get_setting(db::SETTING_MAX_CONCURRENT_CHECKS).await? {
+        config.max_concurrent_checks = v.parse()?;
+    }
+    if let Some(v) = db.get_setting(db::SETTING_JITTER).await? {
+        config.jitter_pct = v.parse()?;
+    }
+    if let Some(v) = db.get_setting(db::SETTING_MISSED_TICKS).await? {
+        config.missed_ticks = serde_json::from_str(&v)?;
+    }
+    if let Some(v) = db.get_setting(db::SETTING_RETENTION).await? {
+        config.retention = serde_json::from_str(&v)?;
+    }
+    if let Some(v) = db.get_setting(db::SETTING_SUBSCRIPTIONS).await? {
+        config.subscriptions = serde_json::from_str(&v)?;
+    }
+    Ok(())
+}
+
+/// Check settings for `server`: its overrides, else the global settings
+pub async fn check_options(state: &AppState, server: &Server) -> CheckOptions {
+    let checks = &server.checks;
+    let tcp_timeout = match checks.tcp_timeout_secs {
+        Some(secs) => secs,
+        None => *state.tcp_timeout_secs.read().await,
+    };
+    let ss_timeout = match checks.ss_timeout_secs {
+        Some(secs) => secs,
+        None => *state.ss_timeout_secs.read().await,
+    };
+    let test_targets = match &checks.test_targets {
+        Some(targets) => targets.clone(),
+        None => state.test_targets.read().await.clone(),
+    };
+    let retries = match checks.retries {
+        Some(retries) => retries,
+        None => *state.retries.read().await,
+    };
+    CheckOptions {
+        tcp_timeout: Duration::from_secs(tcp_timeout),
+        ss_timeout: Duration::from_secs(ss_timeout),
+        test_targets,
+        retries,
+    }
+}
+
+/// Resolves once shutdown has begun
+pub async fn shutdown_requested(state: &AppState) {
+    let mut rx = state.shutdown_tx.subscribe();
+    // The sender lives as long as the state, so this cannot fail
+    let _ = rx.wait_for(|down| *down).await;
+}
+
+/// Marks a server as being checked until dropped
+pub struct InFlightGuard {
+    state: SharedState,
+    server_id: Uuid,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.state
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.server_id);
+    }
+}
+
+/// Claim `server_id` for a check, or `None` if a check of it is already running
+pub fn begin_check(state: &SharedState, server_id: Uuid) -> Option<InFlightGuard> {
+    let mut in_flight = state.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+    if !in_flight.insert(server_id) {
+        return None;
+    }
+    Some(InFlightGuard {
+        state: Arc::clone(state),
+        server_id,
     })
 }
 
+/// A claimed check of one server, waiting for the scheduler to run it
+pub struct ApiCheck {
+    pub server: Server,
+    pub guard: InFlightGuard,
+    /// Gets the result once it is stored
+    pub reply: oneshot::Sender<CheckResult>,
+}
+
+/// Hand a claimed check to the scheduler, so shutdown waits for it like a
+/// scheduled one. The receiver errors if shutdown cut the check short.
+pub fn queue_check(
+    state: &AppState,
+    server: Server,
+    guard: InFlightGuard,
+) -> oneshot::Receiver<CheckResult> {
+    let (reply, rx) = oneshot::channel();
+    // Dropped along with `reply` if the scheduler has already stopped
+    let _ = state.api_check_tx.send(ApiCheck {
+        server,
+        guard,
+        reply,
+    });
+    rx
+}
+
+pub fn is_in_flight(state: &AppState, server_id: Uuid) -> bool {
+    state
+        .in_flight
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .contains(&server_id)
+}
+
+/// How often `server` is due for a check
+pub async fn check_interval(state: &AppState, server: &Server) -> Duration {
+    let secs = match server.checks.check_interval_secs {
+        Some(secs) => secs,
+        None => *state.check_interval_secs.read().await,
+    };
+    Duration::from_secs(secs)
+}
+
+/// Replace the retention policy, rescheduling the daily cleanup if its time moved
+pub async fn set_retention(state: &AppState, retention: RetentionConfig) {
+    // Validated before it gets here
+    let cleanup_at = retention.cleanup_time().unwrap_or_default();
+    *state.retention.write().await = retention;
+    state.cleanup_at_tx.send_if_modified(|at| {
+        let modified = *at != cleanup_at;
+        *at = cleanup_at;
+        modified
+    });
+}
+
 pub async fn is_authenticated(state: &AppState, token: &str) -> bool {
     let sessions = state.sessions.read().await;
     sessions.contains(token)
 }
 
+/// Store a round of results in one batch, then update the in-memory history
+/// and cached check counts. An instance that is not the database's results
+/// writer keeps them in memory only; the counts then come from the writer's
+/// rows on the next stats refresh.
+pub async fn record_results(state: &AppState, results: &[CheckResult]) {
+    let writer = state.db.is_results_writer().await;
+    if writer {
+        if let Err(e) = state.db.insert_results(results).await {
+            tracing::error!("Failed to save results to db: {}", e);
+        }
+    }
+
+    {
+        let mut results_map = state.results.write().await;
+        for result in results {
+            let history = results_map.entry(result.server_id).or_default();
+            history.insert(0, result.clone());
+            history.truncate(MAX_HISTORY);
+        }
+    }
+
+    if !writer {
+        return;
+    }
+    let mut cache = state.stats_cache.write().await;
+    for result in results {
+        *cache.total_checks.entry(result.server_id).or_default() += 1;
+    }
+}
+
+/// Recompute the cached window stats and check counts from the database
+pub async fn refresh_stats_cache(state: &AppState) {
+    let mut windows = Vec::new();
+    for w in StatsWindow::ALL {
+        windows.push(state.db.uptime_stats_all(w).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to compute {:?} stats: {}", w, e);
+            HashMap::new()
+        }));
+    }
+
+    let mut cache = state.stats_cache.write().await;
+    match state.db.count_results_all().await {
+        Ok(total_checks) => cache.total_checks = total_checks,
+        Err(e) => tracing::warn!("Failed to count check results: {}", e),
+    }
+    cache.windows = windows;
+}
+
 pub async fn get_server_statuses(state: &AppState) -> Vec<ServerStatus> {
     let servers = state.servers.read().await;
     let results = state.results.read().await;
-    let db = state.db.lock().await;
+    let cache = state.stats_cache.read().await;
 
     servers
         .values()
@@ -117,9 +395,28 @@ pub async fn get_server_statuses(state: &AppState) -> Vec<ServerStatus> {
                 Some(latencies.iter().sum::<f64>() / latencies.len() as f64)
             };
 
-            let total_checks = crate::db::count_results_for_server(&db, server.id)
+            let total_checks = cache
+                .total_checks
+                .get(&server.id)
+                .copied()
                 .unwrap_or(history.len() as u64);
 
+            let windows = StatsWindow::ALL
+                .iter()
+                .enumerate()
+                .map(|(i, w)| {
+                    cache
+                        .windows
+                        .get(i)
+                        .and_then(|stats| stats.get(&server.id))
+                        .cloned()
+                        .unwrap_or_else(|| UptimeStats::empty(*w))
+                })
+                .collect();
+
+            let tcp_latency = tcp_latency_stats(&history);
+            let ss_latency = ss_latency_stats(&history);
+
             ServerStatus {
                 server: server.clone(),
                 latest_result,
@@ -127,7 +424,124 @@ pub async fn get_server_statuses(state: &AppState) -> Vec<ServerStatus> {
                 uptime_pct,
                 avg_latency_ms,
                 total_checks,
+                tcp_latency,
+                ss_latency,
+                windows,
             }
         })
         .collect()
 }
+
+/// Aggregate server statuses into status-page groups.
+/// Configured groups come first (by `order`, then name), followed by any group
+/// names that servers reference but that have no configuration (sorted by name).
+pub fn compute_group_statuses(
+    configs: &[GroupConfig],
+    statuses: &[ServerStatus],
+) -> Vec<GroupStatus> {
+    let mut groups: Vec<GroupStatus> = configs
+        .iter()
+        .map(|g| empty_group(g.name.clone(), g.description.clone(), g.order))
+        .collect();
+
+    for status in statuses {
+        let Some(name) = status.server.group.as_deref() else {
+            continue;
+        };
+        let idx = match groups.iter().position(|g| g.name == name) {
+            Some(idx) => idx,
+            None => {
+                groups.push(empty_group(name.to_string(), None, i32::MAX));
+                groups.len() - 1
+            }
+        };
+        groups[idx].server_ids.push(status.server.id);
+    }
+
+    let by_id: HashMap<Uuid, &ServerStatus> = statuses.iter().map(|s| (s.server.id, s)).collect();
+
+    for group in groups.iter_mut() {
+        let members: Vec<&ServerStatus> = group
+            .server_ids
+            .iter()
+            .filter_map(|id| by_id.get(id).copied())
+            .filter(|s| s.server.enabled)
+            .collect();
+
+        let checked: Vec<&ServerStatus> = members
+            .iter()
+            .copied()
+            .filter(|s| s.latest_result.is_some())
+            .collect();
+
+        group.servers_total = members.len();
+        group.servers_up = checked.iter().filter(|s| is_fully_up(s)).count();
+        group.status = if checked.is_empty() {
+            GroupHealth::Unknown
+        } else if group.servers_up == checked.len() {
+            GroupHealth::Up
+        } else if group.servers_up == 0 {
+            GroupHealth::Down
+        } else {
+            GroupHealth::Partial
+        };
+
+        let uptimes: Vec<f64> = members
+            .iter()
+            .filter(|s| !s.history.is_empty())
+            .map(|s| s.uptime_pct)
+            .collect();
+        group.uptime_pct = if uptimes.is_empty() {
+            None
+        } else {
+            Some(uptimes.iter().sum::<f64>() / uptimes.len() as f64)
+        };
+    }
+
+    groups.sort_by(|a, b| a.order.cmp(&b.order).then_with(|| a.name.cmp(&b.name)));
+    groups
+}
+
+fn empty_group(name: String, description: Option<String>, order: i32) -> GroupStatus {
+    GroupStatus {
+        name,
+        description,
+        order,
+        status: GroupHealth::Unknown,
+        uptime_pct: None,
+        servers_up: 0,
+        servers_total: 0,
+        server_ids: Vec::new(),
+    }
+}
+
+/// Latest check passed TCP and every SS check that ran, including each user's
+pub fn is_fully_up(status: &ServerStatus) -> bool {
+    status
+        .latest_result
+        .as_ref()
+        .is_some_and(CheckResult::is_fully_up)
+}
+
+/// Group statuses as seen by anonymous users (publicly listed servers only)
+pub async fn public_group_statuses(
+    state: &AppState,
+    statuses: &[ServerStatus],
+) -> Vec<GroupStatus> {
+    let listed: Vec<ServerStatus> = statuses
+        .iter()
+        .filter(|s| s.server.visibility.is_listed())
+        .cloned()
+        .collect();
+    compute_group_statuses(&state.groups.read().await, &listed)
+}
+
+/// Recompute public group statuses and push them to SSE clients
+pub async fn broadcast_groups(state: &AppState) {
+    if state.sse_tx.receiver_count() == 0 {
+        return;
+    }
+    let statuses = get_server_statuses(state).await;
+    let groups = public_group_statuses(state, &statuses).await;
+    let _ = state.sse_tx.send(SseEvent::GroupsUpdated { groups });
+}
@@ -0,0 +1,77 @@
+use crate::models::{CheckResult, LatencyStats};
+
+/// Percentile of an ascending-sorted slice using linear interpolation
+/// between closest ranks. `p` is in `0.0..=100.0`.
+pub fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p / 100.0).clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lo = rank.floor() as usize;
+    let hi = rank.ceil() as usize;
+    Some(sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64))
+}
+
+/// Summarize `(succeeded, latency_ms)` samples given in time order (either direction)
+pub fn latency_stats(samples: impl IntoIterator<Item = (bool, Option<f64>)>) -> LatencyStats {
+    let mut attempts = 0u64;
+    let mut failures = 0u64;
+    let mut latencies = Vec::new();
+    for (ok, latency) in samples {
+        attempts += 1;
+        if !ok {
+            failures += 1;
+        } else if let Some(latency) = latency {
+            latencies.push(latency);
+        }
+    }
+
+    let n = latencies.len();
+    let mean = (n > 0).then(|| latencies.iter().sum::<f64>() / n as f64);
+    let stddev =
+        mean.map(|m| (latencies.iter().map(|l| (l - m).powi(2)).sum::<f64>() / n as f64).sqrt());
+    let jitter = (n > 1).then(|| {
+        latencies
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .sum::<f64>()
+            / (n - 1) as f64
+    });
+
+    let mut sorted = latencies;
+    sorted.sort_by(f64::total_cmp);
+
+    LatencyStats {
+        attempts,
+        failures,
+        failure_pct: (attempts > 0).then(|| failures as f64 / attempts as f64 * 100.0),
+        samples: n as u64,
+        mean_ms: mean,
+        p50_ms: percentile(&sorted, 50.0),
+        p90_ms: percentile(&sorted, 90.0),
+        p99_ms: percentile(&sorted, 99.0),
+        stddev_ms: stddev,
+        jitter_ms: jitter,
+    }
+}
+
+/// TCP latency stats over a list of results
+pub fn tcp_latency_stats(results: &[CheckResult]) -> LatencyStats {
+    latency_stats(
+        results
+            .iter()
+            .map(|r| (r.tcp_check.reachable, r.tcp_check.latency_ms)),
+    )
+}
+
+/// SS protocol latency stats over the results that ran an SS check; ones
+/// skipped because TCP was down are left out
+pub fn ss_latency_stats(results: &[CheckResult]) -> LatencyStats {
+    latency_stats(
+        results
+            .iter()
+            .filter(|r| r.tcp_check.reachable)
+            .filter_map(|r| r.ss_check.as_ref())
+            .map(|ss| (ss.success, ss.latency_ms)),
+    )
+}
@@ -0,0 +1,306 @@
+//! Subscription sources: provider URLs serving a SIP008 document or a base64
+//! `ss://` list, fetched on a schedule and mirrored into the server list.
+//!
+//! Servers a subscription creates carry its name as `source` and as a tag.
+//! Each sync updates their address and credentials, adds servers new to the
+//! feed and disables the ones that dropped out of it, re-enabling them if
+//! they return; names, groups, tags, check settings and servers disabled by
+//! the user are left alone. An entry whose address belongs to another server
+//! counts as a duplicate, and one that fails the checks a server added by
+//! hand must pass counts as invalid; both are skipped. A failed fetch or a
+//! feed with no usable servers changes nothing, so a provider outage does not
+//! disable everything.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::config::{self, SubscriptionConfig};
+use crate::links::{self, SharedServer};
+use crate::models::{CheckOverrides, Server, SseEvent, SyncLogEntry, Visibility};
+use crate::state::{broadcast_groups, shutdown_requested, SharedState};
+
+/// How often subscriptions are looked at to see if one is due
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Whole-request limit for fetching a feed
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Feeds larger than this are refused
+const MAX_FEED_BYTES: usize = 4 * 1024 * 1024;
+
+/// Sync each enabled subscription once its interval has passed since its
+/// last sync, as recorded in the sync log, so restarts do not refetch
+pub fn start_subscriptions(state: SharedState) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                () = shutdown_requested(&state) => return,
+            }
+
+            let subscriptions = state.subscriptions.read().await.clone();
+            for sub in subscriptions.iter().filter(|s| s.enabled) {
+                if !is_due(&state, sub).await {
+                    continue;
+                }
+                tokio::select! {
+                    _ = sync_subscription(&state, sub) => {}
+                    () = shutdown_requested(&state) => return,
+                }
+            }
+        }
+    })
+}
+
+async fn is_due(state: &SharedState, sub: &SubscriptionConfig) -> bool {
+    match state.db.load_sync_log(Some(&sub.name), 1).await {
+        Ok(log) => log.first().is_none_or(|last| {
+            let elapsed = (Utc::now() - last.timestamp).to_std().unwrap_or_default();
+            elapsed >= Duration::from_secs(sub.interval_secs)
+        }),
+        Err(e) => {
+            tracing::error!("Failed to load sync log: {}", e);
+            false
+        }
+    }
+}
+
+/// Fetch `sub` and apply it to the server list, recording the outcome in
+/// the sync log. Syncs never run concurrently.
+pub async fn sync_subscription(state: &SharedState, sub: &SubscriptionConfig) -> SyncLogEntry {
+    let _lock = state.sync_lock.lock().await;
+
+    let mut entry = SyncLogEntry {
+        subscription: sub.name.clone(),
+        timestamp: Utc::now(),
+        added: 0,
+        updated: 0,
+        disabled: 0,
+        duplicates: 0,
+        invalid: 0,
+        error: None,
+    };
+    match fetch(&sub.url).await.and_then(|body| links::parse(&body)) {
+        Ok(parsed) => apply(state, sub, parsed, &mut entry).await,
+        Err(e) => entry.error = Some(e.to_string()),
+    }
+
+    match &entry.error {
+        Some(e) => tracing::warn!("Subscription {} sync failed: {}", sub.name, e),
+        None => tracing::info!(
+            "Subscription {} synced: {} added, {} updated, {} disabled, {} duplicate(s), {} invalid",
+            sub.name,
+            entry.added,
+            entry.updated,
+            entry.disabled,
+            entry.duplicates,
+            entry.invalid
+        ),
+    }
+    if let Err(e) = state.db.insert_sync_log(&entry).await {
+        tracing::error!("Failed to record sync of {}: {}", sub.name, e);
+    }
+    entry
+}
+
+async fn fetch(url: &str) -> Result<String> {
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .user_agent(concat!("sserver-status/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_FEED_BYTES as u64)
+    {
+        anyhow::bail!("feed is larger than {} bytes", MAX_FEED_BYTES);
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_FEED_BYTES {
+            anyhow::bail!("feed is larger than {} bytes", MAX_FEED_BYTES);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8(body)?)
+}
+
+/// Make the servers whose `source` is `sub` match the parsed feed
+async fn apply(
+    state: &SharedState,
+    sub: &SubscriptionConfig,
+    parsed: Vec<Result<SharedServer, String>>,
+    entry: &mut SyncLogEntry,
+) {
+    let source = Some(sub.name.as_str());
+    let key = |host: &str, port: u16| (host.to_lowercase(), port);
+    let mut changes = Vec::new();
+    let mut added = HashSet::new();
+    {
+        let mut servers = state.servers.write().await;
+        let mut ours: HashMap<(String, u16), Uuid> = HashMap::new();
+        let mut taken: HashSet<(String, u16)> = HashSet::new();
+        for s in servers.values() {
+            if s.source.as_deref() == source {
+                ours.insert(key(&s.host, s.port), s.id);
+            } else {
+                taken.insert(key(&s.host, s.port));
+            }
+        }
+
+        // Pair each usable feed entry with the server it updates, if any
+        let mut matched: HashSet<Uuid> = HashSet::new();
+        let mut claimed: HashSet<(String, u16)> = HashSet::new();
+        let mut feed: Vec<(Option<Uuid>, SharedServer)> = Vec::new();
+        for item in parsed {
+            let shared = match item {
+                Ok(shared) => shared,
+                Err(_) => {
+                    entry.invalid += 1;
+                    continue;
+                }
+            };
+            let addr = key(&shared.host, shared.port);
+            // A SIP008 id follows a server across address changes
+            let existing = shared
+                .id
+                .filter(|id| {
+                    servers
+                        .get(id)
+                        .is_some_and(|s| s.source.as_deref() == source)
+                })
+                .or_else(|| ours.get(&addr).copied());
+            // Held to the same checks as a server added by hand
+            let candidate = match existing.and_then(|id| servers.get(&id)) {
+                Some(server) => updated(server, shared.clone()),
+                None => to_server(shared.clone(), Uuid::nil(), sub),
+            };
+            if !config::validate_server(&candidate).is_empty() {
+                entry.invalid += 1;
+                continue;
+            }
+            // A new or moved address must not belong to another server,
+            // and no two entries may end up on the same one
+            let moved = existing
+                .and_then(|id| servers.get(&id))
+                .is_none_or(|s| key(&s.host, s.port) != addr);
+            let collides = moved && (taken.contains(&addr) || ours.contains_key(&addr));
+            if collides || existing.is_some_and(|id| matched.contains(&id)) || !claimed.insert(addr)
+            {
+                entry.duplicates += 1;
+                continue;
+            }
+            matched.extend(existing);
+            feed.push((existing, shared));
+        }
+        if feed.is_empty() {
+            entry.error = Some("feed holds no usable servers".into());
+            return;
+        }
+
+        for (id, shared) in feed {
+            match id.and_then(|id| servers.get(&id)) {
+                Some(server) => {
+                    let next = updated(server, shared);
+                    if next == *server {
+                        continue;
+                    }
+                    entry.updated += 1;
+                    changes.push(next);
+                }
+                None => {
+                    let id = match shared.id {
+                        Some(id) if !servers.contains_key(&id) && !added.contains(&id) => id,
+                        _ => Uuid::new_v4(),
+                    };
+                    matched.insert(id);
+                    added.insert(id);
+                    entry.added += 1;
+                    changes.push(to_server(shared, id, sub));
+                }
+            }
+        }
+
+        for server in servers.values() {
+            if server.source.as_deref() == source && server.enabled && !matched.contains(&server.id)
+            {
+                entry.disabled += 1;
+                changes.push(Server {
+                    enabled: false,
+                    disabled_by_source: true,
+                    ..server.clone()
+                });
+            }
+        }
+
+        if changes.is_empty() {
+            return;
+        }
+        if let Err(e) = config::save_servers(state, &mut servers, &changes, &[]).await {
+            entry.added = 0;
+            entry.updated = 0;
+            entry.disabled = 0;
+            entry.error = Some(format!("failed to save servers: {}", e));
+            return;
+        }
+    }
+
+    for server in &changes {
+        // A sync never changes visibility
+        let _ = state.sse_tx.send(SseEvent::ServerUpdated {
+            server: Box::new(server.clone()),
+            was_listed: !added.contains(&server.id) && server.visibility.is_listed(),
+        });
+    }
+    broadcast_groups(state).await;
+}
+
+/// `server` with the address and credentials from the feed
+fn updated(server: &Server, shared: SharedServer) -> Server {
+    Server {
+        host: shared.host,
+        port: shared.port,
+        password: shared.password,
+        method: shared.method,
+        plugin: shared.plugin,
+        plugin_opts: shared.plugin_opts,
+        identity_keys: shared.identity_keys,
+        // Only undo a disable the subscription did itself
+        enabled: server.enabled || server.disabled_by_source,
+        disabled_by_source: false,
+        ..server.clone()
+    }
+}
+
+fn to_server(shared: SharedServer, id: Uuid, sub: &SubscriptionConfig) -> Server {
+    let name = shared
+        .name
+        .filter(|n| !n.trim().is_empty())
+        .unwrap_or_else(|| format!("{}:{}", shared.host, shared.port));
+    Server {
+        id,
+        name,
+        host: shared.host,
+        port: shared.port,
+        password: shared.password,
+        method: shared.method,
+        plugin: shared.plugin,
+        plugin_opts: shared.plugin_opts,
+        identity_keys: shared.identity_keys,
+        users: Vec::new(),
+        enabled: true,
+        tags: vec![sub.name.clone()],
+        visibility: Visibility::default(),
+        display_name: None,
+        group: sub.group.clone(),
+        retention_days: None,
+        checks: CheckOverrides::default(),
+        source: Some(sub.name.clone()),
+        disabled_by_source: false,
+    }
+}